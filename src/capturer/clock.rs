@@ -0,0 +1,198 @@
+use std::time::{Duration, Instant, SystemTime};
+
+// How fast the mapping of a source clock may move forward, and the wall clock
+// correction may change, as a fraction of the elapsed time. Covers the drift
+// of real clocks, while jitter in the arrival of frames averages out.
+const MAX_DRIFT: f64 = 0.0005;
+// Wall clock changes beyond this are treated as the clock being set, and
+// applied at once
+const WALL_CLOCK_STEP: f64 = 1.0;
+
+/// The clocks of the video and the audio of a capture, which line up
+pub(crate) struct CaptureClock {
+    pub video: SourceClock,
+    pub audio: SourceClock,
+}
+
+impl CaptureClock {
+    /// Starts the clocks at the current time
+    pub fn new() -> Self {
+        let (start, start_wall) = (Instant::now(), SystemTime::now());
+        CaptureClock {
+            video: SourceClock::new(start, start_wall),
+            audio: SourceClock::new(start, start_wall),
+        }
+    }
+
+    /// Time since the capture started
+    pub fn elapsed(&self) -> Duration {
+        self.video.start.elapsed()
+    }
+}
+
+/// Maps the timestamps of one source, like the video or the audio of a
+/// backend, onto the time since the capture started and the wall clock.
+///
+/// Sources have their own clocks. The offset to ours is estimated from the
+/// earliest arrival of their frames relative to their timestamps, so delays
+/// in delivering a frame do not show up in its timestamp.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceClock {
+    start: Instant,
+    start_wall: SystemTime,
+    // Time since start minus source time, in seconds
+    offset: Option<f64>,
+    // How far the wall clock moved away from the monotonic clock since start,
+    // in seconds
+    wall_offset: f64,
+    last_arrival: Option<Instant>,
+    last: Duration,
+}
+
+impl SourceClock {
+    pub fn new(start: Instant, start_wall: SystemTime) -> Self {
+        SourceClock {
+            start,
+            start_wall,
+            offset: None,
+            wall_offset: 0.0,
+            last_arrival: None,
+            last: Duration::ZERO,
+        }
+    }
+
+    /// Returns the time since the capture started and the wall clock time of
+    /// a frame with the `source` timestamp that arrived at `arrival`. Frames
+    /// without a timestamp are placed at their arrival.
+    pub fn map(&mut self, source: Option<Duration>, arrival: Instant) -> (Duration, SystemTime) {
+        self.map_at(source, arrival, Instant::now(), SystemTime::now())
+    }
+
+    fn map_at(
+        &mut self,
+        source: Option<Duration>,
+        arrival: Instant,
+        now: Instant,
+        now_wall: SystemTime,
+    ) -> (Duration, SystemTime) {
+        let elapsed = arrival.saturating_duration_since(self.start).as_secs_f64();
+        let drift = match self.last_arrival {
+            Some(last) => arrival.saturating_duration_since(last).as_secs_f64() * MAX_DRIFT,
+            None => 0.0,
+        };
+        self.last_arrival = Some(arrival);
+
+        let time = match source {
+            Some(source) => {
+                let source = source.as_secs_f64();
+                let offset = match self.offset {
+                    Some(offset) => (offset + drift).min(elapsed - source),
+                    None => elapsed - source,
+                };
+                self.offset = Some(offset);
+                source + offset
+            }
+            None => elapsed,
+        };
+        // Timestamps never go backwards, even when the offset shrinks
+        let time = Duration::from_secs_f64(time.max(0.0)).max(self.last);
+        self.last = time;
+
+        let monotonic = now.saturating_duration_since(self.start).as_secs_f64();
+        let wall = match now_wall.duration_since(self.start_wall) {
+            Ok(wall) => wall.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+        let wall_error = wall - monotonic - self.wall_offset;
+        self.wall_offset += match wall_error.abs() > WALL_CLOCK_STEP {
+            true => wall_error,
+            false => wall_error.clamp(-drift, drift),
+        };
+
+        let display_time = match self.wall_offset >= 0.0 {
+            true => self.start_wall + time + Duration::from_secs_f64(self.wall_offset),
+            false => self.start_wall + time - Duration::from_secs_f64(-self.wall_offset),
+        };
+        (time, display_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: Duration = Duration::from_nanos(16_666_667);
+
+    #[test]
+    fn test_jitter() {
+        let start = Instant::now();
+        let start_wall = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let mut clock = SourceClock::new(start, start_wall);
+
+        // Frames are captured 5 ms after start and arrive 2 to 9 ms later
+        let source_start = Duration::from_secs(5_000);
+        let mut last = Duration::ZERO;
+        for frame in 0..600u32 {
+            let captured = Duration::from_millis(5) + FRAME * frame;
+            let delay = Duration::from_millis(2 + (frame as u64 * 7919) % 8);
+            let arrival = start + captured + delay;
+            let (time, display_time) = clock.map_at(
+                Some(source_start + FRAME * frame),
+                arrival,
+                arrival,
+                start_wall + captured + delay,
+            );
+
+            assert!(time >= last);
+            last = time;
+            if frame >= 60 {
+                let error = time.as_secs_f64() - captured.as_secs_f64();
+                assert!((0.0..0.0025).contains(&error), "{error}");
+                assert_eq!(display_time, start_wall + time);
+            }
+        }
+    }
+
+    #[test]
+    fn test_drift() {
+        let start = Instant::now();
+        let start_wall = SystemTime::UNIX_EPOCH;
+        let mut clock = SourceClock::new(start, start_wall);
+
+        // The source clock runs 200 ppm slow, which adds up to 72 ms over the
+        // 6 minutes
+        for frame in 0..21_600u32 {
+            let captured = FRAME * frame;
+            let arrival = start + captured + Duration::from_millis(3);
+            let (time, _) = clock.map_at(
+                Some(captured.mul_f64(1.0 - 0.0002)),
+                arrival,
+                arrival,
+                start_wall + captured,
+            );
+            let error = time.as_secs_f64() - captured.as_secs_f64();
+            assert!(error.abs() < 0.0035, "{error}");
+        }
+    }
+
+    #[test]
+    fn test_wall_clock() {
+        let start = Instant::now();
+        let start_wall = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let mut clock = SourceClock::new(start, start_wall);
+
+        // Without timestamps frames are placed at their arrival
+        let arrival = start + Duration::from_secs(1);
+        let (time, display_time) =
+            clock.map_at(None, arrival, arrival, start_wall + Duration::from_secs(1));
+        assert_eq!(time, Duration::from_secs(1));
+        assert_eq!(display_time, start_wall + time);
+
+        // The wall clock being set moves the display time, not the timestamp
+        let arrival = start + Duration::from_secs(2);
+        let (time, display_time) =
+            clock.map_at(None, arrival, arrival, start_wall + Duration::from_secs(62));
+        assert_eq!(time, Duration::from_secs(2));
+        assert_eq!(display_time, start_wall + Duration::from_secs(62));
+    }
+}
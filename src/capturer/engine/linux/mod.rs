@@ -1,8 +1,9 @@
 use std::{
     mem::size_of,
     sync::{
-        atomic::{AtomicBool, AtomicU8},
+        atomic::{AtomicBool, AtomicU8, AtomicUsize},
         mpsc::{self, sync_channel, SyncSender},
+        Arc, Mutex,
     },
     thread::JoinHandle,
     time::Duration,
@@ -17,12 +18,19 @@ use pw::{
         self,
         param::{
             format::{FormatProperties, MediaSubtype, MediaType},
-            video::VideoFormat,
+            video::{VideoFormat, VideoInfoRaw},
             ParamType,
         },
         pod::{Pod, Property},
         sys::{
-            spa_buffer, spa_meta_header, SPA_META_Header, SPA_PARAM_META_size, SPA_PARAM_META_type,
+            spa_buffer, spa_meta, spa_meta_header, spa_meta_region, spa_meta_videotransform,
+            SPA_META_Header, SPA_META_TRANSFORMATION_Flipped, SPA_META_TRANSFORMATION_Flipped180,
+            SPA_META_TRANSFORMATION_Flipped270, SPA_META_TRANSFORMATION_Flipped90,
+            SPA_META_VideoCrop, SPA_META_VideoDamage, SPA_META_VideoTransform, SPA_PARAM_META_size,
+            SPA_PARAM_META_type, SPA_META_TRANSFORMATION_180, SPA_META_TRANSFORMATION_270,
+            SPA_META_TRANSFORMATION_90, SPA_VIDEO_COLOR_PRIMARIES_BT2020,
+            SPA_VIDEO_COLOR_PRIMARIES_SMPTEEG432, SPA_VIDEO_TRANSFER_ARIB_STD_B67,
+            SPA_VIDEO_TRANSFER_SMPTE2084,
         },
         utils::{Direction, SpaTypes},
     },
@@ -30,22 +38,153 @@ use pw::{
 };
 
 use crate::{
-    capturer::Options,
-    frame::{BGRxFrame, Frame, RGBFrame, RGBxFrame, XBGRFrame},
+    capturer::{
+        queue, stats::StatsRecorder, Area, CaptureEvent, CoordinateSpace, FrameRate, Options,
+    },
+    frame::{
+        rgb10_to_rgba16f, undo_video_transform_into, BufferPool, ColorPrimaries, ColorRange,
+        Colorimetry, DirtyRect, FrameLease, FrameType, PixelFormat, TransferFunction,
+        VideoTransform,
+    },
 };
 
 use self::{error::LinCapError, portal::ScreenCastPortal};
 
 mod error;
 mod portal;
+mod raw;
+#[cfg(feature = "screenshot-portal")]
+mod screenshot;
+
+#[cfg(feature = "screenshot-portal")]
+pub use screenshot::screenshot;
 
 static CAPTURER_STATE: AtomicU8 = AtomicU8::new(0);
 static STREAM_STATE_CHANGED_TO_ERROR: AtomicBool = AtomicBool::new(false);
 
+// Formats we offer to the compositor. Which one is picked depends on what it
+// supports.
+pub(crate) const NATIVE_FRAME_TYPES: &[FrameType] = &[
+    FrameType::BGRx,
+    FrameType::RGBx,
+    FrameType::XBGR,
+    FrameType::BGRAFrame,
+    FrameType::RGBA,
+    FrameType::RGB,
+    FrameType::RGBA16F,
+];
+
+// The PipeWire format of every 8 bit entry in NATIVE_FRAME_TYPES
+const NATIVE_VIDEO_FORMATS: [VideoFormat; 6] = [
+    VideoFormat::BGRx,
+    VideoFormat::RGBx,
+    VideoFormat::xBGR,
+    VideoFormat::BGRA,
+    VideoFormat::RGBA,
+    VideoFormat::RGB,
+];
+
+// 10 bit formats, only offered when 10 bit output is requested, with the
+// first bit of red, green and blue in their 32 bit words. They are delivered
+// as RGBA16F frames.
+const DEEP_VIDEO_FORMATS: [(VideoFormat, [u32; 3]); 4] = [
+    (VideoFormat::xRGB_210LE, [20, 10, 0]),
+    (VideoFormat::xBGR_210LE, [0, 10, 20]),
+    (VideoFormat::RGBx_102LE, [22, 12, 2]),
+    (VideoFormat::BGRx_102LE, [2, 12, 22]),
+];
+
+// Maximum number of damage regions we ask the compositor to report per buffer
+const MAX_DAMAGE_REGIONS: usize = 16;
+
 #[derive(Clone)]
 struct ListenerUserData {
-    pub tx: mpsc::Sender<Frame>,
+    pub tx: queue::Sender<FrameLease<'static>>,
+    pub stats: Arc<StatsRecorder>,
+    pub events: mpsc::Sender<CaptureEvent>,
+    // Buffers for frames that have to be copied
+    pub pool: BufferPool,
+    // Where leases send their PipeWire buffer back to
+    pub returned: mpsc::Sender<LeasedBuffer>,
+    // Number of buffers held by leases, which the stream has to outlive
+    pub leased: Arc<AtomicUsize>,
     pub format: spa::param::video::VideoInfoRaw,
+    pub crop_area: Option<Area>,
+    pub crop_coordinates: CoordinateSpace,
+    // Size of the stream in logical coordinates, as reported by the portal
+    pub logical_size: Option<(i32, i32)>,
+    // Number of the next frame, for compositors that don't number them
+    pub next_sequence: u64,
+}
+
+/// A rectangle of pixels inside a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Region {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Region {
+    fn full(width: u32, height: u32) -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    /// Clamps the region to a frame of `width`x`height` pixels, returning
+    /// `None` if nothing of it is left
+    fn clamp(self, width: u32, height: u32) -> Option<Self> {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        let region = Self {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        };
+
+        (region.width > 0 && region.height > 0).then_some(region)
+    }
+
+    /// Intersects `rect` with the region and makes it relative to the
+    /// region's origin
+    fn clip(self, rect: DirtyRect) -> Option<DirtyRect> {
+        rect.clip(
+            self.x as i32,
+            self.y as i32,
+            self.width as i32,
+            self.height as i32,
+        )
+    }
+}
+
+// Converts the user supplied crop area into pixels of a `width`x`height` image
+fn get_crop_region(
+    area: &Area,
+    coordinates: CoordinateSpace,
+    logical_size: Option<(i32, i32)>,
+    width: u32,
+    height: u32,
+) -> Option<Region> {
+    let scale_factor = match (coordinates, logical_size) {
+        (CoordinateSpace::Logical, Some((logical_width, _))) if logical_width > 0 => {
+            width as f64 / logical_width as f64
+        }
+        _ => 1.0,
+    };
+
+    Region {
+        x: (area.origin.x * scale_factor).round() as u32,
+        y: (area.origin.y * scale_factor).round() as u32,
+        width: (area.size.width * scale_factor).round() as u32,
+        height: (area.size.height * scale_factor).round() as u32,
+    }
+    .clamp(width, height)
 }
 
 fn param_changed_callback(
@@ -69,113 +208,419 @@ fn param_changed_callback(
         return;
     }
 
-    user_data
-        .format
-        .parse(param)
-        // TODO: Tell library user of the error
-        .expect("Failed to parse format parameter");
+    // Also called when the compositor renegotiates, for example because the
+    // captured window was resized. Frames take their size from the format.
+    if let Err(e) = user_data.format.parse(param) {
+        eprintln!("pipewire: Failed to parse format ({e})");
+        STREAM_STATE_CHANGED_TO_ERROR.store(true, std::sync::atomic::Ordering::Relaxed);
+        let _ = user_data.events.send(CaptureEvent::StreamError(format!(
+            "Failed to parse format: {e}"
+        )));
+        return;
+    }
+
+    // Screen casts usually have a variable rate of 0/1, bounded by the
+    // maximum
+    let max_framerate = user_data.format.max_framerate();
+    let framerate = match max_framerate.num {
+        0 => user_data.format.framerate(),
+        _ => max_framerate,
+    };
+    if framerate.num > 0 && framerate.denom > 0 {
+        user_data
+            .stats
+            .record_frame_rate(FrameRate::new(framerate.num, framerate.denom));
+    }
 }
 
 fn state_changed_callback(
     _stream: &StreamRef,
-    _user_data: &mut ListenerUserData,
-    _old: StreamState,
+    user_data: &mut ListenerUserData,
+    old: StreamState,
     new: StreamState,
 ) {
-    match new {
+    let event = match new {
+        StreamState::Streaming => CaptureEvent::Started,
+        // Streams also pass through this state while connecting
+        StreamState::Paused if old == StreamState::Streaming => CaptureEvent::Paused,
         StreamState::Error(e) => {
             eprintln!("pipewire: State changed to error({e})");
             STREAM_STATE_CHANGED_TO_ERROR.store(true, std::sync::atomic::Ordering::Relaxed);
+            CaptureEvent::StreamError(e)
         }
-        _ => {}
-    }
+        _ => return,
+    };
+    let _ = user_data.events.send(event);
 }
 
-unsafe fn get_timestamp(buffer: *mut spa_buffer) -> i64 {
+unsafe fn find_meta(buffer: *mut spa_buffer, meta_type: u32) -> Option<*mut spa_meta> {
     let n_metas = (*buffer).n_metas;
-    if n_metas > 0 {
-        let mut meta_ptr = (*buffer).metas;
-        let metas_end = (*buffer).metas.wrapping_add(n_metas as usize);
-        while meta_ptr != metas_end {
-            if (*meta_ptr).type_ == SPA_META_Header {
-                let meta_header: &mut spa_meta_header =
-                    &mut *((*meta_ptr).data as *mut spa_meta_header);
-                return meta_header.pts;
-            }
-            meta_ptr = meta_ptr.wrapping_add(1);
+    let mut meta_ptr = (*buffer).metas;
+    let metas_end = (*buffer).metas.wrapping_add(n_metas as usize);
+    while meta_ptr != metas_end {
+        if (*meta_ptr).type_ == meta_type {
+            return Some(meta_ptr);
+        }
+        meta_ptr = meta_ptr.wrapping_add(1);
+    }
+    None
+}
+
+// Time the frame was captured at on CLOCK_MONOTONIC, zero when unknown
+unsafe fn get_timestamp(buffer: *mut spa_buffer) -> Duration {
+    match find_meta(buffer, SPA_META_Header) {
+        Some(meta) => {
+            let meta_header: &mut spa_meta_header = &mut *((*meta).data as *mut spa_meta_header);
+            Duration::from_nanos(meta_header.pts.max(0) as u64)
         }
-        0
-    } else {
-        0
+        None => Duration::ZERO,
     }
 }
 
+// Number of the frame as counted by the compositor
+unsafe fn get_sequence(buffer: *mut spa_buffer) -> Option<u64> {
+    let meta = find_meta(buffer, SPA_META_Header)?;
+    let meta_header: &spa_meta_header = &*((*meta).data as *const spa_meta_header);
+    Some(meta_header.seq)
+}
+
+unsafe fn get_dirty_rects(buffer: *mut spa_buffer) -> Option<Vec<DirtyRect>> {
+    let meta = find_meta(buffer, SPA_META_VideoDamage)?;
+    let n_regions = (*meta).size as usize / size_of::<spa_meta_region>();
+    let regions = std::slice::from_raw_parts((*meta).data as *const spa_meta_region, n_regions);
+
+    // The region list is terminated by the first region with an empty size
+    Some(
+        regions
+            .iter()
+            .take_while(|r| r.region.size.width != 0 && r.region.size.height != 0)
+            .map(|r| DirtyRect {
+                x: r.region.position.x,
+                y: r.region.position.y,
+                width: r.region.size.width as i32,
+                height: r.region.size.height as i32,
+            })
+            .collect(),
+    )
+}
+
+// Returns the region of the buffer holding the image, if the compositor only
+// filled part of it (e.g. when capturing a single window)
+unsafe fn get_video_crop(buffer: *mut spa_buffer) -> Option<Region> {
+    let meta = find_meta(buffer, SPA_META_VideoCrop)?;
+    let crop = &*((*meta).data as *const spa_meta_region);
+    if crop.region.size.width == 0 || crop.region.size.height == 0 {
+        return None;
+    }
+
+    Some(Region {
+        x: crop.region.position.x.max(0) as u32,
+        y: crop.region.position.y.max(0) as u32,
+        width: crop.region.size.width,
+        height: crop.region.size.height,
+    })
+}
+
+unsafe fn get_video_transform(buffer: *mut spa_buffer) -> VideoTransform {
+    let Some(meta) = find_meta(buffer, SPA_META_VideoTransform) else {
+        return VideoTransform::None;
+    };
+    let transform = &*((*meta).data as *const spa_meta_videotransform);
+
+    match transform.transform {
+        SPA_META_TRANSFORMATION_90 => VideoTransform::Rotate90,
+        SPA_META_TRANSFORMATION_180 => VideoTransform::Rotate180,
+        SPA_META_TRANSFORMATION_270 => VideoTransform::Rotate270,
+        SPA_META_TRANSFORMATION_Flipped => VideoTransform::Flipped,
+        SPA_META_TRANSFORMATION_Flipped90 => VideoTransform::Flipped90,
+        SPA_META_TRANSFORMATION_Flipped180 => VideoTransform::Flipped180,
+        SPA_META_TRANSFORMATION_Flipped270 => VideoTransform::Flipped270,
+        _ => VideoTransform::None,
+    }
+}
+
+// Colorimetry of RGB frames in `format`. Anything not described is taken as
+// sRGB.
+fn get_colorimetry(format: &VideoInfoRaw) -> Colorimetry {
+    Colorimetry {
+        range: ColorRange::Full,
+        primaries: match format.color_primaries() {
+            SPA_VIDEO_COLOR_PRIMARIES_BT2020 => ColorPrimaries::Bt2020,
+            SPA_VIDEO_COLOR_PRIMARIES_SMPTEEG432 => ColorPrimaries::DisplayP3,
+            _ => ColorPrimaries::Bt709,
+        },
+        transfer: match format.transfer_function() {
+            SPA_VIDEO_TRANSFER_SMPTE2084 => TransferFunction::Pq,
+            SPA_VIDEO_TRANSFER_ARIB_STD_B67 => TransferFunction::Hlg,
+            _ => TransferFunction::Srgb,
+        },
+        ..Default::default()
+    }
+}
+
+// A PipeWire buffer held by a FrameLease, sent back to the capture thread
+// to be queued again when the lease is dropped
+struct LeasedBuffer(*mut pw::sys::pw_buffer);
+
+// The buffer is only queued again on the thread owning the stream
+unsafe impl Send for LeasedBuffer {}
+
 fn process_callback(stream: &StreamRef, user_data: &mut ListenerUserData) {
     let buffer = unsafe { stream.dequeue_raw_buffer() };
-    if !buffer.is_null() {
-        'outside: {
-            let buffer = unsafe { (*buffer).buffer };
-            if buffer.is_null() {
-                break 'outside;
-            }
-            let timestamp = unsafe { get_timestamp(buffer) };
+    if buffer.is_null() {
+        user_data.stats.record_out_of_buffers();
+        return;
+    }
 
-            let n_datas = unsafe { (*buffer).n_datas };
-            if n_datas < 1 {
-                return;
-            }
-            let frame_size = user_data.format.size();
-            let frame_data: Vec<u8> = unsafe {
-                std::slice::from_raw_parts(
-                    (*(*buffer).datas).data as *mut u8,
-                    (*(*buffer).datas).maxsize as usize,
+    // Frames borrowing the buffer queue it again once they are dropped
+    let lease = unsafe { lease_frame(buffer, user_data) };
+    let leased = lease.as_ref().is_some_and(FrameLease::is_borrowed);
+    if leased {
+        user_data
+            .leased
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+    if let Some(lease) = lease {
+        if let Err(e) = user_data.tx.send(lease) {
+            eprintln!("{e}");
+        }
+    }
+
+    if !leased {
+        unsafe { stream.queue_raw_buffer(buffer) };
+    }
+}
+
+// Returns `None` for buffers that are skipped, counting the ones that can't be
+// read as corrupt
+unsafe fn lease_frame(
+    pw_buffer: *mut pw::sys::pw_buffer,
+    user_data: &mut ListenerUserData,
+) -> Option<FrameLease<'static>> {
+    let buffer = (*pw_buffer).buffer;
+    if buffer.is_null() || (*buffer).n_datas < 1 {
+        user_data.stats.record_corrupt();
+        return None;
+    }
+    let timestamp = get_timestamp(buffer);
+    // Frames the compositor skipped show up as gaps in its numbers
+    let sequence = get_sequence(buffer).unwrap_or(user_data.next_sequence);
+    user_data.next_sequence = sequence.wrapping_add(1);
+    let dirty_rects = get_dirty_rects(buffer);
+    let crop = get_video_crop(buffer);
+    let transform = get_video_transform(buffer);
+
+    let frame_size = user_data.format.size();
+    if frame_size.width == 0 || frame_size.height == 0 {
+        user_data.stats.record_corrupt();
+        return None;
+    }
+    let video_format = user_data.format.format();
+    let deep_format = DEEP_VIDEO_FORMATS
+        .iter()
+        .find(|(format, _)| *format == video_format);
+    let format = match video_format {
+        VideoFormat::RGBx => PixelFormat::RGBx,
+        VideoFormat::RGBA => PixelFormat::RGBA,
+        VideoFormat::BGRA => PixelFormat::BGRA,
+        VideoFormat::RGB => PixelFormat::RGB,
+        VideoFormat::xBGR => PixelFormat::XBGR,
+        VideoFormat::BGRx => PixelFormat::BGRx,
+        _ if deep_format.is_some() => PixelFormat::RGBA16F,
+        // Only happens if the compositor ignores the formats we offered
+        _ => {
+            user_data.stats.record_corrupt();
+            let _ = user_data.events.send(CaptureEvent::StreamError(format!(
+                "Unsupported frame format received: {video_format:?}"
+            )));
+            return None;
+        }
+    };
+    let bytes_per_pixel = match video_format {
+        VideoFormat::RGB => 3,
+        _ => 4,
+    };
+    let stride = match (*(*(*buffer).datas).chunk).stride {
+        stride if stride > 0 => stride as usize,
+        _ => frame_size.width as usize * bytes_per_pixel,
+    };
+    let buffer_data: &[u8] = std::slice::from_raw_parts(
+        (*(*buffer).datas).data as *const u8,
+        (*(*buffer).datas).maxsize as usize,
+    );
+
+    // Clamp the crop region to the negotiated size in case the compositor
+    // sends a stale one after a resize
+    let buffer_region = crop
+        .and_then(|crop| crop.clamp(frame_size.width, frame_size.height))
+        .unwrap_or(Region::full(frame_size.width, frame_size.height));
+    let (upright_width, upright_height) =
+        transform.output_size(buffer_region.width as usize, buffer_region.height as usize);
+
+    // Frames are dropped while the crop area lies outside of the stream
+    let output_region = match &user_data.crop_area {
+        Some(area) => get_crop_region(
+            area,
+            user_data.crop_coordinates,
+            user_data.logical_size,
+            upright_width as u32,
+            upright_height as u32,
+        )?,
+        None => Region::full(upright_width as u32, upright_height as u32),
+    };
+    let (width, height) = (output_region.width, output_region.height);
+    let buffer_offset =
+        buffer_region.y as usize * stride + buffer_region.x as usize * bytes_per_pixel;
+    let output_offset =
+        output_region.y as usize * stride + output_region.x as usize * bytes_per_pixel;
+
+    let dirty_rects = dirty_rects.map(|rects| {
+        rects
+            .into_iter()
+            .filter_map(|rect| buffer_region.clip(rect))
+            .map(|rect| {
+                transform.transform_rect(
+                    rect,
+                    buffer_region.width as usize,
+                    buffer_region.height as usize,
                 )
-                .to_vec()
-            };
-
-            if let Err(e) = match user_data.format.format() {
-                VideoFormat::RGBx => user_data.tx.send(Frame::RGBx(RGBxFrame {
-                    display_time: timestamp as u64,
-                    width: frame_size.width as i32,
-                    height: frame_size.height as i32,
-                    data: frame_data,
-                })),
-                VideoFormat::RGB => user_data.tx.send(Frame::RGB(RGBFrame {
-                    display_time: timestamp as u64,
-                    width: frame_size.width as i32,
-                    height: frame_size.height as i32,
-                    data: frame_data,
-                })),
-                VideoFormat::xBGR => user_data.tx.send(Frame::XBGR(XBGRFrame {
-                    display_time: timestamp as u64,
-                    width: frame_size.width as i32,
-                    height: frame_size.height as i32,
-                    data: frame_data,
-                })),
-                VideoFormat::BGRx => user_data.tx.send(Frame::BGRx(BGRxFrame {
-                    display_time: timestamp as u64,
-                    width: frame_size.width as i32,
-                    height: frame_size.height as i32,
-                    data: frame_data,
-                })),
-                _ => panic!("Unsupported frame format received"),
-            } {
-                eprintln!("{e}");
-            }
+            })
+            .filter_map(|rect| output_region.clip(rect))
+            .collect()
+    });
+
+    // Rotated buffers have to be made upright in a copy
+    let upright = (transform != VideoTransform::None).then(|| {
+        let upright_stride = upright_width * bytes_per_pixel;
+        let mut upright = user_data.pool.take(upright_stride * upright_height);
+        upright.resize(upright_stride * upright_height, 0);
+        undo_video_transform_into(
+            &buffer_data[buffer_offset..],
+            stride,
+            bytes_per_pixel,
+            buffer_region.width as usize,
+            buffer_region.height as usize,
+            transform,
+            &mut upright,
+        );
+        (upright, upright_stride)
+    });
+    let (data, data_stride, offset) = match &upright {
+        Some((upright, upright_stride)) => (
+            &upright[..],
+            *upright_stride,
+            output_region.y as usize * upright_stride + output_region.x as usize * bytes_per_pixel,
+        ),
+        None => (buffer_data, stride, buffer_offset + output_offset),
+    };
+    let output_size = data_stride * (height as usize - 1) + width as usize * bytes_per_pixel;
+    if offset + output_size > data.len() {
+        user_data.stats.record_corrupt();
+        return None;
+    }
+
+    // 10 bit pixels are unpacked, leaving the PipeWire buffer free
+    let unpacked = deep_format.map(|(_, shifts)| {
+        let mut unpacked = user_data.pool.take(width as usize * height as usize * 8);
+        rgb10_to_rgba16f(
+            &data[offset..],
+            data_stride,
+            (width as usize, height as usize),
+            *shifts,
+            &mut unpacked,
+        );
+        unpacked
+    });
+    // Ends the borrow of `upright`, which is moved into the lease below
+    let data = data[offset..].as_ptr();
+
+    let lease = match (unpacked, upright) {
+        (Some(unpacked), _) => FrameLease::pooled(
+            unpacked,
+            0,
+            width as usize * 8,
+            width,
+            height,
+            format,
+            timestamp,
+        )
+        .with_colorimetry(get_colorimetry(&user_data.format)),
+        (None, Some((upright, upright_stride))) => FrameLease::pooled(
+            upright,
+            offset,
+            upright_stride,
+            width,
+            height,
+            format,
+            timestamp,
+        ),
+        // Everything else is leased where it is, crops only move the start
+        // of the frame
+        (None, None) => {
+            let returned = user_data.returned.clone();
+            let pw_buffer = LeasedBuffer(pw_buffer);
+            FrameLease::borrowed(data, stride, width, height, format, timestamp, move || {
+                let _ = returned.send(pw_buffer);
+            })
         }
-    } else {
-        eprintln!("Out of buffers");
+    };
+
+    Some(lease.with_sequence(sequence).with_dirty_rects(dirty_rects))
+}
+
+// The format closest to `frame_type`, so as little as possible has to be
+// converted in software
+fn preferred_video_format(frame_type: FrameType) -> VideoFormat {
+    if wants_deep_formats(frame_type) {
+        return DEEP_VIDEO_FORMATS[0].0;
     }
+    let frame_type = match frame_type.pixel_format() {
+        PixelFormat::BGR | PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::P010 => {
+            FrameType::BGRx
+        }
+        _ => frame_type,
+    };
+    NATIVE_FRAME_TYPES
+        .iter()
+        .position(|native| *native == frame_type)
+        .map_or(VideoFormat::BGRx, |index| NATIVE_VIDEO_FORMATS[index])
+}
 
-    unsafe { stream.queue_raw_buffer(buffer) };
+fn wants_deep_formats(frame_type: FrameType) -> bool {
+    matches!(
+        frame_type.pixel_format(),
+        PixelFormat::P010 | PixelFormat::RGBA16F
+    )
+}
+
+fn serialize_meta_param(meta_type: u32, size: usize) -> Result<Vec<u8>, LinCapError> {
+    let obj = pw::spa::pod::object!(
+        SpaTypes::ObjectParamMeta,
+        ParamType::Meta,
+        Property::new(
+            SPA_PARAM_META_type,
+            pw::spa::pod::Value::Id(pw::spa::utils::Id(meta_type))
+        ),
+        Property::new(SPA_PARAM_META_size, pw::spa::pod::Value::Int(size as i32)),
+    );
+
+    Ok(pw::spa::pod::serialize::PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &pw::spa::pod::Value::Object(obj),
+    )?
+    .0
+    .into_inner())
 }
 
 // TODO: Format negotiation
 fn pipewire_capturer(
     options: Options,
-    tx: mpsc::Sender<Frame>,
+    tx: queue::Sender<FrameLease<'static>>,
+    stats: Arc<StatsRecorder>,
+    events: mpsc::Sender<CaptureEvent>,
+    pool: BufferPool,
     ready_sender: &SyncSender<bool>,
-    stream_id: u32,
+    session: PortalSession,
 ) -> Result<(), LinCapError> {
     pw::init();
 
@@ -183,9 +628,19 @@ fn pipewire_capturer(
     let context = Context::new(&mainloop)?;
     let core = context.connect(None)?;
 
+    let (returned_tx, returned_rx) = mpsc::channel();
     let user_data = ListenerUserData {
         tx,
+        stats,
+        events: events.clone(),
+        pool,
+        returned: returned_tx,
+        leased: Arc::new(AtomicUsize::new(0)),
         format: Default::default(),
+        crop_area: options.crop_area.clone(),
+        crop_coordinates: options.crop_coordinates,
+        logical_size: session.logical_size,
+        next_sequence: 0,
     };
 
     let stream = pw::stream::Stream::new(
@@ -205,20 +660,42 @@ fn pipewire_capturer(
         .process(process_callback)
         .register()?;
 
+    // Rate the compositor may go down to when nothing changes, which can't
+    // be above the highest rate
+    let min_fps = match options.min_fps {
+        Some(min_fps) if min_fps.as_f64() <= options.fps.as_f64() => min_fps,
+        Some(_) => options.fps,
+        None => FrameRate::default(),
+    };
+
     let obj = pw::spa::pod::object!(
         pw::spa::utils::SpaTypes::ObjectParamFormat,
         pw::spa::param::ParamType::EnumFormat,
         pw::spa::pod::property!(FormatProperties::MediaType, Id, MediaType::Video),
         pw::spa::pod::property!(FormatProperties::MediaSubtype, Id, MediaSubtype::Raw),
+        // The default only states a preference, the alternatives list every
+        // format we accept
         pw::spa::pod::property!(
             FormatProperties::VideoFormat,
-            Choice,
-            Enum,
-            Id,
-            pw::spa::param::video::VideoFormat::RGB,
-            pw::spa::param::video::VideoFormat::RGBA,
-            pw::spa::param::video::VideoFormat::RGBx,
-            pw::spa::param::video::VideoFormat::BGRx,
+            pw::spa::pod::Value::Choice(pw::spa::pod::ChoiceValue::Id(pw::spa::utils::Choice(
+                pw::spa::utils::ChoiceFlags::empty(),
+                pw::spa::utils::ChoiceEnum::Enum {
+                    default: pw::spa::utils::Id(
+                        preferred_video_format(options.output_type).as_raw()
+                    ),
+                    alternatives: NATIVE_VIDEO_FORMATS
+                        .iter()
+                        .copied()
+                        .chain(
+                            DEEP_VIDEO_FORMATS
+                                .iter()
+                                .filter(|_| wants_deep_formats(options.output_type))
+                                .map(|(format, _)| *format)
+                        )
+                        .map(|format| pw::spa::utils::Id(format.as_raw()))
+                        .collect(),
+                }
+            )))
         ),
         pw::spa::pod::property!(
             FormatProperties::VideoSize,
@@ -243,48 +720,55 @@ fn pipewire_capturer(
         ),
         pw::spa::pod::property!(
             FormatProperties::VideoMaxFramerate,
+            Choice,
+            Range,
             Fraction,
             pw::spa::utils::Fraction {
-                num: options.fps,
-                denom: 1
+                // Default
+                num: options.fps.num(),
+                denom: options.fps.den(),
+            },
+            pw::spa::utils::Fraction {
+                // Min
+                num: min_fps.num(),
+                denom: min_fps.den(),
+            },
+            pw::spa::utils::Fraction {
+                // Max
+                num: options.fps.num(),
+                denom: options.fps.den(),
             }
         ),
     );
 
-    let metas_obj = pw::spa::pod::object!(
-        SpaTypes::ObjectParamMeta,
-        ParamType::Meta,
-        Property::new(
-            SPA_PARAM_META_type,
-            pw::spa::pod::Value::Id(pw::spa::utils::Id(SPA_META_Header))
-        ),
-        Property::new(
-            SPA_PARAM_META_size,
-            pw::spa::pod::Value::Int(size_of::<pw::spa::sys::spa_meta_header>() as i32)
-        ),
-    );
-
     let values: Vec<u8> = pw::spa::pod::serialize::PodSerializer::serialize(
         std::io::Cursor::new(Vec::new()),
         &pw::spa::pod::Value::Object(obj),
     )?
     .0
     .into_inner();
-    let metas_values: Vec<u8> = pw::spa::pod::serialize::PodSerializer::serialize(
-        std::io::Cursor::new(Vec::new()),
-        &pw::spa::pod::Value::Object(metas_obj),
-    )?
-    .0
-    .into_inner();
+    let header_meta_values = serialize_meta_param(SPA_META_Header, size_of::<spa_meta_header>())?;
+    let damage_meta_values = serialize_meta_param(
+        SPA_META_VideoDamage,
+        size_of::<spa_meta_region>() * MAX_DAMAGE_REGIONS,
+    )?;
+    let crop_meta_values = serialize_meta_param(SPA_META_VideoCrop, size_of::<spa_meta_region>())?;
+    let transform_meta_values = serialize_meta_param(
+        SPA_META_VideoTransform,
+        size_of::<spa_meta_videotransform>(),
+    )?;
 
     let mut params = [
         pw::spa::pod::Pod::from_bytes(&values).unwrap(),
-        pw::spa::pod::Pod::from_bytes(&metas_values).unwrap(),
+        pw::spa::pod::Pod::from_bytes(&header_meta_values).unwrap(),
+        pw::spa::pod::Pod::from_bytes(&damage_meta_values).unwrap(),
+        pw::spa::pod::Pod::from_bytes(&crop_meta_values).unwrap(),
+        pw::spa::pod::Pod::from_bytes(&transform_meta_values).unwrap(),
     ];
 
     stream.connect(
         Direction::Input,
-        Some(stream_id),
+        Some(session.node_id),
         pw::stream::StreamFlags::AUTOCONNECT | pw::stream::StreamFlags::MAP_BUFFERS,
         &mut params,
     )?;
@@ -297,41 +781,106 @@ fn pipewire_capturer(
 
     let pw_loop = mainloop.loop_();
 
-    // User has called Capturer::start() and we start the main loop
+    // User has called Capturer::start() and we start the main loop. If the
+    // stream state got changed to `Error`, we exit.
     while CAPTURER_STATE.load(std::sync::atomic::Ordering::Relaxed) == 1
-        && /* If the stream state got changed to `Error`, we exit. TODO: tell user that we exited */
-          !STREAM_STATE_CHANGED_TO_ERROR.load(std::sync::atomic::Ordering::Relaxed)
+        && !STREAM_STATE_CHANGED_TO_ERROR.load(std::sync::atomic::Ordering::Relaxed)
     {
-        pw_loop.iterate(Duration::from_millis(100));
+        pw_loop.iterate(Duration::from_millis(10));
+
+        // Dispatches the signal of the portal session closing
+        let _ = session.connection.lock().unwrap().process(Duration::ZERO);
+        if session.closed.load(std::sync::atomic::Ordering::Relaxed) {
+            let _ = events.send(CaptureEvent::SessionRevoked);
+            break;
+        }
+
+        // Buffers of dropped leases can be filled again. The short timeout
+        // above makes sure they are picked up when all buffers were leased
+        // and no more frames arrive.
+        while let Ok(LeasedBuffer(buffer)) = returned_rx.try_recv() {
+            user_data
+                .leased
+                .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+            unsafe { stream.queue_raw_buffer(buffer) };
+        }
+    }
+
+    // Leases point into buffers of the stream, so it is only dropped once
+    // they are gone. Queued ones are copied, the ones that were received are
+    // waited for.
+    user_data
+        .tx
+        .update_queued(|lease| lease.detach(&user_data.pool));
+    user_data.tx.end();
+    while user_data.leased.load(std::sync::atomic::Ordering::Relaxed) > 0 {
+        if returned_rx.recv().is_err() {
+            break;
+        }
+        user_data
+            .leased
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
     }
 
     Ok(())
 }
 
+// The stream the portal handed out
+struct PortalSession {
+    node_id: u32,
+    // Size of the stream in logical coordinates
+    logical_size: Option<(i32, i32)>,
+    // Signals of the session are received on this connection
+    connection: Arc<Mutex<dbus::blocking::Connection>>,
+    // Set once the user stopped sharing
+    closed: Arc<AtomicBool>,
+}
+
 pub struct LinuxCapturer {
     capturer_join_handle: Option<JoinHandle<Result<(), LinCapError>>>,
     // The pipewire stream is deleted when the connection is dropped.
     // That's why we keep it alive
-    _connection: dbus::blocking::Connection,
+    _connection: Arc<Mutex<dbus::blocking::Connection>>,
 }
 
 impl LinuxCapturer {
     // TODO: Error handling
-    pub fn new(options: &Options, tx: mpsc::Sender<Frame>) -> Self {
+    pub fn new(
+        options: &Options,
+        tx: queue::Sender<FrameLease<'static>>,
+        stats: Arc<StatsRecorder>,
+        events: mpsc::Sender<CaptureEvent>,
+        pool: BufferPool,
+    ) -> Self {
         let connection =
             dbus::blocking::Connection::new_session().expect("Failed to create dbus connection");
-        let stream_id = ScreenCastPortal::new(&connection)
+        let portal = ScreenCastPortal::new(&connection)
             .show_cursor(options.show_cursor)
-            .expect("Unsupported cursor mode")
+            .expect("Unsupported cursor mode");
+        let (session_handle, stream) = portal
             .create_stream()
-            .expect("Failed to get screencast stream")
-            .pw_node_id();
+            .expect("Failed to get screencast stream");
+        let session_closed = Arc::new(AtomicBool::new(false));
+        // Capturing works without the signal, the end of the session then
+        // goes unnoticed instead of being reported as SessionRevoked
+        if let Err(e) = portal.watch_session(session_handle, session_closed.clone()) {
+            let _ = events.send(CaptureEvent::Warning(format!(
+                "Failed to watch screencast session: {e}"
+            )));
+        }
+        let connection = Arc::new(Mutex::new(connection));
+        let session = PortalSession {
+            node_id: stream.pw_node_id(),
+            logical_size: stream.size(),
+            connection: connection.clone(),
+            closed: session_closed,
+        };
 
         // TODO: Fix this hack
         let options = options.clone();
         let (ready_sender, ready_recv) = sync_channel(1);
         let capturer_join_handle = std::thread::spawn(move || {
-            let res = pipewire_capturer(options, tx, &ready_sender, stream_id);
+            let res = pipewire_capturer(options, tx, stats, events, pool, &ready_sender, session);
             if res.is_err() {
                 ready_sender.send(false)?;
             }
@@ -364,6 +913,12 @@ impl LinuxCapturer {
     }
 }
 
-pub fn create_capturer(options: &Options, tx: mpsc::Sender<Frame>) -> LinuxCapturer {
-    LinuxCapturer::new(options, tx)
+pub fn create_capturer(
+    options: &Options,
+    tx: queue::Sender<FrameLease<'static>>,
+    stats: Arc<StatsRecorder>,
+    events: mpsc::Sender<CaptureEvent>,
+    pool: BufferPool,
+) -> LinuxCapturer {
+    LinuxCapturer::new(options, tx, stats, events, pool)
 }
@@ -117,6 +117,36 @@ impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>>
 }
 // }
 
+// This code was autogenerated with `dbus-codegen-rust -d org.freedesktop.portal.Desktop -p /org/freedesktop/portal/desktop -f org.freedesktop.portal.Screenshot`, see https://github.com/diwic/dbus-rs
+// {
+#[cfg(feature = "screenshot-portal")]
+trait OrgFreedesktopPortalScreenshot {
+    fn screenshot(
+        &self,
+        parent_window: &str,
+        options: arg::PropMap,
+    ) -> Result<dbus::Path<'static>, dbus::Error>;
+}
+
+#[cfg(feature = "screenshot-portal")]
+impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>>
+    OrgFreedesktopPortalScreenshot for blocking::Proxy<'a, C>
+{
+    fn screenshot(
+        &self,
+        parent_window: &str,
+        options: arg::PropMap,
+    ) -> Result<dbus::Path<'static>, dbus::Error> {
+        self.method_call(
+            "org.freedesktop.portal.Screenshot",
+            "Screenshot",
+            (parent_window, options),
+        )
+        .and_then(|r: (dbus::Path<'static>,)| Ok(r.0))
+    }
+}
+// }
+
 // This code was autogenerated with `dbus-codegen-rust --file org.freedesktop.portal.Request.xml`, see https://github.com/diwic/dbus-rs
 // {
 trait OrgFreedesktopPortalRequest {
@@ -159,6 +189,31 @@ impl<'a, T: blocking::BlockingSender, C: ::std::ops::Deref<Target = T>> OrgFreed
 }
 // }
 
+// This code was autogenerated with `dbus-codegen-rust --file org.freedesktop.portal.Session.xml`, see https://github.com/diwic/dbus-rs
+// {
+#[derive(Debug)]
+pub struct OrgFreedesktopPortalSessionClosed {
+    pub details: arg::PropMap,
+}
+
+impl arg::AppendAll for OrgFreedesktopPortalSessionClosed {
+    fn append(&self, i: &mut arg::IterAppend) {
+        arg::RefArg::append(&self.details, i);
+    }
+}
+
+impl arg::ReadAll for OrgFreedesktopPortalSessionClosed {
+    fn read(i: &mut arg::Iter) -> Result<Self, arg::TypeMismatchError> {
+        Ok(OrgFreedesktopPortalSessionClosed { details: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for OrgFreedesktopPortalSessionClosed {
+    const NAME: &'static str = "Closed";
+    const INTERFACE: &'static str = "org.freedesktop.portal.Session";
+}
+// }
+
 type Response = Option<OrgFreedesktopPortalRequestResponse>;
 
 #[derive(Debug)]
@@ -174,27 +229,51 @@ pub struct StreamVardict {
 #[derive(Debug)]
 pub struct Stream(u32, StreamVardict);
 
+// Reads a `(ii)` tuple wrapped in a variant
+fn i32_pair_from_dbus(value: &dyn RefArg) -> Option<(i32, i32)> {
+    let mut fields = value.as_iter()?.next()?.as_iter()?;
+    let first = fields.next()?.as_i64()?;
+    let second = fields.next()?.as_i64()?;
+    Some((first as i32, second as i32))
+}
+
 impl Stream {
     pub fn pw_node_id(&self) -> u32 {
         self.0
     }
 
+    /// Size of the stream in compositor (logical) coordinates
+    pub fn size(&self) -> Option<(i32, i32)> {
+        self.1.size
+    }
+
     pub fn from_dbus(stream: &Variant<Box<dyn RefArg>>) -> Option<Self> {
-        let mut stream = stream.as_iter()?.next()?.as_iter()?;
-        let pipewire_node_id = stream.next()?.as_iter()?.next()?.as_u64()?;
-
-        // TODO: Get the rest of the properties
-
-        Some(Self(
-            pipewire_node_id as u32,
-            StreamVardict {
-                id: None,
-                position: None,
-                size: None,
-                source_type: None,
-                mapping_id: None,
-            },
-        ))
+        let mut streams = stream.as_iter()?.next()?.as_iter()?;
+        let mut stream = streams.next()?.as_iter()?;
+        let pipewire_node_id = stream.next()?.as_u64()?;
+
+        let mut vardict = StreamVardict {
+            id: None,
+            position: None,
+            size: None,
+            source_type: None,
+            mapping_id: None,
+        };
+
+        if let Some(mut props) = stream.next().and_then(|props| props.as_iter()) {
+            while let (Some(key), Some(value)) = (props.next(), props.next()) {
+                match key.as_str() {
+                    Some("id") => vardict.id = value.as_str().map(String::from),
+                    Some("position") => vardict.position = i32_pair_from_dbus(value),
+                    Some("size") => vardict.size = i32_pair_from_dbus(value),
+                    Some("source_type") => vardict.source_type = value.as_u64().map(|t| t as u32),
+                    Some("mapping_id") => vardict.mapping_id = value.as_str().map(String::from),
+                    _ => {}
+                }
+            }
+        }
+
+        Some(Self(pipewire_node_id as u32, vardict))
     }
 }
 
@@ -307,7 +386,7 @@ impl<'a> ScreenCastPortal<'a> {
         Ok(())
     }
 
-    fn create_session(&self) -> Result<dbus::Path, LinCapError> {
+    fn create_session(&self) -> Result<dbus::Path<'static>, LinCapError> {
         let request_handle = self.proxy.create_session(self.create_session_args())?;
 
         let response = Arc::new(Mutex::new(None));
@@ -400,10 +479,36 @@ impl<'a> ScreenCastPortal<'a> {
         Err(LinCapError::new(String::from("Did not get response")))
     }
 
-    pub fn create_stream(&self) -> Result<Stream, LinCapError> {
+    /// Returns the stream with the handle of the session it belongs to
+    pub fn create_stream(&self) -> Result<(dbus::Path<'static>, Stream), LinCapError> {
         let session_handle = self.create_session()?;
         self.select_sources(session_handle.clone())?;
-        self.start(session_handle)
+        let stream = self.start(session_handle.clone())?;
+        Ok((session_handle, stream))
+    }
+
+    /// Sets `closed` once the session ends, for example because the user
+    /// stopped sharing. The signal is only received while the connection is
+    /// processed.
+    pub fn watch_session(
+        &self,
+        session_handle: dbus::Path<'static>,
+        closed: Arc<AtomicBool>,
+    ) -> Result<(), dbus::Error> {
+        let mut rule = MatchRule::new();
+        rule.path = Some(session_handle);
+        rule.msg_type = Some(dbus::MessageType::Signal);
+        rule.sender = Some(BusName::from("org.freedesktop.portal.Desktop"));
+        rule.interface = Some(Interface::from("org.freedesktop.portal.Session"));
+        self.proxy.connection.add_match(
+            rule,
+            move |_: OrgFreedesktopPortalSessionClosed, _, _| {
+                closed.store(true, std::sync::atomic::Ordering::Relaxed);
+                false
+            },
+        )?;
+
+        Ok(())
     }
 
     pub fn show_cursor(mut self, mode: bool) -> Result<Self, LinCapError> {
@@ -420,3 +525,56 @@ impl<'a> ScreenCastPortal<'a> {
         Err(LinCapError::new("Unsupported cursor mode".to_string()))
     }
 }
+
+#[cfg(feature = "screenshot-portal")]
+pub struct ScreenshotPortal<'a> {
+    proxy: Proxy<'a, &'a Connection>,
+    token: String,
+}
+
+#[cfg(feature = "screenshot-portal")]
+impl<'a> ScreenshotPortal<'a> {
+    pub fn new(connection: &'a Connection) -> Self {
+        let proxy = connection.with_proxy(
+            "org.freedesktop.portal.Desktop",
+            "/org/freedesktop/portal/desktop",
+            Duration::from_secs(4),
+        );
+
+        let token = format!("scap_{}", rand::random::<u16>());
+
+        Self { proxy, token }
+    }
+
+    /// Takes a screenshot of the whole desktop without asking the user to
+    /// pick a region, returning the URI of the image the portal saved
+    pub fn screenshot(&self) -> Result<String, LinCapError> {
+        let mut options = arg::PropMap::new();
+        options.insert(
+            String::from("handle_token"),
+            Variant(Box::new(self.token.clone())),
+        );
+        options.insert(String::from("interactive"), Variant(Box::new(false)));
+        let request_handle = self.proxy.screenshot("", options)?;
+
+        let response = Arc::new(Mutex::new(None));
+        let response_clone = Arc::clone(&response);
+        ScreenCastPortal::handle_req_response(
+            self.proxy.connection,
+            request_handle,
+            1200, // Wait 2 min, the user may be asked for permission
+            Duration::from_millis(100),
+            response_clone,
+        )?;
+
+        if let Some(res) = response.lock()?.take() {
+            match_response!(res.response);
+            return match res.results.get("uri").and_then(|uri| uri.0.as_str()) {
+                Some(uri) => Ok(uri.to_string()),
+                None => Err(LinCapError::new(String::from("Did not get the image URI"))),
+            };
+        }
+
+        Err(LinCapError::new(String::from("Did not get response")))
+    }
+}
@@ -0,0 +1,23 @@
+use std::sync::mpsc;
+
+use crate::{capturer::RawCapturer, frame::FrameLease};
+
+impl<'a> RawCapturer<'a> {
+    /// Get the next captured frame without copying it out of the PipeWire
+    /// buffer where possible.
+    ///
+    /// Frames are delivered as the compositor sends them, without
+    /// [Options::output_type](crate::capturer::Options::output_type) or
+    /// [Options::output_resolution](crate::capturer::Options::output_resolution)
+    /// applied. Their times and the statistics of the capture are kept as for
+    /// [Capturer::get_next_frame](crate::capturer::Capturer::get_next_frame).
+    pub fn get_next_frame_lease(&self) -> Result<FrameLease<'a>, mpsc::RecvError> {
+        loop {
+            let (sent, lease) = self.capturer.rx.recv_timed()?;
+            if let Some(lease) = self.capturer.engine.process_lease(lease, sent) {
+                self.capturer.stats.record_delivery(sent);
+                return Ok(lease);
+            }
+        }
+    }
+}
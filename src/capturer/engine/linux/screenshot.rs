@@ -0,0 +1,77 @@
+use std::{fs::File, time::SystemTime};
+
+use super::{error::LinCapError, portal::ScreenshotPortal};
+use crate::frame::{RGBAFrame, VideoFrame};
+
+/// Takes a screenshot of the whole desktop through the screenshot portal
+pub fn screenshot() -> Result<VideoFrame, LinCapError> {
+    let connection = dbus::blocking::Connection::new_session()?;
+    let uri = ScreenshotPortal::new(&connection).screenshot()?;
+    let path = path_from_uri(&uri)
+        .ok_or_else(|| LinCapError::new(format!("Unsupported image URI: {uri}")))?;
+    let frame = File::open(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| decode_png(file).map_err(|e| e.to_string()))
+        .map_err(|e| LinCapError::new(format!("{path}: {e}")));
+    // The portal leaves the image to us
+    let _ = std::fs::remove_file(&path);
+    frame
+}
+
+// Path of a `file://` URI, with percent escapes decoded
+fn path_from_uri(uri: &str) -> Option<String> {
+    let path = uri.strip_prefix("file://")?.as_bytes();
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        match path[i] {
+            b'%' => {
+                let hex = std::str::from_utf8(path.get(i + 1..i + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn decode_png(file: File) -> Result<VideoFrame, png::DecodingError> {
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data)?;
+    data.truncate(info.buffer_size());
+
+    let data = match info.color_type {
+        png::ColorType::Rgba => data,
+        png::ColorType::Rgb => data
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => data
+            .chunks_exact(2)
+            .flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]])
+            .collect(),
+        // Palettes are expanded to RGB(A) by the transformations
+        _ => data
+            .iter()
+            .flat_map(|&gray| [gray, gray, gray, 255])
+            .collect(),
+    };
+
+    Ok(VideoFrame::RGBA(RGBAFrame {
+        display_time: SystemTime::now(),
+        timestamp: Default::default(),
+        sequence: 0,
+        repeated: false,
+        width: info.width as i32,
+        height: info.height as i32,
+        data,
+        dirty_rects: None,
+    }))
+}
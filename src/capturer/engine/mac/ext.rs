@@ -1,6 +1,13 @@
-use cidre::cg;
+use cidre::{cf, cg};
 use core_graphics_helmer_fork::display::{CGDisplay, CGDisplayMode};
 
+use crate::frame::DirtyRect;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGRectMakeWithDictionaryRepresentation(dict: &cf::Dictionary, rect: *mut cg::Rect) -> bool;
+}
+
 pub trait DirectDisplayIdExt {
     fn display_mode(&self) -> Option<CGDisplayMode>;
 }
@@ -11,3 +18,35 @@ impl DirectDisplayIdExt for cg::DirectDisplayId {
         CGDisplay::new(self.0).display_mode()
     }
 }
+
+/// Converts the `SCStreamFrameInfo.dirtyRects` attachment (an array of
+/// `CGRect` dictionaries) into [DirtyRect]s
+pub fn dirty_rects_from_cf(value: &cf::Type) -> Option<Vec<DirtyRect>> {
+    if value.get_type_id() != cf::Array::type_id() {
+        return None;
+    }
+    let rects: &cf::ArrayOf<cf::Dictionary> = unsafe { std::mem::transmute(value) };
+
+    Some(
+        rects
+            .iter()
+            .filter_map(|dict| {
+                let mut rect = cg::Rect {
+                    origin: cg::Point { x: 0.0, y: 0.0 },
+                    size: cg::Size {
+                        width: 0.0,
+                        height: 0.0,
+                    },
+                };
+                unsafe { CGRectMakeWithDictionaryRepresentation(dict, &mut rect) }.then(|| {
+                    DirtyRect {
+                        x: rect.origin.x as i32,
+                        y: rect.origin.y as i32,
+                        width: rect.size.width as i32,
+                        height: rect.size.height as i32,
+                    }
+                })
+            })
+            .collect(),
+    )
+}
@@ -1,6 +1,5 @@
 use std::sync::atomic::AtomicBool;
-use std::sync::mpsc;
-use std::{cmp, sync::Arc};
+use std::sync::{mpsc, Arc};
 
 use cidre::mach;
 use cidre::sc::StreamDelegateImpl;
@@ -10,10 +9,12 @@ use cidre::{
 };
 use futures::executor::block_on;
 
-use crate::frame::{AudioFormat, AudioFrame, Frame, FrameType, VideoFrame};
+use crate::frame::{
+    AudioFormat, AudioFrame, BufferPool, Frame, FrameType, PixelFormat, ScaleMode, VideoFrame,
+};
 use crate::targets::Target;
 use crate::{
-    capturer::{Area, Options, Point, Resolution, Size},
+    capturer::{queue, Area, CaptureEvent, Options, Point, Resolution, Size},
     frame::BGRAFrame,
     targets,
 };
@@ -24,8 +25,17 @@ pub(crate) mod ext;
 mod pixel_buffer;
 mod pixelformat;
 
+// Formats ScreenCaptureKit is configured to deliver
+pub(crate) const NATIVE_FRAME_TYPES: &[FrameType] = &[FrameType::NV12, FrameType::BGRAFrame];
+
+// SCStreamErrorCode values that end a stream
+const SC_STREAM_ERROR_NO_CAPTURE_SOURCE: isize = -3815;
+const SC_STREAM_ERROR_USER_STOPPED: isize = -3817;
+
 struct ErrorHandlerInner {
     error_flag: Arc<AtomicBool>,
+    events: mpsc::Sender<CaptureEvent>,
+    tx: queue::Sender<ChannelItem>,
 }
 
 define_obj_type!(
@@ -45,15 +55,27 @@ impl sc::stream::DelegateImpl for ErrorHandler {
         error: &ns::Error,
     ) {
         eprintln!("Screen capture error occurred.");
-        self.inner_mut()
+        let inner = self.inner_mut();
+        inner
             .error_flag
             .store(true, std::sync::atomic::Ordering::Relaxed);
+
+        let event = match error.code() {
+            SC_STREAM_ERROR_NO_CAPTURE_SOURCE => CaptureEvent::TargetClosed,
+            SC_STREAM_ERROR_USER_STOPPED => CaptureEvent::SessionRevoked,
+            _ => CaptureEvent::StreamError(error.localized_description().to_string()),
+        };
+        let _ = inner.events.send(event);
+        inner.tx.end();
     }
 }
 
 #[repr(C)]
 pub struct CapturerInner {
-    pub tx: mpsc::Sender<ChannelItem>,
+    pub(crate) tx: queue::Sender<ChannelItem>,
+    // Numbers of the next video and audio frames
+    pub(crate) video_sequence: u64,
+    pub(crate) audio_sequence: u64,
 }
 
 define_obj_type!(pub Capturer + StreamOutputImpl, CapturerInner, CAPTURER);
@@ -69,10 +91,39 @@ impl sc::stream::OutputImpl for Capturer {
         sample_buf: &mut cm::SampleBuf,
         kind: sc::OutputType,
     ) {
-        let _ = self.inner_mut().tx.send((sample_buf.retained(), kind));
+        let inner = self.inner_mut();
+        let sequence = match kind {
+            // Idle frames repeat the number of the previous frame, and have
+            // nothing to repeat before the first one
+            sc::OutputType::Screen if !is_complete_frame(sample_buf) => {
+                match inner.video_sequence.checked_sub(1) {
+                    Some(sequence) => sequence,
+                    None => return,
+                }
+            }
+            sc::OutputType::Screen => {
+                inner.video_sequence += 1;
+                inner.video_sequence - 1
+            }
+            _ => {
+                inner.audio_sequence += 1;
+                inner.audio_sequence - 1
+            }
+        };
+        let _ = inner.tx.send((sample_buf.retained(), kind, sequence));
     }
 }
 
+// Whether a screen sample holds a new frame, rather than an idle or blank one
+fn is_complete_frame(sample: &cm::SampleBuf) -> bool {
+    sample
+        .attaches(false)
+        .and_then(|attaches| attaches.iter().next())
+        .and_then(|attaches| attaches.get(sc::FrameInfo::status().as_cf()))
+        .and_then(|status| status.as_number().to_i32())
+        == Some(0)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub(crate) enum CreateCapturerError {
     #[error("{0}")]
@@ -85,8 +136,9 @@ pub(crate) enum CreateCapturerError {
 
 pub(crate) fn create_capturer(
     options: &Options,
-    tx: mpsc::Sender<ChannelItem>,
+    tx: queue::Sender<ChannelItem>,
     error_flag: Arc<AtomicBool>,
+    events: mpsc::Sender<CaptureEvent>,
 ) -> Result<(arc::R<Capturer>, arc::R<ErrorHandler>, arc::R<sc::Stream>), CreateCapturerError> {
     // If no target is specified, capture the main display
     let target = options
@@ -161,14 +213,13 @@ pub(crate) fn create_capturer(
         },
     };
 
-    let pixel_format = match options.output_type {
-        FrameType::YUVFrame => cv::PixelFormat::_420V,
-        FrameType::BGR0 => cv::PixelFormat::_32_BGRA,
-        FrameType::RGB => cv::PixelFormat::_32_BGRA,
-        FrameType::BGRAFrame => cv::PixelFormat::_32_BGRA,
+    // Other YUV and RGB formats are converted from these in software
+    let pixel_format = match options.output_type.pixel_format() {
+        PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::P010 => cv::PixelFormat::_420V,
+        _ => cv::PixelFormat::_32_BGRA,
     };
 
-    let [width, height] = get_output_frame_size(options);
+    let [width, height] = get_stream_frame_size(options);
 
     let mut stream_config = sc::StreamCfg::new();
     stream_config.set_width(width as usize);
@@ -177,17 +228,25 @@ pub(crate) fn create_capturer(
     stream_config.set_pixel_format(pixel_format);
     stream_config.set_shows_cursor(options.show_cursor);
     stream_config.set_minimum_frame_interval(cm::Time {
-        value: 1,
-        scale: options.fps as i32,
+        value: options.fps.den() as i64,
+        scale: options.fps.num() as i32,
         epoch: 0,
         flags: cm::TimeFlags::VALID,
     });
     stream_config.set_captures_audio(options.captures_audio);
 
-    let error_handler = ErrorHandler::with(ErrorHandlerInner { error_flag });
+    let error_handler = ErrorHandler::with(ErrorHandlerInner {
+        error_flag,
+        events,
+        tx: tx.clone(),
+    });
     let stream = sc::Stream::with_delegate(&filter, &stream_config, error_handler.as_ref());
 
-    let capturer = CapturerInner { tx };
+    let capturer = CapturerInner {
+        tx,
+        video_sequence: 0,
+        audio_sequence: 0,
+    };
 
     let queue = dispatch::Queue::serial_with_ar_pool();
 
@@ -207,6 +266,13 @@ pub(crate) fn create_capturer(
 }
 
 pub fn get_output_frame_size(options: &Options) -> [u32; 2] {
+    let [width, height] = get_source_frame_size(options);
+
+    options.output_resolution.output_size(width, height)
+}
+
+// Size of the captured area in pixels
+fn get_source_frame_size(options: &Options) -> [u32; 2] {
     let target = options
         .target
         .clone()
@@ -215,27 +281,33 @@ pub fn get_output_frame_size(options: &Options) -> [u32; 2] {
     let scale_factor = targets::get_scale_factor(&target);
     let source_rect = get_crop_area(options);
 
-    // Calculate the output height & width based on the required resolution
     // Output width and height need to be multiplied by scale (or dpi)
-    let mut output_width = (source_rect.size.width as u32) * (scale_factor as u32);
-    let mut output_height = (source_rect.size.height as u32) * (scale_factor as u32);
-    // 1200x800
+    let width = (source_rect.size.width as u32) * (scale_factor as u32);
+    let height = (source_rect.size.height as u32) * (scale_factor as u32);
+
+    [width, height]
+}
+
+// Size the stream is configured with. ScreenCaptureKit keeps the aspect ratio
+// of the source, so custom sizes only get scaled to fit (or cover) here and
+// are finished in software.
+fn get_stream_frame_size(options: &Options) -> [u32; 2] {
+    let [width, height] = get_source_frame_size(options);
+
     match options.output_resolution {
-        Resolution::Captured => {}
-        _ => {
-            let [resolved_width, resolved_height] = options
-                .output_resolution
-                .value((source_rect.size.width as f32) / (source_rect.size.height as f32));
-            // 1280 x 853
-            output_width = cmp::min(output_width, resolved_width);
-            output_height = cmp::min(output_height, resolved_height);
+        Resolution::Custom {
+            width: output_width,
+            height: output_height,
+        } => {
+            let mode = match options.scale_mode {
+                ScaleMode::Fill => ScaleMode::Fill,
+                ScaleMode::Fit | ScaleMode::Stretch => ScaleMode::Fit,
+            };
+            let (width, height) = mode.content_size((width, height), (output_width, output_height));
+            [width, height]
         }
+        resolution => resolution.output_size(width, height),
     }
-
-    output_width -= output_width % 2;
-    output_height -= output_height % 2;
-
-    [output_width, output_height]
 }
 
 pub fn get_crop_area(options: &Options) -> Area {
@@ -276,30 +348,18 @@ pub fn get_crop_area(options: &Options) -> Area {
 pub fn process_sample_buffer(
     mut sample: arc::R<cm::SampleBuf>,
     of_type: sc::stream::OutputType,
+    sequence: u64,
     output_type: FrameType,
+    pool: &BufferPool,
 ) -> Option<Frame> {
-    let system_time = std::time::SystemTime::now();
-    let system_mach_time = mach::abs_time();
-
+    // Time of the sample on the host clock, which audio and video share
     let frame_cm_time = sample.pts();
     let frame_mach_time = cm::Clock::convert_host_time_to_sys_units(frame_cm_time);
-
-    let mach_time_diff = if frame_mach_time > system_mach_time {
-        (frame_mach_time - system_mach_time) as i64
-    } else {
-        -((system_mach_time - frame_mach_time) as i64)
-    };
-
-    // Convert mach time difference to nanoseconds
     let mach_timebase = mach::TimeBaseInfo::new();
-    let nanos_diff = (mach_time_diff * mach_timebase.numer as i64) / mach_timebase.denom as i64;
-
-    // Calculate frame SystemTime
-    let frame_system_time = if nanos_diff >= 0 {
-        system_time + std::time::Duration::from_nanos(nanos_diff as u64)
-    } else {
-        system_time - std::time::Duration::from_nanos((-nanos_diff) as u64)
-    };
+    let timestamp = std::time::Duration::from_nanos(
+        (frame_mach_time as u128 * mach_timebase.numer as u128 / mach_timebase.denom as u128)
+            as u64,
+    );
 
     match of_type {
         sc::stream::OutputType::Screen => {
@@ -315,29 +375,53 @@ pub fn process_sample_buffer(
                 .unwrap()
             {
                 0 => unsafe {
-                    return Some(Frame::Video(match output_type {
-                        FrameType::YUVFrame => {
-                            let yuvframe =
-                                pixelformat::create_yuv_frame(sample.as_mut(), frame_system_time)
-                                    .unwrap();
+                    let dirty_rects = attaches
+                        .get(sc::FrameInfo::dirty_rects().as_cf())
+                        .and_then(ext::dirty_rects_from_cf);
+
+                    return Some(Frame::Video(match output_type.pixel_format() {
+                        PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::P010 => {
+                            let yuvframe = pixelformat::create_yuv_frame(
+                                sample.as_mut(),
+                                timestamp,
+                                sequence,
+                                dirty_rects,
+                                pool,
+                            )
+                            .unwrap();
                             VideoFrame::YUVFrame(yuvframe)
                         }
-                        FrameType::RGB => {
-                            let rgbframe =
-                                pixelformat::create_rgb_frame(sample.as_mut(), frame_system_time)
-                                    .unwrap();
+                        PixelFormat::RGB => {
+                            let rgbframe = pixelformat::create_rgb_frame(
+                                sample.as_mut(),
+                                timestamp,
+                                sequence,
+                                dirty_rects,
+                                pool,
+                            )
+                            .unwrap();
                             VideoFrame::RGB(rgbframe)
                         }
-                        FrameType::BGR0 => {
-                            let bgrframe =
-                                pixelformat::create_bgr_frame(sample.as_mut(), frame_system_time)
-                                    .unwrap();
+                        PixelFormat::BGR => {
+                            let bgrframe = pixelformat::create_bgr_frame(
+                                sample.as_mut(),
+                                timestamp,
+                                sequence,
+                                dirty_rects,
+                                pool,
+                            )
+                            .unwrap();
                             VideoFrame::BGR0(bgrframe)
                         }
-                        FrameType::BGRAFrame => {
-                            let bgraframe =
-                                pixelformat::create_bgra_frame(sample.as_mut(), frame_system_time)
-                                    .unwrap();
+                        _ => {
+                            let bgraframe = pixelformat::create_bgra_frame(
+                                sample.as_mut(),
+                                timestamp,
+                                sequence,
+                                dirty_rects,
+                                pool,
+                            )
+                            .unwrap();
                             VideoFrame::BGRA(bgraframe)
                         }
                     }));
@@ -346,10 +430,15 @@ pub fn process_sample_buffer(
                     // Quick hack - just send an empty frame, and the caller can figure out how to handle it
                     if let FrameType::BGRAFrame = output_type {
                         return Some(Frame::Video(VideoFrame::BGRA(BGRAFrame {
-                            display_time: frame_system_time,
+                            display_time: std::time::SystemTime::UNIX_EPOCH + timestamp,
+                            timestamp,
+                            sequence,
+                            repeated: false,
                             width: 0,
                             height: 0,
                             data: vec![],
+                            // Idle frames have no changes
+                            dirty_rects: Some(vec![]),
                         })));
                     }
                 }
@@ -368,15 +457,18 @@ pub fn process_sample_buffer(
                 });
             }
 
-            return Some(Frame::Audio(AudioFrame::new(
-                AudioFormat::F32,
-                2,
-                false,
-                bytes,
-                sample.num_samples() as usize,
-                48_000,
-                frame_system_time,
-            )));
+            return Some(Frame::Audio(
+                AudioFrame::new(
+                    AudioFormat::F32,
+                    2,
+                    false,
+                    bytes,
+                    sample.num_samples() as usize,
+                    48_000,
+                    timestamp,
+                )
+                .with_sequence(sequence),
+            ));
         }
         _ => None,
     }
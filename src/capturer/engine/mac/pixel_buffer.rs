@@ -21,7 +21,7 @@ impl RawCapturer<'_> {
             }
 
             return match capturer.rx.recv_timeout(Duration::from_millis(10)) {
-                Ok(v) => Ok(v),
+                Ok((sample, kind, _)) => Ok((sample, kind)),
                 Err(mpsc::RecvTimeoutError::Timeout) => continue,
                 Err(mpsc::RecvTimeoutError::Disconnected) => Err(mpsc::RecvError),
             };
@@ -1,15 +1,18 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use cidre::{cm, cv};
 
 use crate::frame::{
-    convert_bgra_to_rgb, get_cropped_data, remove_alpha_channel, BGRAFrame, BGRFrame, RGBFrame,
-    YUVFrame,
+    convert_packed, BGRAFrame, BGRFrame, BufferPool, ColorMatrix, ColorRange, Colorimetry,
+    DirtyRect, PackedFormat, Plane, PlaneMut, RGBFrame, YUVFrame,
 };
 
 pub unsafe fn create_yuv_frame(
     sample_buffer: &mut cm::SampleBuf,
-    display_time: SystemTime,
+    timestamp: Duration,
+    sequence: u64,
+    dirty_rects: Option<Vec<DirtyRect>>,
+    pool: &BufferPool,
 ) -> Option<YUVFrame> {
     let image_buffer = sample_buffer.image_buf_mut().unwrap();
 
@@ -28,22 +31,21 @@ pub unsafe fn create_yuv_frame(
     }
 
     let luminance_stride = image_buffer.plane_bytes_per_row(0);
-    let luminance_bytes = unsafe {
+    let luminance_bytes = pool.copy(unsafe {
         std::slice::from_raw_parts(
             image_buffer.plane_base_address(0),
             luminance_stride * image_buffer.plane_height(0),
         )
-    }
-    .to_vec();
+    });
 
-    let chrominance_stride = image_buffer.plane_bytes_per_row(0);
-    let chrominance_bytes = unsafe {
+    // Plane 1 holds the interleaved U and V samples at half resolution
+    let chrominance_stride = image_buffer.plane_bytes_per_row(1);
+    let chrominance_bytes = pool.copy(unsafe {
         std::slice::from_raw_parts(
-            image_buffer.plane_base_address(0),
-            luminance_stride * image_buffer.plane_height(0),
+            image_buffer.plane_base_address(1),
+            chrominance_stride * image_buffer.plane_height(1),
         )
-    }
-    .to_vec();
+    });
 
     unsafe {
         image_buffer
@@ -53,19 +55,33 @@ pub unsafe fn create_yuv_frame(
     };
 
     Some(YUVFrame {
-        display_time,
+        display_time: SystemTime::UNIX_EPOCH + timestamp,
+        timestamp,
+        sequence,
+        repeated: false,
         width: width as i32,
         height: height as i32,
         luminance_bytes,
         luminance_stride: luminance_stride as i32,
         chrominance_bytes,
         chrominance_stride: chrominance_stride as i32,
+        // The stream is configured for video range 420v, and ScreenCaptureKit
+        // tags it with the BT.709 matrix by default
+        colorimetry: Colorimetry {
+            matrix: ColorMatrix::Bt709,
+            range: ColorRange::Limited,
+            ..Default::default()
+        },
+        dirty_rects,
     })
 }
 
 pub unsafe fn create_bgr_frame(
     sample_buffer: &mut cm::SampleBuf,
-    display_time: SystemTime,
+    timestamp: Duration,
+    sequence: u64,
+    dirty_rects: Option<Vec<DirtyRect>>,
+    pool: &BufferPool,
 ) -> Option<BGRFrame> {
     let image_buffer = sample_buffer.image_buf_mut().unwrap();
 
@@ -89,22 +105,27 @@ pub unsafe fn create_bgr_frame(
             image_buffer.plane_base_address(0),
             stride * image_buffer.plane_height(0),
         )
-    }
-    .to_vec();
-
-    let cropped_data = get_cropped_data(bytes, (stride / 4) as i32, height as i32, width as i32);
+    };
+    let data = pack_pixels(bytes, stride, (width, height), PackedFormat::BGR, pool);
 
     Some(BGRFrame {
-        display_time,
+        display_time: SystemTime::UNIX_EPOCH + timestamp,
+        timestamp,
+        sequence,
+        repeated: false,
         width: width as i32, // width does not give accurate results - https://stackoverflow.com/questions/19587185/cvpixelbuffergetbytesperrow-for-cvimagebufferref-returns-unexpected-wrong-valu
         height: height as i32,
-        data: remove_alpha_channel(cropped_data),
+        data,
+        dirty_rects,
     })
 }
 
 pub unsafe fn create_bgra_frame(
     sample_buffer: &mut cm::SampleBuf,
-    display_time: SystemTime,
+    timestamp: Duration,
+    sequence: u64,
+    dirty_rects: Option<Vec<DirtyRect>>,
+    pool: &BufferPool,
 ) -> Option<BGRAFrame> {
     let image_buffer = sample_buffer.image_buf_mut().unwrap();
 
@@ -124,7 +145,7 @@ pub unsafe fn create_bgra_frame(
 
     let stride = image_buffer.plane_bytes_per_row(0);
 
-    let mut data: Vec<u8> = vec![];
+    let mut data = pool.take(4 * width * height).into_vec();
 
     let bytes = unsafe {
         std::slice::from_raw_parts(
@@ -139,16 +160,23 @@ pub unsafe fn create_bgra_frame(
     }
 
     Some(BGRAFrame {
-        display_time,
+        display_time: SystemTime::UNIX_EPOCH + timestamp,
+        timestamp,
+        sequence,
+        repeated: false,
         width: width as i32, // width does not give accurate results - https://stackoverflow.com/questions/19587185/cvpixelbuffergetbytesperrow-for-cvimagebufferref-returns-unexpected-wrong-valu
         height: height as i32,
         data,
+        dirty_rects,
     })
 }
 
 pub unsafe fn create_rgb_frame(
     sample_buffer: &mut cm::SampleBuf,
-    display_time: SystemTime,
+    timestamp: Duration,
+    sequence: u64,
+    dirty_rects: Option<Vec<DirtyRect>>,
+    pool: &BufferPool,
 ) -> Option<RGBFrame> {
     let image_buffer = sample_buffer.image_buf_mut().unwrap();
 
@@ -173,15 +201,45 @@ pub unsafe fn create_rgb_frame(
             image_buffer.plane_base_address(0),
             stride * image_buffer.plane_height(0),
         )
-    }
-    .to_vec();
-
-    let cropped_data = get_cropped_data(bytes, (stride / 4) as i32, height as i32, width as i32);
+    };
+    let data = pack_pixels(bytes, stride, (width, height), PackedFormat::RGB, pool);
 
     Some(RGBFrame {
-        display_time,
+        display_time: SystemTime::UNIX_EPOCH + timestamp,
+        timestamp,
+        sequence,
+        repeated: false,
         width: width as i32, // width does not give accurate results - https://stackoverflow.com/questions/19587185/cvpixelbuffergetbytesperrow-for-cvimagebufferref-returns-unexpected-wrong-valu
         height: height as i32,
-        data: convert_bgra_to_rgb(cropped_data),
+        data,
+        dirty_rects,
     })
 }
+
+// Converts the BGRA rows of a pixel buffer into tightly packed `format` pixels
+// in a buffer of `pool`
+fn pack_pixels(
+    bytes: &[u8],
+    stride: usize,
+    (width, height): (usize, usize),
+    format: PackedFormat,
+    pool: &BufferPool,
+) -> Vec<u8> {
+    let row_size = width * format.bytes_per_pixel();
+    let mut data = pool.take(row_size * height).into_vec();
+    data.resize(row_size * height, 0);
+    convert_packed(
+        Plane {
+            data: bytes,
+            stride,
+        },
+        PackedFormat::BGRA,
+        PlaneMut {
+            data: &mut data,
+            stride: row_size,
+        },
+        format,
+        (width, height),
+    );
+    data
+}
@@ -1,7 +1,13 @@
-use std::sync::mpsc;
+use std::{
+    cell::{OnceCell, RefCell},
+    sync::{mpsc, Arc},
+    time::{Duration, Instant},
+};
 
-use super::Options;
-use crate::frame::Frame;
+use super::{clock::CaptureClock, queue, stats::StatsRecorder, CaptureEvent, Options, Resolution};
+#[cfg(target_os = "linux")]
+use crate::frame::FrameLease;
+use crate::frame::{BufferPool, Frame, FrameType, PixelFormat, ScaleFilter, VideoFrame};
 
 #[cfg(target_os = "macos")]
 pub mod mac;
@@ -16,9 +22,23 @@ mod linux;
 pub type ChannelItem = (
     cidre::arc::R<cidre::cm::SampleBuf>,
     cidre::sc::stream::OutputType,
+    u64,
 );
-#[cfg(not(target_os = "macos"))]
+#[cfg(target_os = "windows")]
 pub type ChannelItem = Frame;
+#[cfg(target_os = "linux")]
+pub type ChannelItem = crate::frame::FrameLease<'static>;
+
+// Whether the item holds a video frame, which may be dropped when the
+// consumer falls behind
+pub(crate) fn is_video(item: &ChannelItem) -> bool {
+    #[cfg(target_os = "macos")]
+    return matches!(item.1, cidre::sc::stream::OutputType::Screen);
+    #[cfg(target_os = "windows")]
+    return matches!(item, Frame::Video(_));
+    #[cfg(target_os = "linux")]
+    return true;
+}
 
 pub fn get_output_frame_size(options: &Options) -> [u32; 2] {
     #[cfg(target_os = "macos")]
@@ -38,8 +58,41 @@ pub fn get_output_frame_size(options: &Options) -> [u32; 2] {
     }
 }
 
+/// Frame types this platform can deliver. Those the backend produces natively
+/// come first, the rest are converted in software.
+pub fn supported_frame_types() -> Vec<FrameType> {
+    #[cfg(target_os = "macos")]
+    let native = mac::NATIVE_FRAME_TYPES;
+    #[cfg(target_os = "windows")]
+    let native = win::NATIVE_FRAME_TYPES;
+    #[cfg(target_os = "linux")]
+    let native = linux::NATIVE_FRAME_TYPES;
+
+    let converted = FrameType::ALL
+        .into_iter()
+        .filter(|frame_type| !native.contains(frame_type));
+    native.iter().copied().chain(converted).collect()
+}
+
+/// Takes a screenshot where there is a cheaper way than starting a capture,
+/// `None` where there isn't or it failed
+pub(crate) fn screenshot() -> Option<VideoFrame> {
+    #[cfg(all(target_os = "linux", feature = "screenshot-portal"))]
+    return linux::screenshot().ok();
+
+    #[cfg(not(all(target_os = "linux", feature = "screenshot-portal")))]
+    None
+}
+
 pub struct Engine {
     options: Options,
+    stats: Arc<StatsRecorder>,
+    events: mpsc::Sender<CaptureEvent>,
+    // Size of the first frame, when Options::keep_output_size is set
+    output_size: OnceCell<(u32, u32)>,
+    clock: RefCell<CaptureClock>,
+    // Buffers frames are copied into, shared with the backend
+    pool: BufferPool,
 
     #[cfg(target_os = "macos")]
     mac: (
@@ -58,49 +111,85 @@ pub struct Engine {
 }
 
 impl Engine {
-    pub fn new(options: &Options, tx: mpsc::Sender<ChannelItem>) -> Engine {
+    pub(crate) fn new(
+        options: &Options,
+        tx: queue::Sender<ChannelItem>,
+        stats: Arc<StatsRecorder>,
+        events: mpsc::Sender<CaptureEvent>,
+    ) -> Engine {
+        let pool = BufferPool::new();
+
         #[cfg(target_os = "macos")]
         {
             let error_flag = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
-            let mac = mac::create_capturer(options, tx, error_flag.clone()).unwrap();
+            let mac =
+                mac::create_capturer(options, tx, error_flag.clone(), events.clone()).unwrap();
+            if !options.fps.is_zero() {
+                stats.record_frame_rate(options.fps);
+            }
 
             Engine {
                 mac,
                 error_flag,
                 options: (*options).clone(),
+                stats,
+                events,
+                output_size: OnceCell::new(),
+                clock: RefCell::new(CaptureClock::new()),
+                pool,
             }
         }
 
         #[cfg(target_os = "windows")]
         {
-            let win = win::create_capturer(&options, tx).unwrap();
+            let win =
+                win::create_capturer(&options, tx, stats.clone(), events.clone(), pool.clone())
+                    .unwrap();
+            if !options.fps.is_zero() {
+                stats.record_frame_rate(options.fps);
+            }
             return Engine {
                 win,
                 options: (*options).clone(),
+                stats,
+                events,
+                output_size: OnceCell::new(),
+                clock: RefCell::new(CaptureClock::new()),
+                pool,
             };
         }
 
         #[cfg(target_os = "linux")]
         {
-            let linux = linux::create_capturer(&options, tx);
+            let linux =
+                linux::create_capturer(&options, tx, stats.clone(), events.clone(), pool.clone());
             return Engine {
                 linux,
                 options: (*options).clone(),
+                stats,
+                events,
+                output_size: OnceCell::new(),
+                clock: RefCell::new(CaptureClock::new()),
+                pool,
             };
         }
     }
 
     pub fn start(&mut self) {
+        self.clock = RefCell::new(CaptureClock::new());
+
         #[cfg(target_os = "macos")]
         {
             use futures::executor::block_on;
 
             block_on(self.mac.2.start()).expect("Failed to start capture");
+            let _ = self.events.send(CaptureEvent::Started);
         }
 
         #[cfg(target_os = "windows")]
         {
             self.win.start_capture();
+            let _ = self.events.send(CaptureEvent::Started);
         }
 
         #[cfg(target_os = "linux")]
@@ -132,12 +221,132 @@ impl Engine {
         get_output_frame_size(&self.options)
     }
 
-    pub fn process_channel_item(&self, data: ChannelItem) -> Option<Frame> {
+    /// Time since the capture started, on the clock of the frame timestamps
+    pub fn elapsed(&self) -> Duration {
+        self.clock.borrow().elapsed()
+    }
+
+    /// Turns an item the backend sent at `sent` into a frame in the
+    /// requested format, with times on the clock of the capture
+    pub fn process_channel_item(&self, data: ChannelItem, sent: Instant) -> Option<Frame> {
         #[cfg(target_os = "macos")]
-        {
-            mac::process_sample_buffer(data.0, data.1, self.options.output_type)
+        let frame = self.process_frame(
+            mac::process_sample_buffer(
+                data.0,
+                data.1,
+                data.2,
+                self.options.output_type,
+                &self.pool,
+            )?,
+            sent,
+        )?;
+        #[cfg(target_os = "windows")]
+        let frame = self.process_frame(data, sent)?;
+        #[cfg(target_os = "linux")]
+        let frame = Frame::Video(self.process_lease(data, sent)?.into_video_frame());
+
+        match frame {
+            Frame::Video(frame) => {
+                let frame = self.resize_frame(frame);
+                Some(Frame::Video(
+                    frame.converted(self.options.output_type.pixel_format()),
+                ))
+            }
+            frame => Some(frame),
+        }
+    }
+
+    /// Hands the buffers of a frame back to the pool, for the frames that
+    /// follow to be copied into
+    pub fn recycle(&self, frame: VideoFrame) {
+        for buffer in frame.into_buffers() {
+            self.pool.recycle(buffer);
+        }
+    }
+
+    // Drops idle frames and puts the others on the clock of the capture
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    fn process_frame(&self, mut frame: Frame, sent: Instant) -> Option<Frame> {
+        // macOS signals idle frames with empty ones, which are not delivered
+        if matches!(&frame, Frame::Video(video) if video.width() == 0) {
+            return None;
+        }
+
+        // Backends put the time on their own clock in the timestamp, zero
+        // when they don't know it
+        let source_time = Some(frame.timestamp()).filter(|time| !time.is_zero());
+        let mut clock = self.clock.borrow_mut();
+        let clock = match frame {
+            Frame::Video(_) => &mut clock.video,
+            Frame::Audio(_) => &mut clock.audio,
+        };
+        let (timestamp, display_time) = clock.map(source_time, sent);
+        frame.set_time(timestamp, display_time);
+
+        if let Frame::Video(frame) = &frame {
+            self.record_native(frame.format(), frame.width(), frame.height());
         }
-        #[cfg(not(target_os = "macos"))]
-        return Some(data);
+        Some(frame)
+    }
+
+    /// Takes a lease of the PipeWire backend through the same steps as
+    /// [Engine::process_channel_item], short of copying it into a frame
+    #[cfg(target_os = "linux")]
+    pub(crate) fn process_lease<'a>(
+        &self,
+        mut lease: FrameLease<'a>,
+        sent: Instant,
+    ) -> Option<FrameLease<'a>> {
+        if lease.width() == 0 {
+            return None;
+        }
+
+        let source_time = Some(lease.timestamp()).filter(|time| !time.is_zero());
+        let (timestamp, display_time) = self.clock.borrow_mut().video.map(source_time, sent);
+        lease.set_time(timestamp, display_time);
+
+        self.record_native(lease.format(), lease.width(), lease.height());
+        Some(lease)
+    }
+
+    // Announces frames that differ in format or size from the previous one
+    fn record_native(&self, format: PixelFormat, width: u32, height: u32) {
+        if self.stats.record_native(format, width, height) {
+            let _ = self.events.send(CaptureEvent::FormatChanged {
+                format,
+                width,
+                height,
+            });
+        }
+    }
+
+    // Brings frames to the output resolution in software, for the sizes the
+    // backend cannot deliver itself
+    fn resize_frame(&self, frame: VideoFrame) -> VideoFrame {
+        let resolution = self.options.output_resolution;
+        let scaled_by_backend = match resolution {
+            Resolution::Captured => true,
+            // ScreenCaptureKit always preserves the aspect ratio of the source
+            Resolution::Custom { .. } => false,
+            _ => cfg!(target_os = "macos"),
+        };
+
+        let (width, height) = frame.size();
+        let [output_width, output_height] = match scaled_by_backend {
+            true => [width, height],
+            false => resolution.output_size(width, height),
+        };
+        let (output_width, output_height) = match self.options.keep_output_size {
+            true => *self
+                .output_size
+                .get_or_init(|| (output_width, output_height)),
+            false => (output_width, output_height),
+        };
+        frame.resized(
+            output_width,
+            output_height,
+            ScaleFilter::Area,
+            self.options.scale_mode,
+        )
     }
 }
@@ -1,19 +1,25 @@
 use crate::{
-    capturer::{Area, Options, Point, Resolution, Size},
-    frame::{AudioFormat, AudioFrame, BGRAFrame, Frame, FrameType, VideoFrame},
+    capturer::{queue, stats::StatsRecorder, Area, CaptureEvent, Options, Point, Size},
+    frame::{
+        AudioFormat, AudioFrame, BGRAFrame, BufferPool, ColorMatrix, ColorPrimaries, ColorRange,
+        Colorimetry, Frame, FrameType, PixelFormat, RGBA16FFrame, RGBAFrame, TransferFunction,
+        VideoFrame,
+    },
     targets::{self, get_scale_factor, Target},
 };
-use ::windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
     StreamInstant,
 };
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{cmp, time::Duration};
+use std::time::SystemTime;
+use std::time::{Duration, Instant};
 use std::{
     os::windows,
     ptr::null_mut,
-    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
 };
 use windows_capture::{
     capture::{CaptureControl, Context, GraphicsCaptureApiHandler},
@@ -27,12 +33,22 @@ use windows_capture::{
     window::Window as WCWindow,
 };
 
+// Formats Windows.Graphics.Capture delivers
+pub(crate) const NATIVE_FRAME_TYPES: &[FrameType] =
+    &[FrameType::BGRAFrame, FrameType::RGBA, FrameType::RGBA16F];
+
 #[derive(Debug)]
 struct Capturer {
-    pub tx: mpsc::Sender<Frame>,
+    pub tx: queue::Sender<Frame>,
+    // Only set when a crop area was requested, so frames otherwise follow
+    // the size of the target
     pub crop: Option<Area>,
-    pub start_time: (i64, SystemTime),
-    pub perf_freq: i64,
+    pub color_format: ColorFormat,
+    pub stats: Arc<StatsRecorder>,
+    pub events: mpsc::Sender<CaptureEvent>,
+    pub pool: BufferPool,
+    // Number of the next frame
+    pub sequence: u64,
 }
 
 #[derive(Clone)]
@@ -55,19 +71,11 @@ impl GraphicsCaptureApiHandler for Capturer {
         Ok(Self {
             tx: context.flags.tx,
             crop: context.flags.crop,
-            start_time: (
-                unsafe {
-                    let mut time = 0;
-                    QueryPerformanceCounter(&mut time);
-                    time
-                },
-                SystemTime::now(),
-            ),
-            perf_freq: unsafe {
-                let mut freq = 0;
-                QueryPerformanceFrequency(&mut freq);
-                freq
-            },
+            color_format: context.flags.color_format,
+            stats: context.flags.stats,
+            events: context.flags.events,
+            pool: context.flags.pool,
+            sequence: 0,
         })
     }
 
@@ -76,22 +84,28 @@ impl GraphicsCaptureApiHandler for Capturer {
         frame: &mut WCFrame,
         _: InternalCaptureControl,
     ) -> Result<(), Self::Error> {
-        let elapsed = frame.timespan().Duration - self.start_time.0;
-        let display_time = self
-            .start_time
-            .1
-            .checked_add(Duration::from_secs_f64(
-                elapsed as f64 / self.perf_freq as f64,
-            ))
-            .unwrap();
-
-        match &self.crop {
+        // The system relative time of the frame, in 100 ns units of the
+        // performance counter
+        let timestamp = Duration::from_nanos(frame.timespan().Duration.max(0) as u64 * 100);
+        let display_time = SystemTime::UNIX_EPOCH + timestamp;
+        // Frames that are skipped below leave a gap in the numbers
+        let sequence = self.sequence;
+        self.sequence += 1;
+
+        let bgr_frame = match &self.crop {
             Some(cropped_area) => {
-                // get the cropped area
+                // get the cropped area, limited to the frame as the target
+                // may have been resized
                 let start_x = cropped_area.origin.x as u32;
                 let start_y = cropped_area.origin.y as u32;
-                let end_x = (cropped_area.origin.x + cropped_area.size.width) as u32;
-                let end_y = (cropped_area.origin.y + cropped_area.size.height) as u32;
+                let end_x =
+                    ((cropped_area.origin.x + cropped_area.size.width) as u32).min(frame.width());
+                let end_y =
+                    ((cropped_area.origin.y + cropped_area.size.height) as u32).min(frame.height());
+                if start_x >= end_x || start_y >= end_y {
+                    // The crop area is outside of the shrunk target
+                    return Ok(());
+                }
 
                 // crop the frame
                 let mut cropped_buffer = frame
@@ -101,51 +115,129 @@ impl GraphicsCaptureApiHandler for Capturer {
                 // get raw frame buffer
                 let raw_frame_buffer = match cropped_buffer.as_nopadding_buffer() {
                     Ok(buffer) => buffer,
-                    Err(_) => return Err(("Failed to get raw buffer").into()),
+                    Err(_) => {
+                        self.stats.record_corrupt();
+                        self.end_stream(CaptureEvent::StreamError(
+                            "Failed to get raw buffer".to_string(),
+                        ));
+                        return Err(("Failed to get raw buffer").into());
+                    }
                 };
 
-                let current_time = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Failed to get current time")
-                    .as_nanos() as u64;
-
-                let bgr_frame = BGRAFrame {
+                BGRAFrame {
                     display_time,
-                    width: cropped_area.size.width as i32,
-                    height: cropped_area.size.height as i32,
-                    data: raw_frame_buffer.to_vec(),
-                };
-
-                let _ = self.tx.send(Frame::Video(VideoFrame::BGRA(bgr_frame)));
+                    timestamp,
+                    sequence,
+                    repeated: false,
+                    width: (end_x - start_x) as i32,
+                    height: (end_y - start_y) as i32,
+                    data: self.pool.copy(raw_frame_buffer),
+                    // windows-capture does not expose the dirty regions of a frame
+                    dirty_rects: None,
+                }
             }
             None => {
                 // get raw frame buffer
                 let mut frame_buffer = frame.buffer().unwrap();
-                let raw_frame_buffer = frame_buffer.as_raw_buffer();
-                let frame_data = raw_frame_buffer.to_vec();
-                let current_time = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Failed to get current time")
-                    .as_nanos() as u64;
-                let bgr_frame = BGRAFrame {
+                let raw_frame_buffer = match frame_buffer.as_nopadding_buffer() {
+                    Ok(buffer) => buffer,
+                    Err(_) => {
+                        self.stats.record_corrupt();
+                        self.end_stream(CaptureEvent::StreamError(
+                            "Failed to get raw buffer".to_string(),
+                        ));
+                        return Err(("Failed to get raw buffer").into());
+                    }
+                };
+                let frame_data = self.pool.copy(raw_frame_buffer);
+                BGRAFrame {
                     display_time,
+                    timestamp,
+                    sequence,
+                    repeated: false,
                     width: frame.width() as i32,
                     height: frame.height() as i32,
                     data: frame_data,
-                };
+                    dirty_rects: None,
+                }
+            }
+        };
 
-                let _ = self.tx.send(Frame::Video(VideoFrame::BGRA(bgr_frame)));
+        let video_frame = match self.color_format {
+            ColorFormat::Bgra8 => VideoFrame::BGRA(bgr_frame),
+            ColorFormat::Rgba8 => {
+                let BGRAFrame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    data,
+                    dirty_rects,
+                } = bgr_frame;
+                VideoFrame::RGBA(RGBAFrame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    data,
+                    dirty_rects,
+                })
             }
-        }
+            // The desktop is composed in linear scRGB, with HDR content
+            // exceeding 1.0
+            ColorFormat::Rgba16F => {
+                let BGRAFrame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    data,
+                    dirty_rects,
+                } = bgr_frame;
+                VideoFrame::RGBA16F(RGBA16FFrame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    data,
+                    colorimetry: Colorimetry {
+                        matrix: ColorMatrix::Bt709,
+                        range: ColorRange::Full,
+                        primaries: ColorPrimaries::Bt709,
+                        transfer: TransferFunction::Linear,
+                    },
+                    dirty_rects,
+                })
+            }
+        };
+
+        let _ = self.tx.send(Frame::Video(video_frame));
         Ok(())
     }
 
     fn on_closed(&mut self) -> Result<(), Self::Error> {
-        println!("Closed");
+        self.end_stream(CaptureEvent::TargetClosed);
         Ok(())
     }
 }
 
+impl Capturer {
+    // Reports why the stream stopped and lets the receiver know no more
+    // frames follow
+    fn end_stream(&self, event: CaptureEvent) {
+        let _ = self.events.send(event);
+        self.tx.end();
+    }
+}
+
 impl WCStream {
     pub fn start_capture(&mut self) {
         let cc = match &self.settings {
@@ -172,8 +264,12 @@ impl WCStream {
 
 #[derive(Clone, Debug)]
 struct FlagStruct {
-    pub tx: mpsc::Sender<Frame>,
+    pub tx: queue::Sender<Frame>,
     pub crop: Option<Area>,
+    pub color_format: ColorFormat,
+    pub stats: Arc<StatsRecorder>,
+    pub events: mpsc::Sender<CaptureEvent>,
+    pub pool: BufferPool,
 }
 
 #[derive(Debug)]
@@ -184,16 +280,22 @@ pub enum CreateCapturerError {
 
 pub fn create_capturer(
     options: &Options,
-    tx: mpsc::Sender<Frame>,
+    tx: queue::Sender<Frame>,
+    stats: Arc<StatsRecorder>,
+    events: mpsc::Sender<CaptureEvent>,
+    pool: BufferPool,
 ) -> Result<WCStream, CreateCapturerError> {
     let target = options
         .target
         .clone()
         .unwrap_or_else(|| Target::Display(targets::get_main_display()));
 
-    let color_format = match options.output_type {
-        FrameType::BGRAFrame => ColorFormat::Bgra8,
-        _ => ColorFormat::Rgba8,
+    // Other formats are converted from these in software. HDR output needs
+    // the half float format, as the others are clipped to SDR.
+    let color_format = match options.output_type.pixel_format() {
+        PixelFormat::RGBA | PixelFormat::RGBx | PixelFormat::RGB => ColorFormat::Rgba8,
+        PixelFormat::RGBA16F | PixelFormat::P010 => ColorFormat::Rgba16F,
+        _ => ColorFormat::Bgra8,
     };
 
     let show_cursor = match options.show_cursor {
@@ -210,18 +312,27 @@ pub fn create_capturer(
         DrawBorderSettings::Default
     };
 
+    let minimum_update_interval = match options.fps.is_zero() {
+        true => MinimumUpdateIntervalSettings::Default,
+        false => MinimumUpdateIntervalSettings::Custom(options.fps.interval()),
+    };
+
     let settings = match target {
         Target::Display(display) => Settings::Display(WCSettings::new(
             WCMonitor::from_raw_hmonitor(display.raw_handle.0),
             show_cursor,
             draw_border,
             SecondaryWindowSettings::Default,
-            MinimumUpdateIntervalSettings::Default,
+            minimum_update_interval,
             DirtyRegionSettings::Default,
             color_format,
             FlagStruct {
                 tx: tx.clone(),
-                crop: Some(get_crop_area(options)),
+                crop: options.crop_area.as_ref().map(|_| get_crop_area(options)),
+                color_format,
+                stats: stats.clone(),
+                events: events.clone(),
+                pool: pool.clone(),
             },
         )),
         Target::Window(window) => Settings::Window(WCSettings::new(
@@ -229,12 +340,16 @@ pub fn create_capturer(
             show_cursor,
             draw_border,
             SecondaryWindowSettings::Default,
-            MinimumUpdateIntervalSettings::Default,
+            minimum_update_interval,
             DirtyRegionSettings::Default,
             color_format,
             FlagStruct {
                 tx: tx.clone(),
-                crop: Some(get_crop_area(options)),
+                crop: options.crop_area.as_ref().map(|_| get_crop_area(options)),
+                color_format,
+                stats: stats.clone(),
+                events: events.clone(),
+                pool: pool.clone(),
             },
         )),
     };
@@ -243,7 +358,7 @@ pub fn create_capturer(
         let (ctrl_tx, ctrl_rx) = mpsc::channel();
         let (ready_tx, ready_rx) = mpsc::channel();
 
-        spawn_audio_stream(tx.clone(), ready_tx, ctrl_rx);
+        spawn_audio_stream(tx.clone(), events, ready_tx, ctrl_rx);
 
         match ready_rx.recv() {
             Ok(Ok(())) => {}
@@ -271,25 +386,9 @@ pub fn get_output_frame_size(options: &Options) -> [u32; 2] {
 
     let crop_area = get_crop_area(options);
 
-    let mut output_width = (crop_area.size.width) as u32;
-    let mut output_height = (crop_area.size.height) as u32;
-
-    match options.output_resolution {
-        Resolution::Captured => {}
-        _ => {
-            let [resolved_width, resolved_height] = options
-                .output_resolution
-                .value((crop_area.size.width as f32) / (crop_area.size.height as f32));
-            // 1280 x 853
-            output_width = cmp::min(output_width, resolved_width);
-            output_height = cmp::min(output_height, resolved_height);
-        }
-    }
-
-    output_width -= output_width % 2;
-    output_height -= output_height % 2;
-
-    [output_width, output_height]
+    options
+        .output_resolution
+        .output_size(crop_area.size.width as u32, crop_area.size.height as u32)
 }
 
 fn get_absolute_value(value: f64, scale_factor: f64) -> f64 {
@@ -342,9 +441,7 @@ enum AudioStreamControl {
 }
 
 fn build_audio_stream(
-    sample_tx: mpsc::Sender<
-        Result<(Vec<u8>, cpal::InputCallbackInfo, SystemTime), cpal::StreamError>,
-    >,
+    sample_tx: mpsc::Sender<Result<(Vec<u8>, cpal::InputCallbackInfo), cpal::StreamError>>,
 ) -> Result<(cpal::Stream, cpal::SupportedStreamConfig), CreateCapturerError> {
     let host = cpal::default_host();
     let output_device =
@@ -365,7 +462,7 @@ fn build_audio_stream(
                 let sample_tx = sample_tx.clone();
                 move |data, info: &cpal::InputCallbackInfo| {
                     sample_tx
-                        .send(Ok((data.bytes().to_vec(), info.clone(), SystemTime::now())))
+                        .send(Ok((data.bytes().to_vec(), info.clone())))
                         .unwrap();
                 }
             },
@@ -379,8 +476,14 @@ fn build_audio_stream(
     Ok((stream, supported_config))
 }
 
+// Name of the device audio is captured from
+fn default_output_device_name() -> Option<String> {
+    cpal::default_host().default_output_device()?.name().ok()
+}
+
 fn spawn_audio_stream(
-    tx: Sender<Frame>,
+    tx: queue::Sender<Frame>,
+    events: mpsc::Sender<CaptureEvent>,
     ready_tx: Sender<Result<(), CreateCapturerError>>,
     ctrl_rx: Receiver<AudioStreamControl>,
 ) {
@@ -414,6 +517,10 @@ fn spawn_audio_stream(
         }
 
         let audio_format = AudioFormat::from(config.sample_format());
+        let mut device_name = default_output_device_name();
+        let mut device_checked = Instant::now();
+        let mut first_capture: Option<StreamInstant> = None;
+        let mut sequence = 0;
 
         loop {
             match ctrl_rx.try_recv() {
@@ -422,17 +529,41 @@ fn spawn_audio_stream(
                 Err(_) => return,
             };
 
-            let (data, info, timestamp) = match sample_rx.recv_timeout(Duration::from_millis(100)) {
+            // cpal keeps capturing the device the stream was built for
+            if device_checked.elapsed() >= Duration::from_secs(1) {
+                device_checked = Instant::now();
+                let name = default_output_device_name();
+                if name != device_name {
+                    device_name = name;
+                    let _ = events.send(CaptureEvent::AudioDeviceChanged);
+                }
+            }
+
+            let (data, info) = match sample_rx.recv_timeout(Duration::from_millis(100)) {
                 Ok(Ok(data)) => data,
                 Err(RecvTimeoutError::Timeout) => {
                     continue;
                 }
-                _ => {
-                    // let _ = tx.send(Err(e));
+                Ok(Err(e)) => {
+                    let _ = events.send(CaptureEvent::StreamError(format!(
+                        "Audio stream failed: {e}"
+                    )));
+                    return;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    let _ = events.send(CaptureEvent::StreamError(
+                        "Audio stream stopped delivering samples".to_string(),
+                    ));
                     return;
                 }
             };
 
+            // Relative to the first buffer, as the clock of the stream can't be
+            // read directly
+            let capture = info.timestamp().capture;
+            let first_capture = *first_capture.get_or_insert(capture);
+            let timestamp = capture.duration_since(&first_capture).unwrap_or_default();
+
             let sample_count =
                 data.len() / (audio_format.sample_size() * config.channels() as usize);
             let frame = AudioFrame::new(
@@ -443,7 +574,9 @@ fn spawn_audio_stream(
                 sample_count,
                 config.sample_rate().0,
                 timestamp,
-            );
+            )
+            .with_sequence(sequence);
+            sequence += 1;
 
             if let Err(_) = tx.send(Frame::Audio(frame)) {
                 return;
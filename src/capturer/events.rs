@@ -0,0 +1,45 @@
+use crate::frame::PixelFormat;
+
+/// A change in the state of a capture, see
+/// [Capturer::events](super::Capturer::events)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CaptureEvent {
+    /// The backend started delivering frames
+    Started,
+    /// The source stopped sending frames for now, for example because the
+    /// captured window is minimized. Only reported on Linux.
+    Paused,
+    /// The frames the backend delivers changed format or size. Also sent
+    /// before the first frame.
+    FormatChanged {
+        format: PixelFormat,
+        width: u32,
+        height: u32,
+    },
+    /// The captured window or display went away
+    TargetClosed,
+    /// The user or the system ended the screen sharing session
+    SessionRevoked,
+    /// The stream failed
+    StreamError(String),
+    /// Something failed that the capture carries on without, such as
+    /// watching for the end of the session on Linux
+    Warning(String),
+    /// The default audio output device changed. Only reported on Windows,
+    /// where audio keeps being captured from the previous device.
+    AudioDeviceChanged,
+}
+
+impl CaptureEvent {
+    /// Whether no more frames follow the event until the capture is
+    /// started again
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            CaptureEvent::TargetClosed
+                | CaptureEvent::SessionRevoked
+                | CaptureEvent::StreamError(_)
+        )
+    }
+}
@@ -0,0 +1,110 @@
+use std::{fmt, time::Duration};
+
+/// A frame rate as a fraction of frames per second, so rates like 29.97 are
+/// exact. The denominator is never 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameRate {
+    num: u32,
+    den: u32,
+}
+
+impl FrameRate {
+    pub const NTSC_23_976: FrameRate = FrameRate::new(24_000, 1001);
+    pub const NTSC_29_97: FrameRate = FrameRate::new(30_000, 1001);
+    pub const NTSC_59_94: FrameRate = FrameRate::new(60_000, 1001);
+
+    /// `num / den` frames per second
+    pub const fn new(num: u32, den: u32) -> Self {
+        assert!(den > 0, "The denominator of a frame rate can't be 0");
+        FrameRate { num, den }
+    }
+
+    /// A whole number of frames per second
+    pub const fn from_fps(fps: u32) -> Self {
+        FrameRate::new(fps, 1)
+    }
+
+    /// Frames per `den()` seconds
+    pub const fn num(&self) -> u32 {
+        self.num
+    }
+
+    pub const fn den(&self) -> u32 {
+        self.den
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.num == 0
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Time between two frames, zero for a rate of 0
+    pub fn interval(&self) -> Duration {
+        match self.num {
+            0 => Duration::ZERO,
+            _ => self.time_of(1),
+        }
+    }
+
+    /// Time the frame with the `index` at this rate starts at. Rounded up to
+    /// whole nanoseconds, so it lies within the frame and rounding errors
+    /// don't add up.
+    pub(crate) fn time_of(&self, index: u64) -> Duration {
+        let nanos = (index as u128 * 1_000_000_000 * self.den as u128).div_ceil(self.num as u128);
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// Index of the frame at this rate that `time` falls into
+    pub(crate) fn index_at(&self, time: Duration) -> u64 {
+        (time.as_nanos() * self.num as u128 / (1_000_000_000 * self.den as u128)) as u64
+    }
+}
+
+/// A rate of 0, which leaves the rate to the backend
+impl Default for FrameRate {
+    fn default() -> Self {
+        FrameRate::from_fps(0)
+    }
+}
+
+impl From<u32> for FrameRate {
+    fn from(fps: u32) -> Self {
+        FrameRate::from_fps(fps)
+    }
+}
+
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.den {
+            1 => write!(f, "{}", self.num),
+            _ => write!(f, "{}/{}", self.num, self.den),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_rate() {
+        let rate = FrameRate::NTSC_29_97;
+        assert_eq!(rate.to_string(), "30000/1001");
+        assert_eq!(FrameRate::from(60).to_string(), "60");
+        assert!((rate.as_f64() - 29.97).abs() < 0.001);
+        assert_eq!(rate.interval(), Duration::from_nanos(33_366_667));
+        assert_eq!(FrameRate::default().interval(), Duration::ZERO);
+
+        // Rounding errors don't add up over many frames
+        assert_eq!(rate.time_of(30_000), Duration::from_secs(1001));
+        assert_eq!(rate.index_at(Duration::from_secs(1001)), 30_000);
+        assert_eq!(rate.index_at(rate.time_of(1234)), 1234);
+        assert_eq!(
+            rate.index_at(rate.time_of(1234) - Duration::from_nanos(1)),
+            1233
+        );
+    }
+}
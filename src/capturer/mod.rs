@@ -1,16 +1,30 @@
+mod clock;
 pub mod engine;
+mod events;
+mod frame_rate;
+mod pacing;
+mod queue;
+mod stats;
 
-use std::{error::Error, sync::mpsc};
+use std::{
+    cell::RefCell,
+    cmp,
+    error::Error,
+    sync::{mpsc, Arc},
+};
 
 use engine::ChannelItem;
 
 use crate::{
-    frame::{Frame, FrameType, VideoFrame},
+    frame::{Frame, FrameType, ScaleMode, VideoFrame},
     has_permission, is_supported,
     targets::Target,
 };
 
 pub use engine::get_output_frame_size;
+pub use events::CaptureEvent;
+pub use frame_rate::FrameRate;
+pub use stats::{CaptureStats, LatencyStats};
 
 #[derive(Debug, Clone, Copy, Default)]
 pub enum Resolution {
@@ -20,6 +34,15 @@ pub enum Resolution {
     _1440p,
     _2160p,
     _4320p,
+    /// Exact output size. The source is fitted into it according to
+    /// [Options::scale_mode].
+    Custom {
+        width: u32,
+        height: u32,
+    },
+    /// Limits the longer side of the frame to the given number of pixels,
+    /// keeping the aspect ratio
+    MaxDimension(u32),
 
     #[default]
     Captured,
@@ -34,11 +57,57 @@ impl Resolution {
             Resolution::_1440p => [2560, (2560_f32 / aspect_ratio).floor() as u32],
             Resolution::_2160p => [3840, (3840_f32 / aspect_ratio).floor() as u32],
             Resolution::_4320p => [7680, (7680_f32 / aspect_ratio).floor() as u32],
+            Resolution::Custom { width, height } => [width, height],
+            Resolution::MaxDimension(max) if aspect_ratio >= 1.0 => {
+                [max, (max as f32 / aspect_ratio).floor() as u32]
+            }
+            Resolution::MaxDimension(max) => [(max as f32 * aspect_ratio).floor() as u32, max],
             Resolution::Captured => {
                 panic!(".value should not be called when Resolution type is Captured")
             }
         }
     }
+
+    /// Size that frames of `width`x`height` pixels are delivered in.
+    ///
+    /// Custom sizes are used as is. Otherwise frames are never upscaled and
+    /// both dimensions are rounded down to even values, as most encoders
+    /// require.
+    pub(crate) fn output_size(&self, width: u32, height: u32) -> [u32; 2] {
+        let mut output_width = width;
+        let mut output_height = height;
+
+        match self {
+            Resolution::Captured => {}
+            Resolution::Custom { width, height } => return [*width, *height],
+            _ => {
+                let [resolved_width, resolved_height] =
+                    self.value((width as f32) / (height as f32));
+                output_width = cmp::min(output_width, resolved_width);
+                output_height = cmp::min(output_height, resolved_height);
+            }
+        }
+
+        output_width -= output_width % 2;
+        output_height -= output_height % 2;
+
+        [output_width, output_height]
+    }
+}
+
+/// What happens to a new frame when [Options::queue_depth] frames are already
+/// waiting to be received
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// Wait until a frame is received, which stalls capturing
+    #[default]
+    Block,
+    /// Drop the oldest waiting frame to make room
+    DropOldest,
+    /// Drop the new frame
+    DropNewest,
+    /// Only keep the most recent frame, whatever the queue depth
+    Latest,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -58,33 +127,95 @@ pub struct Area {
     pub size: Size,
 }
 
+impl Area {
+    // At least one pixel large, with finite coordinates that aren't negative
+    fn is_valid(&self) -> bool {
+        let values = [
+            self.origin.x,
+            self.origin.y,
+            self.size.width,
+            self.size.height,
+        ];
+        values.iter().all(|v| v.is_finite() && *v >= 0.0)
+            && self.size.width >= 1.0
+            && self.size.height >= 1.0
+    }
+}
+
+/// Coordinates an [Area] is given in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoordinateSpace {
+    /// Points, which are pixels divided by the scale factor of the display
+    #[default]
+    Logical,
+    /// Pixels of the frames the target is captured in
+    Physical,
+}
+
 /// Options passed to the screen capturer
 #[derive(Debug, Default, Clone)]
 pub struct Options {
-    pub fps: u32,
+    /// Highest rate to capture frames at. 0 leaves it to the backend.
+    pub fps: FrameRate,
+    /// Lower bound for the maximum rate negotiated with the compositor, which
+    /// may settle on any maximum between it and [Options::fps]. It is not a
+    /// minimum rate of frames: none are sent while nothing changes, see
+    /// [Options::constant_frame_rate] for that. Only used on Linux. The
+    /// maximum that was agreed on is reported in [CaptureStats::frame_rate].
+    pub min_fps: Option<FrameRate>,
+    /// Delivers video at exactly [Options::fps], as most muxers expect.
+    /// Compositors only send frames when something changes, so the last frame
+    /// is repeated when no new one arrives in time, see
+    /// [VideoFrame::is_repeated]. Frames arriving faster are dropped.
+    /// Timestamps are multiples of the frame interval.
+    pub constant_frame_rate: bool,
     pub show_cursor: bool,
     pub show_highlight: bool,
     pub target: Option<Target>,
+    /// Region of the target to capture, in [Options::crop_coordinates]. On
+    /// Linux and Windows no frames are delivered while it lies outside of the
+    /// target.
     pub crop_area: Option<Area>,
+    /// Coordinates [Options::crop_area] is given in. Only used on Linux.
+    pub crop_coordinates: CoordinateSpace,
     pub output_type: FrameType,
     pub output_resolution: Resolution,
+    /// How frames are fitted into a [Resolution::Custom] size with a
+    /// different aspect ratio
+    pub scale_mode: ScaleMode,
+    /// Keeps frames at the size of the first one when the source changes
+    /// size, for example because the captured window is resized. The new
+    /// content is fitted in according to [Options::scale_mode]. Without it
+    /// frames change size, which is announced by
+    /// [CaptureEvent::FormatChanged].
+    pub keep_output_size: bool,
     // excluded targets will only work on macOS
     pub excluded_targets: Option<Vec<Target>>,
     /// Only implemented for Windows and macOS currently
     pub captures_audio: bool,
     pub exclude_current_process_audio: bool,
+    /// Number of video frames that can wait to be received before
+    /// [Options::drop_policy] applies. 0 lets them pile up without limit.
+    /// Audio is never dropped.
+    pub queue_depth: usize,
+    pub drop_policy: DropPolicy,
 }
 
 /// Screen capturer class
 pub struct Capturer {
     engine: engine::Engine,
-    rx: mpsc::Receiver<ChannelItem>,
+    rx: queue::Receiver<ChannelItem>,
+    stats: Arc<stats::StatsRecorder>,
+    events: mpsc::Receiver<CaptureEvent>,
+    pacer: Option<RefCell<pacing::Pacer>>,
 }
 
 #[derive(Debug)]
 pub enum CapturerBuildError {
     NotSupported,
     PermissionNotGranted,
+    /// [Options::crop_area] is empty or has negative or non-finite values
+    InvalidCropArea(Area),
 }
 
 impl std::fmt::Display for CapturerBuildError {
@@ -94,6 +225,7 @@ impl std::fmt::Display for CapturerBuildError {
             CapturerBuildError::PermissionNotGranted => {
                 write!(f, "Permission to capture the screen is not granted")
             }
+            CapturerBuildError::InvalidCropArea(area) => write!(f, "Invalid crop area: {area:?}"),
         }
     }
 }
@@ -111,35 +243,143 @@ impl Capturer {
             return Err(CapturerBuildError::PermissionNotGranted);
         }
 
-        let (tx, rx) = mpsc::channel();
-        let engine = engine::Engine::new(&options, tx);
+        if let Some(area) = options.crop_area.as_ref().filter(|area| !area.is_valid()) {
+            return Err(CapturerBuildError::InvalidCropArea(area.clone()));
+        }
 
-        Ok(Capturer { engine, rx })
+        let (tx, rx) = queue::channel(options.queue_depth, options.drop_policy, engine::is_video);
+        let stats = Arc::new(stats::StatsRecorder::default());
+        let (events_tx, events) = mpsc::channel();
+        let engine = engine::Engine::new(&options, tx, stats.clone(), events_tx);
+        let pacer = (options.constant_frame_rate && !options.fps.is_zero())
+            .then(|| RefCell::new(pacing::Pacer::new(options.fps)));
+
+        Ok(Capturer {
+            engine,
+            rx,
+            stats,
+            events,
+            pacer,
+        })
     }
 
     // TODO
     // Prevent starting capture if already started
     /// Start capturing the frames
     pub fn start_capture(&mut self) {
+        if let Some(pacer) = &self.pacer {
+            pacer.borrow_mut().reset();
+        }
         self.engine.start();
     }
 
     /// Stop the capturer
     pub fn stop_capture(&mut self) {
+        // Frames are discarded while stopping, so a blocked backend can
+        // finish. On Linux they also point into buffers of the stream that
+        // ends.
+        self.rx.close();
         self.engine.stop();
+        self.rx.reopen();
     }
 
-    /// Get the next captured frame
+    /// Get the next captured frame. Fails once the stream ended, see
+    /// [CaptureEvent::is_terminal].
+    ///
+    /// The frame owns its pixels, so they are copied out of the buffers of
+    /// the backend, into those of frames passed to [Capturer::recycle] where
+    /// possible. [Capturer::raw] gives access to them without the copy on
+    /// macOS and Linux.
     pub fn get_next_frame(&self) -> Result<Frame, mpsc::RecvError> {
+        if let Some(pacer) = &self.pacer {
+            return self.get_next_paced_frame(pacer);
+        }
+
         loop {
-            let res = self.rx.recv()?;
+            let (sent, res) = self.rx.recv_timed()?;
 
-            if let Some(frame) = self.engine.process_channel_item(res) {
+            if let Some(frame) = self.engine.process_channel_item(res, sent) {
+                if let Frame::Video(_) = frame {
+                    self.stats.record_delivery(sent);
+                }
                 return Ok(frame);
             }
         }
     }
 
+    // Audio is passed through, video goes through the pacer
+    fn get_next_paced_frame(
+        &self,
+        pacer: &RefCell<pacing::Pacer>,
+    ) -> Result<Frame, mpsc::RecvError> {
+        loop {
+            let now = self.engine.elapsed();
+            if let Some((frame, sent)) = pacer.borrow_mut().pop(now) {
+                match frame.is_repeated() {
+                    true => self.stats.record_repeat(),
+                    false => self.stats.record_delivery(sent),
+                }
+                return Ok(Frame::Video(frame));
+            }
+
+            let deadline = pacer.borrow().deadline();
+            let (sent, res) = match deadline {
+                Some(deadline) => match self.rx.recv_timed_timeout(deadline.saturating_sub(now)) {
+                    Ok(item) => item,
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return Err(mpsc::RecvError),
+                },
+                None => self.rx.recv_timed()?,
+            };
+
+            match self.engine.process_channel_item(res, sent) {
+                Some(Frame::Video(frame)) => pacer.borrow_mut().push(frame, sent),
+                Some(frame) => return Ok(frame),
+                None => {}
+            }
+        }
+    }
+
+    /// Hands back a frame that is no longer needed, so that the following
+    /// frames are copied into its buffers instead of newly allocated ones
+    pub fn recycle(&self, frame: Frame) {
+        if let Frame::Video(frame) = frame {
+            self.engine.recycle(frame);
+        }
+    }
+
+    /// Frame types [Options::output_type] accepts on this platform. Those the
+    /// backend produces natively come first, the rest are converted in
+    /// software.
+    pub fn supported_frame_types() -> Vec<FrameType> {
+        engine::supported_frame_types()
+    }
+
+    /// Number of frames dropped because [Options::queue_depth] frames were
+    /// waiting to be received
+    pub fn dropped_frames(&self) -> u64 {
+        self.rx.dropped()
+    }
+
+    /// Current statistics of the capture
+    pub fn stats(&self) -> CaptureStats {
+        CaptureStats {
+            dropped_by_queue: self.rx.dropped(),
+            dropped_by_pacing: self
+                .pacer
+                .as_ref()
+                .map_or(0, |pacer| pacer.borrow().dropped()),
+            queue_depth: self.rx.queued(),
+            ..self.stats.snapshot()
+        }
+    }
+
+    /// Changes in the state of the capture, like the stream failing or the
+    /// captured window being closed
+    pub fn events(&self) -> &mpsc::Receiver<CaptureEvent> {
+        &self.events
+    }
+
     /// Get the dimensions the frames will be captured in
     pub fn get_output_frame_size(&mut self) -> [u32; 2] {
         self.engine.get_output_frame_size()
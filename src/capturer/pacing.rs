@@ -0,0 +1,244 @@
+use std::time::{Duration, Instant};
+
+use super::FrameRate;
+use crate::frame::VideoFrame;
+
+/// Turns the video frames of a capture into a constant frame rate stream, see
+/// [Options::constant_frame_rate](super::Options::constant_frame_rate).
+///
+/// Time is divided into slots of one frame interval since the capture
+/// started. Every slot gets the last frame captured before it ends, so frames
+/// beyond one per slot are dropped and slots without a new frame repeat the
+/// previous one.
+pub(crate) struct Pacer {
+    rate: FrameRate,
+    // Slot the next frame is emitted for
+    next_slot: u64,
+    // Frame shown in `next_slot` with the time it was sent, and whether it
+    // was emitted before
+    current: Option<(VideoFrame, Instant, bool)>,
+    // Frame of a later slot, waiting for the slots before it to be emitted
+    pending: Option<(VideoFrame, Instant, u64)>,
+    dropped: u64,
+}
+
+impl Pacer {
+    pub fn new(rate: FrameRate) -> Self {
+        assert!(!rate.is_zero(), "Pacing needs a frame rate");
+        Pacer {
+            rate,
+            next_slot: 0,
+            current: None,
+            pending: None,
+            dropped: 0,
+        }
+    }
+
+    /// Time since the capture started at which the next frame is due, if no
+    /// new frame arrives before. Frames get one interval past the end of
+    /// their slot to arrive.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.current.as_ref()?;
+        Some(self.rate.time_of(self.next_slot + 2))
+    }
+
+    /// Takes a frame the backend sent at `sent`
+    pub fn push(&mut self, mut frame: VideoFrame, sent: Instant) {
+        let slot = self.rate.index_at(frame.timestamp());
+        if let Some((replaced, _, pending_slot)) = self.pending.take() {
+            self.dropped += 1;
+            merge_dirty_rects(&mut frame, &replaced);
+            self.pending = Some((frame, sent, slot.max(pending_slot)));
+            return;
+        }
+
+        match &self.current {
+            None => {
+                self.next_slot = slot;
+                self.current = Some((frame, sent, false));
+            }
+            // Late frames still replace the content of the next slot
+            Some((replaced, _, emitted)) if slot <= self.next_slot => {
+                if !emitted {
+                    self.dropped += 1;
+                    merge_dirty_rects(&mut frame, replaced);
+                }
+                self.current = Some((frame, sent, false));
+            }
+            Some(_) => self.pending = Some((frame, sent, slot)),
+        }
+    }
+
+    /// Returns the next frame with the time it was sent if its slot ended,
+    /// `now` being the time since the capture started
+    pub fn pop(&mut self, now: Duration) -> Option<(VideoFrame, Instant)> {
+        let pending_slot = self.pending.as_ref().map(|(_, _, slot)| *slot);
+        if self.current.is_none()
+            || (pending_slot.is_none() && now < self.rate.time_of(self.next_slot + 2))
+        {
+            return None;
+        }
+
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        let (mut paced, sent, repeated) = match pending_slot == Some(self.next_slot) {
+            // The pending frame takes over, so this one doesn't have to be
+            // copied
+            true => {
+                let (frame, sent, _) = self.pending.take().unwrap();
+                self.current.replace((frame, sent, false)).unwrap()
+            }
+            false => {
+                let (frame, sent, emitted) = self.current.as_mut().unwrap();
+                (frame.clone(), *sent, std::mem::replace(emitted, true))
+            }
+        };
+        paced.set_repeated(repeated);
+        if repeated {
+            paced.set_dirty_rects(Some(Vec::new()));
+        }
+
+        let timestamp = self.rate.time_of(slot);
+        let display_time = match timestamp >= paced.timestamp() {
+            true => paced.display_time() + (timestamp - paced.timestamp()),
+            false => paced.display_time() - (paced.timestamp() - timestamp),
+        };
+        paced.set_time(timestamp, display_time);
+        Some((paced, sent))
+    }
+
+    /// Number of frames dropped because a later one arrived for the same slot
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Forgets all frames, for when the capture restarts
+    pub fn reset(&mut self) {
+        *self = Pacer {
+            dropped: self.dropped,
+            ..Pacer::new(self.rate)
+        };
+    }
+}
+
+// Adds the regions that changed in `replaced`, which is never delivered, to
+// those of the frame following it. They only add up for frames of one size.
+fn merge_dirty_rects(frame: &mut VideoFrame, replaced: &VideoFrame) {
+    let dirty_rects = match (replaced.dirty_rects(), frame.dirty_rects()) {
+        (Some(replaced_rects), Some(rects)) if replaced.size() == frame.size() => {
+            Some([replaced_rects, rects].concat())
+        }
+        _ => None,
+    };
+    frame.set_dirty_rects(dirty_rects);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{DirtyRect, PixelFormat};
+    use std::time::SystemTime;
+
+    fn frame(millis: u64) -> VideoFrame {
+        let timestamp = Duration::from_millis(millis);
+        let mut frame =
+            VideoFrame::packed(PixelFormat::BGRA, 1, 1, vec![0; 4]).with_sequence(millis);
+        frame.set_time(timestamp, SystemTime::UNIX_EPOCH + timestamp);
+        frame
+    }
+
+    fn drain(pacer: &mut Pacer, now: u64) -> Vec<(u64, u64, bool)> {
+        std::iter::from_fn(|| pacer.pop(Duration::from_millis(now)))
+            .map(|(frame, _)| {
+                assert_eq!(
+                    frame.display_time(),
+                    SystemTime::UNIX_EPOCH + frame.timestamp()
+                );
+                (
+                    frame.timestamp().as_millis() as u64,
+                    frame.sequence(),
+                    frame.is_repeated(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pacing() {
+        // Slots of 100 ms
+        let mut pacer = Pacer::new(FrameRate::from_fps(10));
+        assert_eq!(pacer.deadline(), None);
+        assert!(pacer.pop(Duration::from_secs(1)).is_none());
+
+        pacer.push(frame(120), Instant::now());
+        assert_eq!(pacer.deadline(), Some(Duration::from_millis(300)));
+        assert!(drain(&mut pacer, 250).is_empty());
+
+        // Only the last frame of a slot is kept
+        pacer.push(frame(150), Instant::now());
+        pacer.push(frame(190), Instant::now());
+        pacer.push(frame(210), Instant::now());
+        assert_eq!(drain(&mut pacer, 250), vec![(100, 190, false)]);
+        assert_eq!(pacer.dropped(), 2);
+
+        // Slots without a frame repeat the previous one
+        assert_eq!(
+            drain(&mut pacer, 520),
+            vec![(200, 210, false), (300, 210, true)]
+        );
+        pacer.push(frame(530), Instant::now());
+        assert_eq!(drain(&mut pacer, 530), vec![(400, 210, true)]);
+        assert_eq!(drain(&mut pacer, 700), vec![(500, 530, false)]);
+
+        // A frame arriving after its slot was filled shows in the next one
+        assert!(drain(&mut pacer, 799).is_empty());
+        assert_eq!(drain(&mut pacer, 800), vec![(600, 530, true)]);
+        pacer.push(frame(650), Instant::now());
+        assert_eq!(drain(&mut pacer, 900), vec![(700, 650, false)]);
+        assert_eq!(pacer.dropped(), 2);
+
+        pacer.reset();
+        assert_eq!(pacer.deadline(), None);
+        assert_eq!(pacer.dropped(), 2);
+    }
+
+    #[test]
+    fn test_pacing_dirty_rects() {
+        let rect = |x| DirtyRect {
+            x,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        let damaged = |millis, rects: Option<Vec<DirtyRect>>| {
+            let mut frame = frame(millis);
+            frame.set_dirty_rects(rects);
+            frame
+        };
+
+        let mut pacer = Pacer::new(FrameRate::from_fps(10));
+        pacer.push(damaged(120, Some(vec![rect(0)])), Instant::now());
+        pacer.push(damaged(150, Some(vec![rect(1)])), Instant::now());
+        pacer.push(damaged(210, Some(vec![rect(2)])), Instant::now());
+        // The pending frame replaced by a later one
+        pacer.push(damaged(220, Some(vec![rect(3)])), Instant::now());
+
+        let rects: Vec<_> = std::iter::from_fn(|| pacer.pop(Duration::from_millis(500)))
+            .map(|(frame, _)| frame.dirty_rects().map(<[DirtyRect]>::to_vec))
+            .collect();
+        assert_eq!(
+            rects,
+            vec![
+                Some(vec![rect(0), rect(1)]),
+                Some(vec![rect(2), rect(3)]),
+                Some(vec![]),
+            ]
+        );
+
+        // Unknown damage stays unknown
+        pacer.push(damaged(550, None), Instant::now());
+        pacer.push(damaged(560, Some(vec![rect(4)])), Instant::now());
+        let last = std::iter::from_fn(|| pacer.pop(Duration::from_millis(700))).last();
+        assert_eq!(last.unwrap().0.dirty_rects(), None);
+    }
+}
@@ -0,0 +1,377 @@
+//! Channel between the backends and [Capturer](super::Capturer) that holds a
+//! limited number of video frames, mirroring the `std::sync::mpsc` API
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{
+        mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use super::DropPolicy;
+
+struct State<T> {
+    // Items with the time they were sent
+    items: VecDeque<(Instant, T)>,
+    // Number of droppable items in `items`
+    queued: usize,
+    senders: usize,
+    receiver_alive: bool,
+    // Set while the capturer stops, new items are discarded
+    closed: bool,
+    // Set when the stream ended while senders are still around
+    ended: bool,
+    dropped: u64,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    // Signalled when an item is queued or the last sender is gone
+    available: Condvar,
+    // Signalled when an item is taken or the queue stops accepting items
+    space: Condvar,
+    // Maximum number of droppable items, 0 for no limit
+    depth: usize,
+    policy: DropPolicy,
+    // Only video frames count towards the depth, audio is always queued
+    droppable: fn(&T) -> bool,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// Creates a queue that holds up to `depth` droppable items before `policy`
+/// applies
+pub(crate) fn channel<T>(
+    depth: usize,
+    policy: DropPolicy,
+    droppable: fn(&T) -> bool,
+) -> (Sender<T>, Receiver<T>) {
+    let depth = match policy {
+        DropPolicy::Latest => 1,
+        _ => depth,
+    };
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            items: VecDeque::new(),
+            queued: 0,
+            senders: 1,
+            receiver_alive: true,
+            closed: false,
+            ended: false,
+            dropped: 0,
+        }),
+        available: Condvar::new(),
+        space: Condvar::new(),
+        depth,
+        policy,
+        droppable,
+    });
+
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues `item`, which may drop it or an older item depending on the
+    /// policy. Fails only when the receiver is gone.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let shared = &*self.shared;
+        let droppable = (shared.droppable)(&item);
+        let mut state = shared.lock();
+
+        loop {
+            if !state.receiver_alive {
+                return Err(SendError(item));
+            }
+            if state.closed {
+                return Ok(());
+            }
+            if !droppable || shared.depth == 0 || state.queued < shared.depth {
+                break;
+            }
+
+            match shared.policy {
+                DropPolicy::Block => state = shared.space.wait(state).unwrap(),
+                DropPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return Ok(());
+                }
+                DropPolicy::DropOldest | DropPolicy::Latest => {
+                    let oldest = state
+                        .items
+                        .iter()
+                        .position(|(_, item)| (shared.droppable)(item))
+                        .unwrap();
+                    state.items.remove(oldest);
+                    state.queued -= 1;
+                    state.dropped += 1;
+                }
+            }
+        }
+
+        state.items.push_back((Instant::now(), item));
+        state.queued += droppable as usize;
+        shared.available.notify_one();
+        Ok(())
+    }
+
+    /// Calls `f` on every item waiting to be received, which must not change
+    /// whether the item is droppable
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn update_queued(&self, f: impl FnMut(&mut T)) {
+        let mut state = self.shared.lock();
+        state.items.iter_mut().map(|(_, item)| item).for_each(f);
+    }
+
+    /// Lets the receiver fail like all senders were dropped once the queued
+    /// items are taken, for when the stream ended
+    pub fn end(&self) {
+        self.shared.lock().ended = true;
+        self.shared.available.notify_all();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.available.notify_all();
+        }
+    }
+}
+
+impl<T> fmt::Debug for Sender<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sender").finish_non_exhaustive()
+    }
+}
+
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    fn take(&self, state: &mut State<T>) -> Option<(Instant, T)> {
+        let (sent, item) = state.items.pop_front()?;
+        if (self.shared.droppable)(&item) {
+            state.queued -= 1;
+            self.shared.space.notify_one();
+        }
+        Some((sent, item))
+    }
+
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_timed().map(|(_, item)| item)
+    }
+
+    /// Like [Receiver::recv], also returning when the item was sent
+    pub fn recv_timed(&self) -> Result<(Instant, T), RecvError> {
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.take(&mut state) {
+                return Ok(item);
+            }
+            if state.senders == 0 || state.ended {
+                return Err(RecvError);
+            }
+            state = self.shared.available.wait(state).unwrap();
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_timed_timeout(timeout).map(|(_, item)| item)
+    }
+
+    /// Like [Receiver::recv_timeout], also returning when the item was sent
+    pub fn recv_timed_timeout(&self, timeout: Duration) -> Result<(Instant, T), RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(item) = self.take(&mut state) {
+                return Ok(item);
+            }
+            if state.senders == 0 || state.ended {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self
+                .shared
+                .available
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock();
+        match self.take(&mut state) {
+            Some((_, item)) => Ok(item),
+            None if state.senders == 0 || state.ended => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Number of droppable items waiting to be received
+    pub fn queued(&self) -> usize {
+        self.shared.lock().queued
+    }
+
+    /// Number of items dropped so far because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.shared.lock().dropped
+    }
+
+    /// Discards queued and new items until [Receiver::reopen], so blocked
+    /// senders can finish while the capturer stops
+    pub fn close(&self) {
+        let mut state = self.shared.lock();
+        state.closed = true;
+        state.items.clear();
+        state.queued = 0;
+        self.shared.space.notify_all();
+    }
+
+    pub fn reopen(&self) {
+        let mut state = self.shared.lock();
+        state.closed = false;
+        state.ended = false;
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_alive = false;
+        self.shared.space.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // Odd numbers stand in for audio, which is never dropped
+    fn is_even(item: &u32) -> bool {
+        item & 1 == 0
+    }
+
+    fn drain(rx: &Receiver<u32>) -> Vec<u32> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn test_drop_policies() {
+        let (tx, rx) = channel(2, DropPolicy::DropOldest, is_even);
+        for item in [0, 1, 2, 4, 3, 6] {
+            tx.send(item).unwrap();
+        }
+        assert_eq!(drain(&rx), vec![1, 4, 3, 6]);
+        assert_eq!(rx.dropped(), 2);
+
+        let (tx, rx) = channel(2, DropPolicy::DropNewest, is_even);
+        for item in [0, 1, 2, 4, 3, 6] {
+            tx.send(item).unwrap();
+        }
+        assert_eq!(drain(&rx), vec![0, 1, 2, 3]);
+        assert_eq!(rx.dropped(), 2);
+
+        // The depth is ignored when only the latest frame is kept
+        let (tx, rx) = channel(8, DropPolicy::Latest, is_even);
+        for item in [0, 2, 1, 4] {
+            tx.send(item).unwrap();
+        }
+        assert_eq!(drain(&rx), vec![1, 4]);
+        assert_eq!(rx.dropped(), 2);
+
+        let (tx, rx) = channel(0, DropPolicy::DropNewest, is_even);
+        for item in 0..100 {
+            tx.send(item).unwrap();
+        }
+        assert_eq!(drain(&rx).len(), 100);
+        assert_eq!(rx.dropped(), 0);
+    }
+
+    #[test]
+    fn test_block() {
+        let (tx, rx) = channel(1, DropPolicy::Block, is_even);
+        let producer = thread::spawn(move || {
+            for item in [0, 2, 4] {
+                tx.send(item).unwrap();
+            }
+        });
+
+        let received: Vec<_> = std::iter::from_fn(|| rx.recv().ok()).collect();
+        producer.join().unwrap();
+        assert_eq!(received, vec![0, 2, 4]);
+        assert_eq!(rx.dropped(), 0);
+    }
+
+    #[test]
+    fn test_close() {
+        let (tx, rx) = channel(1, DropPolicy::Block, is_even);
+        tx.send(0).unwrap();
+        let producer = thread::spawn(move || tx.send(2));
+
+        // A blocked sender gives up once the queue is closed
+        thread::sleep(Duration::from_millis(10));
+        rx.close();
+        producer.join().unwrap().unwrap();
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = channel(1, DropPolicy::Block, is_even);
+        drop(rx);
+        assert!(tx.send(0).is_err());
+    }
+
+    #[test]
+    fn test_end() {
+        let (tx, rx) = channel(0, DropPolicy::Block, is_even);
+        tx.send(0).unwrap();
+        tx.end();
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(rx.recv(), Err(RecvError));
+
+        rx.reopen();
+        tx.send(2).unwrap();
+        assert_eq!(rx.recv(), Ok(2));
+    }
+
+    #[test]
+    fn test_update_queued() {
+        let (tx, rx) = channel(0, DropPolicy::Block, is_even);
+        for item in [0, 1, 2] {
+            tx.send(item).unwrap();
+        }
+        tx.update_queued(|item| *item += 10);
+        assert_eq!(drain(&rx), vec![10, 11, 12]);
+    }
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::FrameRate;
+use crate::frame::PixelFormat;
+
+// Number of recent frames the latency percentiles are taken over
+const LATENCY_SAMPLES: usize = 120;
+// Window the frame rate is measured over
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// How a capture is going, see [Capturer::stats](super::Capturer::stats)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CaptureStats {
+    /// Video frames returned by
+    /// [Capturer::get_next_frame](super::Capturer::get_next_frame)
+    pub frames_delivered: u64,
+    /// Video frames delivered again because no new frame arrived in time, see
+    /// [Options::constant_frame_rate](super::Options::constant_frame_rate)
+    pub frames_repeated: u64,
+    /// Video frames dropped because the consumer fell behind, see
+    /// [Options::drop_policy](super::Options::drop_policy)
+    pub dropped_by_queue: u64,
+    /// Video frames dropped because they arrived faster than
+    /// [Options::fps](super::Options::fps), with
+    /// [Options::constant_frame_rate](super::Options::constant_frame_rate)
+    pub dropped_by_pacing: u64,
+    /// Video frames the backend delivered in a state that could not be read.
+    /// Not detected on macOS.
+    pub dropped_corrupt: u64,
+    /// Times PipeWire had no buffer to hand out, which means frames were
+    /// skipped. Always 0 on other platforms.
+    pub dropped_out_of_buffers: u64,
+    /// Frames delivered during the last second
+    pub fps: f64,
+    /// Time from the backend handing a frame over to its delivery, including
+    /// waiting in the queue and any conversion, over the most recent frames
+    pub latency: LatencyStats,
+    /// Format of the frames the backend delivers, before any conversion
+    pub native_format: Option<PixelFormat>,
+    /// Size of the frames the backend delivers, before any scaling
+    pub native_size: Option<(u32, u32)>,
+    /// Highest rate the backend agreed to deliver frames at. Negotiated with
+    /// the compositor on Linux, [Options::fps](super::Options::fps) elsewhere.
+    pub frame_rate: Option<FrameRate>,
+    /// Video frames currently waiting to be received
+    pub queue_depth: usize,
+}
+
+/// Percentiles of the capture to delivery latency
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Recorded {
+    frames_delivered: u64,
+    frames_repeated: u64,
+    dropped_corrupt: u64,
+    dropped_out_of_buffers: u64,
+    // Delivery times within the last FPS_WINDOW
+    deliveries: VecDeque<Instant>,
+    latencies: VecDeque<Duration>,
+    native_format: Option<PixelFormat>,
+    native_size: Option<(u32, u32)>,
+    frame_rate: Option<FrameRate>,
+}
+
+impl Recorded {
+    // Forgets the deliveries that fell out of FPS_WINDOW
+    fn trim_deliveries(&mut self, now: Instant) {
+        while self
+            .deliveries
+            .front()
+            .is_some_and(|delivery| now.saturating_duration_since(*delivery) >= FPS_WINDOW)
+        {
+            self.deliveries.pop_front();
+        }
+    }
+}
+
+/// Collects statistics from the backend and the consumer side of a capture
+#[derive(Debug, Default)]
+pub(crate) struct StatsRecorder {
+    recorded: Mutex<Recorded>,
+}
+
+impl StatsRecorder {
+    #[cfg_attr(target_os = "macos", allow(dead_code))]
+    pub fn record_corrupt(&self) {
+        self.recorded.lock().unwrap().dropped_corrupt += 1;
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn record_out_of_buffers(&self) {
+        self.recorded.lock().unwrap().dropped_out_of_buffers += 1;
+    }
+
+    /// Records the format of a frame as the backend delivered it, returning
+    /// whether it differs from the previous frame
+    pub fn record_native(&self, format: PixelFormat, width: u32, height: u32) -> bool {
+        let mut recorded = self.recorded.lock().unwrap();
+        let format_changed = recorded.native_format.replace(format) != Some(format);
+        let size_changed = recorded.native_size.replace((width, height)) != Some((width, height));
+        format_changed || size_changed
+    }
+
+    pub fn record_frame_rate(&self, frame_rate: FrameRate) {
+        self.recorded.lock().unwrap().frame_rate = Some(frame_rate);
+    }
+
+    /// Records the delivery of a frame the backend handed over at `sent`
+    pub fn record_delivery(&self, sent: Instant) {
+        self.record_delivery_at(sent, Instant::now());
+    }
+
+    fn record_delivery_at(&self, sent: Instant, now: Instant) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.frames_delivered += 1;
+        recorded.deliveries.push_back(now);
+        recorded.trim_deliveries(now);
+        if recorded.latencies.len() == LATENCY_SAMPLES {
+            recorded.latencies.pop_front();
+        }
+        recorded
+            .latencies
+            .push_back(now.saturating_duration_since(sent));
+    }
+
+    /// Records the delivery of a repeated frame, which adds no latency
+    pub fn record_repeat(&self) {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.frames_delivered += 1;
+        recorded.frames_repeated += 1;
+        let now = Instant::now();
+        recorded.deliveries.push_back(now);
+        recorded.trim_deliveries(now);
+    }
+
+    pub fn snapshot(&self) -> CaptureStats {
+        self.snapshot_at(Instant::now())
+    }
+
+    fn snapshot_at(&self, now: Instant) -> CaptureStats {
+        let mut recorded = self.recorded.lock().unwrap();
+        recorded.trim_deliveries(now);
+
+        let mut latencies: Vec<_> = recorded.latencies.iter().copied().collect();
+        latencies.sort_unstable();
+        let percentile = |percent: usize| match latencies.len() {
+            0 => Duration::ZERO,
+            len => latencies[(len - 1) * percent / 100],
+        };
+
+        CaptureStats {
+            frames_delivered: recorded.frames_delivered,
+            frames_repeated: recorded.frames_repeated,
+            dropped_corrupt: recorded.dropped_corrupt,
+            dropped_out_of_buffers: recorded.dropped_out_of_buffers,
+            fps: recorded.deliveries.len() as f64 / FPS_WINDOW.as_secs_f64(),
+            latency: LatencyStats {
+                p50: percentile(50),
+                p90: percentile(90),
+                p99: percentile(99),
+                max: percentile(100),
+            },
+            native_format: recorded.native_format,
+            native_size: recorded.native_size,
+            frame_rate: recorded.frame_rate,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let recorder = StatsRecorder::default();
+        assert_eq!(recorder.snapshot(), CaptureStats::default());
+
+        // 30 frames at 30 fps with 1 to 30 ms of latency
+        let start = Instant::now();
+        for frame in 0..30u32 {
+            let now = start + Duration::from_millis(frame as u64 * 1000 / 30 + 100);
+            recorder.record_delivery_at(now - Duration::from_millis(frame as u64 + 1), now);
+        }
+        assert!(recorder.record_native(PixelFormat::BGRA, 1920, 1080));
+        assert!(!recorder.record_native(PixelFormat::BGRA, 1920, 1080));
+        recorder.record_corrupt();
+        recorder.record_frame_rate(FrameRate::NTSC_59_94);
+
+        let stats = recorder.snapshot_at(start + Duration::from_millis(1000));
+        assert_eq!(stats.frames_delivered, 30);
+        assert_eq!(stats.fps, 30.0);
+        assert_eq!(stats.latency.p50, Duration::from_millis(15));
+        assert_eq!(stats.latency.max, Duration::from_millis(30));
+        assert_eq!(stats.native_format, Some(PixelFormat::BGRA));
+        assert_eq!(stats.native_size, Some((1920, 1080)));
+        assert_eq!(stats.dropped_corrupt, 1);
+        assert_eq!(stats.frame_rate, Some(FrameRate::NTSC_59_94));
+
+        // Only the frames of the last second count towards the frame rate
+        let stats = recorder.snapshot_at(start + Duration::from_millis(1600));
+        assert_eq!(stats.fps, 14.0);
+        assert_eq!(stats.frames_delivered, 30);
+
+        // Deliveries are forgotten without taking snapshots too
+        for frame in 0..100u64 {
+            let now = start + Duration::from_millis(2000 + frame * 100);
+            recorder.record_delivery_at(now, now);
+        }
+        assert_eq!(recorder.recorded.lock().unwrap().deliveries.len(), 10);
+    }
+}
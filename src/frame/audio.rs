@@ -1,4 +1,7 @@
-use std::{alloc::System, time::SystemTime};
+use std::{
+    alloc::System,
+    time::{Duration, SystemTime},
+};
 
 pub struct AudioFrame {
     format: AudioFormat,
@@ -7,7 +10,9 @@ pub struct AudioFrame {
     data: Vec<u8>,
     sample_count: usize,
     rate: u32,
-    timestamp: SystemTime,
+    display_time: SystemTime,
+    timestamp: Duration,
+    sequence: u64,
 }
 
 impl AudioFrame {
@@ -18,7 +23,7 @@ impl AudioFrame {
         data: Vec<u8>,
         sample_count: usize,
         rate: u32,
-        timestamp: SystemTime,
+        timestamp: Duration,
     ) -> Self {
         assert!(data.len() >= sample_count * format.sample_size() as usize * channels as usize);
 
@@ -29,10 +34,19 @@ impl AudioFrame {
             data,
             sample_count,
             rate,
+            // Replaced by the capturer with the times on its clock
+            display_time: SystemTime::UNIX_EPOCH + timestamp,
             timestamp,
+            sequence: 0,
         }
     }
 
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    pub(crate) fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
     pub fn format(&self) -> AudioFormat {
         self.format
     }
@@ -65,10 +79,28 @@ impl AudioFrame {
         self.sample_count
     }
 
+    /// Wall clock time the first sample was captured at
     pub fn time(&self) -> SystemTime {
+        self.display_time
+    }
+
+    /// Time since the capture started, on a monotonic clock shared with the
+    /// video frames of the capture
+    pub fn timestamp(&self) -> Duration {
         self.timestamp
     }
 
+    /// Number of the frame in the audio of the capture, which goes up by one
+    /// for every buffer the source produced
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub(crate) fn set_time(&mut self, timestamp: Duration, display_time: SystemTime) {
+        self.timestamp = timestamp;
+        self.display_time = display_time;
+    }
+
     pub fn plane_data(&self, plane: usize) -> &[u8] {
         if !self.is_planar {
             return &self.data;
@@ -81,7 +113,7 @@ impl AudioFrame {
 }
 
 #[non_exhaustive]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AudioFormat {
     I8,
     I16,
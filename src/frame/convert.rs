@@ -0,0 +1,1185 @@
+use super::{
+    crop_packed, simd, BGRAFrame, BGRFrame, BGRxFrame, I420Frame, P010Frame, PixelFormat,
+    RGBAFrame, RGBFrame, RGBxFrame, ToneMapping, VideoFrame, XBGRFrame, YUVFrame,
+};
+
+/// Matrix used to convert between RGB and YUV
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMatrix {
+    /// ITU-R BT.601, used for SD content
+    Bt601,
+    /// ITU-R BT.709, used for HD content
+    #[default]
+    Bt709,
+    /// ITU-R BT.2020 non-constant luminance, used for UHD and HDR content
+    Bt2020,
+}
+
+impl ColorMatrix {
+    // Red and blue luma coefficients (Kr, Kb)
+    pub(crate) fn coefficients(self) -> (f64, f64) {
+        match self {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+            ColorMatrix::Bt2020 => (0.2627, 0.0593),
+        }
+    }
+}
+
+/// Range of the values in a YUV frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorRange {
+    /// Luma in 16..=235 and chroma in 16..=240, as most encoders expect
+    #[default]
+    Limited,
+    /// Luma and chroma use the full 0..=255 range
+    Full,
+}
+
+impl ColorRange {
+    // Luma offset and the luma and chroma scales relative to full range
+    fn scales(self) -> (i32, f64, f64) {
+        match self {
+            ColorRange::Limited => (16, 219.0 / 255.0, 224.0 / 255.0),
+            ColorRange::Full => (0, 1.0, 1.0),
+        }
+    }
+}
+
+/// Chromaticities of the red, green and blue primaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorPrimaries {
+    /// ITU-R BT.709, shared with sRGB
+    #[default]
+    Bt709,
+    /// Display P3, used by most wide gamut displays
+    DisplayP3,
+    /// ITU-R BT.2020, used for HDR content
+    Bt2020,
+}
+
+/// How the RGB values of a frame relate to light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransferFunction {
+    /// The sRGB curve, with 1.0 as SDR white
+    #[default]
+    Srgb,
+    /// Linear light, with 1.0 as 80 nits as in scRGB
+    Linear,
+    /// SMPTE ST 2084 perceptual quantizer, covering up to 10000 nits
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma
+    Hlg,
+}
+
+/// Color description of a frame: how YUV values relate to RGB and how RGB
+/// values relate to light
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Colorimetry {
+    pub matrix: ColorMatrix,
+    pub range: ColorRange,
+    pub primaries: ColorPrimaries,
+    pub transfer: TransferFunction,
+}
+
+/// Byte order of a packed RGB buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackedFormat {
+    BGRA,
+    RGBA,
+    BGRx,
+    RGBx,
+    XBGR,
+    RGB,
+    BGR,
+}
+
+impl PackedFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PackedFormat::RGB | PackedFormat::BGR => 3,
+            _ => 4,
+        }
+    }
+
+    // Offsets of the red, green and blue bytes within a pixel
+    pub(crate) fn channels(self) -> [usize; 3] {
+        match self {
+            PackedFormat::BGRA | PackedFormat::BGRx | PackedFormat::BGR => [2, 1, 0],
+            PackedFormat::RGBA | PackedFormat::RGBx | PackedFormat::RGB => [0, 1, 2],
+            PackedFormat::XBGR => [3, 2, 1],
+        }
+    }
+
+    // Offset of the alpha or padding byte, which is written as opaque
+    pub(crate) fn filler(self) -> Option<usize> {
+        match self {
+            PackedFormat::BGRA | PackedFormat::RGBA | PackedFormat::BGRx | PackedFormat::RGBx => {
+                Some(3)
+            }
+            PackedFormat::XBGR => Some(0),
+            PackedFormat::RGB | PackedFormat::BGR => None,
+        }
+    }
+}
+
+/// A plane of image data with `stride` bytes per row
+#[derive(Debug, Clone, Copy)]
+pub struct Plane<'a> {
+    pub data: &'a [u8],
+    pub stride: usize,
+}
+
+/// A writable plane of image data with `stride` bytes per row
+#[derive(Debug)]
+pub struct PlaneMut<'a> {
+    pub data: &'a mut [u8],
+    pub stride: usize,
+}
+
+// Fractional bits of the fixed point coefficients. They are kept small
+// enough for every coefficient to fit in an `i16`, as the SSE2 kernels need.
+pub(crate) const RGB_SHIFT: u32 = 15;
+pub(crate) const YUV_SHIFT: u32 = 13;
+
+fn fixed(value: f64, shift: u32) -> i32 {
+    (value * (1 << shift) as f64).round() as i32
+}
+
+fn clamp_u8(value: i32) -> u8 {
+    value.clamp(0, 255) as u8
+}
+
+fn assert_plane(len: usize, stride: usize, row_size: usize, rows: usize, name: &str) {
+    assert!(stride >= row_size, "{name} stride is smaller than a row");
+    assert!(
+        rows == 0 || len >= stride * (rows - 1) + row_size,
+        "{name} plane is too small"
+    );
+}
+
+// Fixed point rows of the RGB to YUV matrix, scaled to the target range
+pub(crate) struct RgbToYuv {
+    pub(crate) y: [i32; 3],
+    pub(crate) u: [i32; 3],
+    pub(crate) v: [i32; 3],
+    pub(crate) y_offset: i32,
+}
+
+impl RgbToYuv {
+    fn new(colorimetry: Colorimetry) -> Self {
+        let (kr, kb) = colorimetry.matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        let (y_offset, y_scale, c_scale) = colorimetry.range.scales();
+        let cb = c_scale / (2.0 * (1.0 - kb));
+        let cr = c_scale / (2.0 * (1.0 - kr));
+
+        RgbToYuv {
+            y: [
+                fixed(kr * y_scale, RGB_SHIFT),
+                fixed(kg * y_scale, RGB_SHIFT),
+                fixed(kb * y_scale, RGB_SHIFT),
+            ],
+            u: [
+                fixed(-kr * cb, RGB_SHIFT),
+                fixed(-kg * cb, RGB_SHIFT),
+                fixed((1.0 - kb) * cb, RGB_SHIFT),
+            ],
+            v: [
+                fixed((1.0 - kr) * cr, RGB_SHIFT),
+                fixed(-kg * cr, RGB_SHIFT),
+                fixed(-kb * cr, RGB_SHIFT),
+            ],
+            y_offset,
+        }
+    }
+
+    pub(crate) fn luma(&self, [r, g, b]: [i32; 3]) -> u8 {
+        let y = self.y[0] * r + self.y[1] * g + self.y[2] * b;
+        clamp_u8(((y + (1 << (RGB_SHIFT - 1))) >> RGB_SHIFT) + self.y_offset)
+    }
+
+    // Takes the sum of four RGB samples
+    pub(crate) fn chroma(&self, [r, g, b]: [i32; 3]) -> (u8, u8) {
+        let shift = RGB_SHIFT + 2;
+        let round = 1 << (shift - 1);
+        let u = self.u[0] * r + self.u[1] * g + self.u[2] * b;
+        let v = self.v[0] * r + self.v[1] * g + self.v[2] * b;
+        (
+            clamp_u8(((u + round) >> shift) + 128),
+            clamp_u8(((v + round) >> shift) + 128),
+        )
+    }
+}
+
+// Fixed point YUV to RGB matrix, including the range expansion
+pub(crate) struct YuvToRgb {
+    pub(crate) y: i32,
+    pub(crate) rv: i32,
+    pub(crate) gu: i32,
+    pub(crate) gv: i32,
+    pub(crate) bu: i32,
+    pub(crate) y_offset: i32,
+}
+
+impl YuvToRgb {
+    fn new(colorimetry: Colorimetry) -> Self {
+        let (kr, kb) = colorimetry.matrix.coefficients();
+        let kg = 1.0 - kr - kb;
+        let (y_offset, y_scale, c_scale) = colorimetry.range.scales();
+
+        YuvToRgb {
+            y: fixed(1.0 / y_scale, YUV_SHIFT),
+            rv: fixed(2.0 * (1.0 - kr) / c_scale, YUV_SHIFT),
+            gu: fixed(-2.0 * kb * (1.0 - kb) / kg / c_scale, YUV_SHIFT),
+            gv: fixed(-2.0 * kr * (1.0 - kr) / kg / c_scale, YUV_SHIFT),
+            bu: fixed(2.0 * (1.0 - kb) / c_scale, YUV_SHIFT),
+            y_offset,
+        }
+    }
+
+    pub(crate) fn rgb(&self, y: u8, u: u8, v: u8) -> [u8; 3] {
+        let round = 1 << (YUV_SHIFT - 1);
+        let y = self.y * (y as i32 - self.y_offset) + round;
+        let u = u as i32 - 128;
+        let v = v as i32 - 128;
+        [
+            clamp_u8((y + self.rv * v) >> YUV_SHIFT),
+            clamp_u8((y + self.gu * u + self.gv * v) >> YUV_SHIFT),
+            clamp_u8((y + self.bu * u) >> YUV_SHIFT),
+        ]
+    }
+}
+
+/// Converts between packed RGB formats, setting any alpha or padding byte
+/// to opaque
+pub fn convert_packed(
+    src: Plane,
+    src_format: PackedFormat,
+    dst: PlaneMut,
+    dst_format: PackedFormat,
+    (width, height): (usize, usize),
+) {
+    let src_bpp = src_format.bytes_per_pixel();
+    let dst_bpp = dst_format.bytes_per_pixel();
+    assert_plane(
+        src.data.len(),
+        src.stride,
+        width * src_bpp,
+        height,
+        "Source",
+    );
+    assert_plane(
+        dst.data.len(),
+        dst.stride,
+        width * dst_bpp,
+        height,
+        "Destination",
+    );
+
+    let src_channels = src_format.channels();
+    let dst_channels = dst_format.channels();
+    let filler = dst_format.filler();
+
+    // Source byte of every destination byte
+    let mut order = [0; 4];
+    for (&s, &d) in src_channels.iter().zip(&dst_channels) {
+        order[d] = s;
+    }
+
+    for y in 0..height {
+        let src_row = &src.data[y * src.stride..][..width * src_bpp];
+        let dst_row = &mut dst.data[y * dst.stride..][..width * dst_bpp];
+        match (src_bpp, dst_bpp) {
+            (4, 4) => simd::swizzle(src_row, dst_row, order, filler),
+            (4, 3) => simd::pack(src_row, dst_row, [order[0], order[1], order[2]]),
+            _ => {
+                for (src_pixel, dst_pixel) in src_row
+                    .chunks_exact(src_bpp)
+                    .zip(dst_row.chunks_exact_mut(dst_bpp))
+                {
+                    for (&s, &d) in src_channels.iter().zip(&dst_channels) {
+                        dst_pixel[d] = src_pixel[s];
+                    }
+                    if let Some(filler) = filler {
+                        dst_pixel[filler] = 255;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Writes the luma plane and hands the chroma of every 2x2 block to
+// `write_chroma`. Odd edges reuse the last row or column.
+fn packed_to_yuv(
+    src: Plane,
+    format: PackedFormat,
+    y_plane: PlaneMut,
+    (width, height): (usize, usize),
+    colorimetry: Colorimetry,
+    mut write_chroma: impl FnMut(usize, usize, u8, u8),
+) {
+    let bpp = format.bytes_per_pixel();
+    assert_plane(src.data.len(), src.stride, width * bpp, height, "Source");
+    assert_plane(y_plane.data.len(), y_plane.stride, width, height, "Luma");
+
+    let matrix = RgbToYuv::new(colorimetry);
+    let channels = format.channels();
+    let row = |y: usize| &src.data[y * src.stride..][..width * bpp];
+
+    for y in 0..height {
+        let luma = &mut y_plane.data[y * y_plane.stride..][..width];
+        simd::luma(row(y), luma, bpp, channels, &matrix);
+    }
+
+    let mut u = vec![0; width.div_ceil(2)];
+    let mut v = vec![0; width.div_ceil(2)];
+    for cy in 0..height.div_ceil(2) {
+        let rows = [row(cy * 2), row((cy * 2 + 1).min(height - 1))];
+        simd::chroma(rows, width, &mut u, &mut v, bpp, channels, &matrix);
+        for (cx, (&u, &v)) in u.iter().zip(&v).enumerate() {
+            write_chroma(cx, cy, u, v);
+        }
+    }
+}
+
+/// Converts packed RGB to NV12: a full resolution luma plane followed by a
+/// half resolution plane of interleaved U and V samples
+pub fn packed_to_nv12(
+    src: Plane,
+    format: PackedFormat,
+    y_plane: PlaneMut,
+    uv_plane: PlaneMut,
+    size: (usize, usize),
+    colorimetry: Colorimetry,
+) {
+    let (chroma_width, chroma_height) = (size.0.div_ceil(2), size.1.div_ceil(2));
+    assert_plane(
+        uv_plane.data.len(),
+        uv_plane.stride,
+        chroma_width * 2,
+        chroma_height,
+        "Chroma",
+    );
+
+    packed_to_yuv(src, format, y_plane, size, colorimetry, |x, y, u, v| {
+        let offset = y * uv_plane.stride + x * 2;
+        uv_plane.data[offset] = u;
+        uv_plane.data[offset + 1] = v;
+    });
+}
+
+/// Converts packed RGB to I420: a full resolution luma plane followed by
+/// half resolution U and V planes
+pub fn packed_to_i420(
+    src: Plane,
+    format: PackedFormat,
+    y_plane: PlaneMut,
+    u_plane: PlaneMut,
+    v_plane: PlaneMut,
+    size: (usize, usize),
+    colorimetry: Colorimetry,
+) {
+    let (chroma_width, chroma_height) = (size.0.div_ceil(2), size.1.div_ceil(2));
+    assert_plane(
+        u_plane.data.len(),
+        u_plane.stride,
+        chroma_width,
+        chroma_height,
+        "U",
+    );
+    assert_plane(
+        v_plane.data.len(),
+        v_plane.stride,
+        chroma_width,
+        chroma_height,
+        "V",
+    );
+
+    packed_to_yuv(src, format, y_plane, size, colorimetry, |x, y, u, v| {
+        u_plane.data[y * u_plane.stride + x] = u;
+        v_plane.data[y * v_plane.stride + x] = v;
+    });
+}
+
+/// Converts packed RGB to planar YUV 4:4:4: luma, U and V planes at full
+/// resolution
+pub fn packed_to_yuv444(
+    src: Plane,
+    format: PackedFormat,
+    y_plane: PlaneMut,
+    u_plane: PlaneMut,
+    v_plane: PlaneMut,
+    (width, height): (usize, usize),
+    colorimetry: Colorimetry,
+) {
+    let bpp = format.bytes_per_pixel();
+    assert_plane(src.data.len(), src.stride, width * bpp, height, "Source");
+    assert_plane(y_plane.data.len(), y_plane.stride, width, height, "Luma");
+    assert_plane(u_plane.data.len(), u_plane.stride, width, height, "U");
+    assert_plane(v_plane.data.len(), v_plane.stride, width, height, "V");
+
+    let matrix = RgbToYuv::new(colorimetry);
+    let channels = format.channels();
+    for y in 0..height {
+        let row = &src.data[y * src.stride..][..width * bpp];
+        simd::luma(
+            row,
+            &mut y_plane.data[y * y_plane.stride..][..width],
+            bpp,
+            channels,
+            &matrix,
+        );
+        for (x, pixel) in row.chunks_exact(bpp).enumerate() {
+            // The chroma of a single pixel is that of a block of four equal ones
+            let rgb = channels.map(|channel| pixel[channel] as i32 * 4);
+            let (u, v) = matrix.chroma(rgb);
+            u_plane.data[y * u_plane.stride + x] = u;
+            v_plane.data[y * v_plane.stride + x] = v;
+        }
+    }
+}
+
+// Writes packed RGB from the luma plane and the chroma returned by
+// `read_chroma` for every 2x2 block
+fn yuv_to_packed(
+    y_plane: Plane,
+    dst: PlaneMut,
+    format: PackedFormat,
+    (width, height): (usize, usize),
+    colorimetry: Colorimetry,
+    mut read_chroma: impl FnMut(usize, usize) -> (u8, u8),
+) {
+    let bpp = format.bytes_per_pixel();
+    assert_plane(y_plane.data.len(), y_plane.stride, width, height, "Luma");
+    assert_plane(
+        dst.data.len(),
+        dst.stride,
+        width * bpp,
+        height,
+        "Destination",
+    );
+
+    let matrix = YuvToRgb::new(colorimetry);
+    let channels = format.channels();
+    let filler = format.filler();
+
+    let mut u = vec![0; width.div_ceil(2)];
+    let mut v = vec![0; width.div_ceil(2)];
+    for y in 0..height {
+        if y % 2 == 0 {
+            for (x, (u, v)) in u.iter_mut().zip(&mut v).enumerate() {
+                (*u, *v) = read_chroma(x, y / 2);
+            }
+        }
+
+        let luma = &y_plane.data[y * y_plane.stride..][..width];
+        let dst_row = &mut dst.data[y * dst.stride..][..width * bpp];
+        simd::rgb(luma, &u, &v, dst_row, bpp, channels, filler, &matrix);
+    }
+}
+
+/// Converts NV12 to packed RGB
+pub fn nv12_to_packed(
+    y_plane: Plane,
+    uv_plane: Plane,
+    dst: PlaneMut,
+    format: PackedFormat,
+    size: (usize, usize),
+    colorimetry: Colorimetry,
+) {
+    let (chroma_width, chroma_height) = (size.0.div_ceil(2), size.1.div_ceil(2));
+    assert_plane(
+        uv_plane.data.len(),
+        uv_plane.stride,
+        chroma_width * 2,
+        chroma_height,
+        "Chroma",
+    );
+
+    yuv_to_packed(y_plane, dst, format, size, colorimetry, |x, y| {
+        let offset = y * uv_plane.stride + x * 2;
+        (uv_plane.data[offset], uv_plane.data[offset + 1])
+    });
+}
+
+/// Converts I420 to packed RGB
+pub fn i420_to_packed(
+    y_plane: Plane,
+    u_plane: Plane,
+    v_plane: Plane,
+    dst: PlaneMut,
+    format: PackedFormat,
+    size: (usize, usize),
+    colorimetry: Colorimetry,
+) {
+    let (chroma_width, chroma_height) = (size.0.div_ceil(2), size.1.div_ceil(2));
+    assert_plane(
+        u_plane.data.len(),
+        u_plane.stride,
+        chroma_width,
+        chroma_height,
+        "U",
+    );
+    assert_plane(
+        v_plane.data.len(),
+        v_plane.stride,
+        chroma_width,
+        chroma_height,
+        "V",
+    );
+
+    yuv_to_packed(y_plane, dst, format, size, colorimetry, |x, y| {
+        (
+            u_plane.data[y * u_plane.stride + x],
+            v_plane.data[y * v_plane.stride + x],
+        )
+    });
+}
+
+// Rounds a plane of 16 bit little endian samples to a tightly packed plane
+// of 8 bit samples
+fn narrow_plane(src: Plane, samples: usize, rows: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(samples * rows);
+    for y in 0..rows {
+        let row = &src.data[y * src.stride..][..samples * 2];
+        output.extend(row.chunks_exact(2).map(|sample| {
+            let value = u16::from_le_bytes([sample[0], sample[1]]) as u32;
+            ((value + 128) >> 8).min(255) as u8
+        }));
+    }
+    output
+}
+
+// Widens a tightly packed plane of 8 bit samples to 16 bit little endian
+// samples
+fn widen_plane(data: &[u8]) -> Vec<u8> {
+    data.iter()
+        .flat_map(|&value| ((value as u16) << 8).to_le_bytes())
+        .collect()
+}
+
+impl VideoFrame {
+    /// Converts the frame to a tightly packed buffer of `format` pixels
+    pub fn to_packed(&self, format: PackedFormat) -> Vec<u8> {
+        if self.needs_linear_conversion() {
+            return self.tone_mapped(ToneMapping::default()).to_packed(format);
+        }
+
+        let size = (self.width() as usize, self.height() as usize);
+        let stride = size.0 * format.bytes_per_pixel();
+        let mut data = vec![0; stride * size.1];
+        let dst = PlaneMut {
+            data: &mut data,
+            stride,
+        };
+        let plane = |index| Plane {
+            data: self.data(index),
+            stride: self.stride(index),
+        };
+
+        match self {
+            VideoFrame::YUVFrame(frame) => {
+                nv12_to_packed(plane(0), plane(1), dst, format, size, frame.colorimetry)
+            }
+            VideoFrame::I420(frame) => i420_to_packed(
+                plane(0),
+                plane(1),
+                plane(2),
+                dst,
+                format,
+                size,
+                frame.colorimetry,
+            ),
+            VideoFrame::P010(frame) => {
+                let (y, uv) = self.nv12_planes();
+                let chroma_stride = size.0.div_ceil(2) * 2;
+                nv12_to_packed(
+                    Plane {
+                        data: &y,
+                        stride: size.0,
+                    },
+                    Plane {
+                        data: &uv,
+                        stride: chroma_stride,
+                    },
+                    dst,
+                    format,
+                    size,
+                    frame.colorimetry,
+                )
+            }
+            _ => {
+                let src_format = self.format().packed_format().unwrap();
+                convert_packed(plane(0), src_format, dst, format, size);
+            }
+        }
+        data
+    }
+
+    /// Converts the frame to RGB, for example to inspect YUV output
+    pub fn to_rgb(&self) -> RGBFrame {
+        RGBFrame {
+            display_time: self.display_time(),
+            timestamp: self.timestamp(),
+            sequence: self.sequence(),
+            repeated: self.is_repeated(),
+            width: self.width() as i32,
+            height: self.height() as i32,
+            data: self.to_packed(PackedFormat::RGB),
+            dirty_rects: self.dirty_rects().map(<[_]>::to_vec),
+        }
+    }
+
+    // Tightly packed NV12 luma and chroma planes of the frame. RGB frames are
+    // converted with the default colorimetry.
+    fn nv12_planes(&self) -> (Vec<u8>, Vec<u8>) {
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let plane = |index| Plane {
+            data: self.data(index),
+            stride: self.stride(index),
+        };
+
+        match self {
+            VideoFrame::YUVFrame(_) => (
+                crop_packed(plane(0).data, plane(0).stride, 1, 0, 0, width, height),
+                crop_packed(
+                    plane(1).data,
+                    plane(1).stride,
+                    2,
+                    0,
+                    0,
+                    chroma_width,
+                    chroma_height,
+                ),
+            ),
+            VideoFrame::I420(_) => {
+                let y = crop_packed(plane(0).data, plane(0).stride, 1, 0, 0, width, height);
+                let mut uv = Vec::with_capacity(chroma_width * 2 * chroma_height);
+                for row in 0..chroma_height {
+                    let u = &plane(1).data[row * plane(1).stride..][..chroma_width];
+                    let v = &plane(2).data[row * plane(2).stride..][..chroma_width];
+                    uv.extend(u.iter().zip(v).flat_map(|(&u, &v)| [u, v]));
+                }
+                (y, uv)
+            }
+            VideoFrame::P010(_) => (
+                narrow_plane(plane(0), width, height),
+                narrow_plane(plane(1), chroma_width * 2, chroma_height),
+            ),
+            _ => {
+                let mut y = vec![0; width * height];
+                let mut uv = vec![0; chroma_width * 2 * chroma_height];
+                packed_to_nv12(
+                    plane(0),
+                    self.format().packed_format().unwrap(),
+                    PlaneMut {
+                        data: &mut y,
+                        stride: width,
+                    },
+                    PlaneMut {
+                        data: &mut uv,
+                        stride: chroma_width * 2,
+                    },
+                    (width, height),
+                    Colorimetry::default(),
+                );
+                (y, uv)
+            }
+        }
+    }
+
+    // Tightly packed I420 luma, U and V planes of the frame. RGB frames are
+    // converted with the default colorimetry.
+    fn i420_planes(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let Some(src_format) = self.format().packed_format() else {
+            let (y, uv) = self.nv12_planes();
+            let u = uv.iter().step_by(2).copied().collect();
+            let v = uv.iter().skip(1).step_by(2).copied().collect();
+            return (y, u, v);
+        };
+
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let mut y = vec![0; width * height];
+        let mut u = vec![0; chroma_width * chroma_height];
+        let mut v = vec![0; chroma_width * chroma_height];
+        packed_to_i420(
+            Plane {
+                data: self.data(0),
+                stride: self.stride(0),
+            },
+            src_format,
+            PlaneMut {
+                data: &mut y,
+                stride: width,
+            },
+            PlaneMut {
+                data: &mut u,
+                stride: chroma_width,
+            },
+            PlaneMut {
+                data: &mut v,
+                stride: chroma_width,
+            },
+            (width, height),
+            Colorimetry::default(),
+        );
+        (y, u, v)
+    }
+
+    /// Converts the frame to `format`, returning it as is if it already has
+    /// that format.
+    ///
+    /// YUV frames keep their colorimetry, RGB frames are converted to YUV with
+    /// [Colorimetry::default]. P010 frames converted from 8 bit formats have
+    /// the lower bits of every sample cleared.
+    ///
+    /// HDR frames are tone mapped with [ToneMapping::default] for 8 bit
+    /// formats and become HDR10 when converted to P010.
+    pub fn converted(self, format: PixelFormat) -> VideoFrame {
+        if self.format() == format {
+            return self;
+        }
+        if self.needs_linear_conversion() {
+            return match format {
+                PixelFormat::RGBA16F => VideoFrame::RGBA16F(self.to_rgba16f()),
+                PixelFormat::P010 if self.is_hdr() => VideoFrame::P010(self.to_hdr10()),
+                _ => self.tone_mapped(ToneMapping::default()).converted(format),
+            };
+        }
+
+        let display_time = self.display_time();
+        let timestamp = self.timestamp();
+        let sequence = self.sequence();
+        let repeated = self.is_repeated();
+        let width = self.width() as i32;
+        let height = self.height() as i32;
+        let dirty_rects = self.dirty_rects().map(<[_]>::to_vec);
+
+        macro_rules! packed_frame {
+            ($variant:ident, $frame:ident, $packed_format:ident) => {
+                VideoFrame::$variant($frame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    data: self.to_packed(PackedFormat::$packed_format),
+                    dirty_rects,
+                })
+            };
+        }
+
+        let colorimetry = self.colorimetry().unwrap_or_default();
+        match format {
+            PixelFormat::RGB => packed_frame!(RGB, RGBFrame, RGB),
+            PixelFormat::RGBA => packed_frame!(RGBA, RGBAFrame, RGBA),
+            PixelFormat::RGBx => packed_frame!(RGBx, RGBxFrame, RGBx),
+            PixelFormat::XBGR => packed_frame!(XBGR, XBGRFrame, XBGR),
+            PixelFormat::BGRx => packed_frame!(BGRx, BGRxFrame, BGRx),
+            PixelFormat::BGR => packed_frame!(BGR0, BGRFrame, BGR),
+            PixelFormat::BGRA => packed_frame!(BGRA, BGRAFrame, BGRA),
+            PixelFormat::RGBA16F => VideoFrame::RGBA16F(self.to_rgba16f()),
+            PixelFormat::NV12 => {
+                let (y, uv) = self.nv12_planes();
+                VideoFrame::YUVFrame(YUVFrame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    luminance_bytes: y,
+                    luminance_stride: width,
+                    chrominance_bytes: uv,
+                    chrominance_stride: (width as u32).div_ceil(2) as i32 * 2,
+                    colorimetry,
+                    dirty_rects,
+                })
+            }
+            PixelFormat::I420 => {
+                let (y, u, v) = self.i420_planes();
+                let chroma_width = (width as u32).div_ceil(2) as i32;
+                VideoFrame::I420(I420Frame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    y_bytes: y,
+                    y_stride: width,
+                    u_bytes: u,
+                    u_stride: chroma_width,
+                    v_bytes: v,
+                    v_stride: chroma_width,
+                    colorimetry,
+                    dirty_rects,
+                })
+            }
+            PixelFormat::P010 => {
+                let (y, uv) = self.nv12_planes();
+                VideoFrame::P010(P010Frame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated,
+                    width,
+                    height,
+                    luminance_bytes: widen_plane(&y),
+                    luminance_stride: width * 2,
+                    chrominance_bytes: widen_plane(&uv),
+                    chrominance_stride: (width as u32).div_ceil(2) as i32 * 4,
+                    colorimetry,
+                    dirty_rects,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BT601_LIMITED: Colorimetry = Colorimetry {
+        matrix: ColorMatrix::Bt601,
+        range: ColorRange::Limited,
+        primaries: ColorPrimaries::Bt709,
+        transfer: TransferFunction::Srgb,
+    };
+    const BT709_LIMITED: Colorimetry = Colorimetry {
+        matrix: ColorMatrix::Bt709,
+        range: ColorRange::Limited,
+        primaries: ColorPrimaries::Bt709,
+        transfer: TransferFunction::Srgb,
+    };
+    const BT601_FULL: Colorimetry = Colorimetry {
+        matrix: ColorMatrix::Bt601,
+        range: ColorRange::Full,
+        primaries: ColorPrimaries::Bt709,
+        transfer: TransferFunction::Srgb,
+    };
+
+    // 4x2 RGB color bars: white, red, green, blue over yellow, cyan,
+    // magenta, black
+    const BARS: [u8; 24] = [
+        255, 255, 255, 255, 0, 0, 0, 255, 0, 0, 0, 255, //
+        255, 255, 0, 0, 255, 255, 255, 0, 255, 0, 0, 0,
+    ];
+
+    fn to_nv12(
+        src: &[u8],
+        stride: usize,
+        format: PackedFormat,
+        size: (usize, usize),
+        colorimetry: Colorimetry,
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut y = vec![0; size.0 * size.1];
+        let mut uv = vec![0; size.0.div_ceil(2) * 2 * size.1.div_ceil(2)];
+        packed_to_nv12(
+            Plane { data: src, stride },
+            format,
+            PlaneMut {
+                data: &mut y,
+                stride: size.0,
+            },
+            PlaneMut {
+                data: &mut uv,
+                stride: size.0.div_ceil(2) * 2,
+            },
+            size,
+            colorimetry,
+        );
+        (y, uv)
+    }
+
+    #[test]
+    fn test_primaries() {
+        let red = [255, 0, 0];
+        assert_eq!(
+            to_nv12(&red, 3, PackedFormat::RGB, (1, 1), BT601_LIMITED),
+            (vec![81], vec![90, 240])
+        );
+        assert_eq!(
+            to_nv12(&red, 3, PackedFormat::RGB, (1, 1), BT709_LIMITED),
+            (vec![63], vec![102, 240])
+        );
+        assert_eq!(
+            to_nv12(&red, 3, PackedFormat::RGB, (1, 1), BT601_FULL),
+            (vec![76], vec![85, 255])
+        );
+        assert_eq!(
+            to_nv12(
+                &[255, 255, 255],
+                3,
+                PackedFormat::RGB,
+                (1, 1),
+                BT709_LIMITED
+            ),
+            (vec![235], vec![128, 128])
+        );
+        assert_eq!(
+            to_nv12(&[0, 0, 0], 3, PackedFormat::RGB, (1, 1), BT709_LIMITED),
+            (vec![16], vec![128, 128])
+        );
+    }
+
+    #[test]
+    fn test_golden_nv12() {
+        let (y, uv) = to_nv12(&BARS, 12, PackedFormat::RGB, (4, 2), BT709_LIMITED);
+        assert_eq!(y, vec![235, 63, 173, 32, 219, 188, 78, 16]);
+        assert_eq!(uv, vec![100, 131, 156, 125]);
+
+        let (y, uv) = to_nv12(&BARS, 12, PackedFormat::RGB, (4, 2), BT601_FULL);
+        assert_eq!(y, vec![255, 76, 150, 29, 226, 179, 105, 0]);
+        assert_eq!(uv, vec![96, 133, 160, 123]);
+    }
+
+    #[test]
+    fn test_golden_i420() {
+        let mut y = [0; 8];
+        let mut u = [0; 2];
+        let mut v = [0; 2];
+        packed_to_i420(
+            Plane {
+                data: &BARS,
+                stride: 12,
+            },
+            PackedFormat::RGB,
+            PlaneMut {
+                data: &mut y,
+                stride: 4,
+            },
+            PlaneMut {
+                data: &mut u,
+                stride: 2,
+            },
+            PlaneMut {
+                data: &mut v,
+                stride: 2,
+            },
+            (4, 2),
+            BT709_LIMITED,
+        );
+        assert_eq!(y, [235, 63, 173, 32, 219, 188, 78, 16]);
+        assert_eq!((u, v), ([100, 156], [131, 125]));
+    }
+
+    #[test]
+    fn test_golden_yuv444() {
+        let mut y = [0; 8];
+        let mut u = [0; 8];
+        let mut v = [0; 8];
+        packed_to_yuv444(
+            Plane {
+                data: &BARS,
+                stride: 12,
+            },
+            PackedFormat::RGB,
+            PlaneMut {
+                data: &mut y,
+                stride: 4,
+            },
+            PlaneMut {
+                data: &mut u,
+                stride: 4,
+            },
+            PlaneMut {
+                data: &mut v,
+                stride: 4,
+            },
+            (4, 2),
+            BT709_LIMITED,
+        );
+        assert_eq!(y, [235, 63, 173, 32, 219, 188, 78, 16]);
+        assert_eq!(u, [128, 102, 42, 240, 16, 154, 214, 128]);
+        assert_eq!(v, [128, 240, 26, 118, 138, 16, 230, 128]);
+    }
+
+    #[test]
+    fn test_strides_and_formats() {
+        // The same image as BGRA with 4 bytes of padding after every row
+        let mut bgra = vec![0xAA; 20 * 2];
+        convert_packed(
+            Plane {
+                data: &BARS,
+                stride: 12,
+            },
+            PackedFormat::RGB,
+            PlaneMut {
+                data: &mut bgra,
+                stride: 20,
+            },
+            PackedFormat::BGRA,
+            (4, 2),
+        );
+        assert_eq!(&bgra[..8], &[255, 255, 255, 255, 0, 0, 255, 255]);
+        assert_eq!(&bgra[16..20], &[0xAA; 4]);
+
+        let mut xbgr = vec![0; 32];
+        convert_packed(
+            Plane {
+                data: &bgra,
+                stride: 20,
+            },
+            PackedFormat::BGRA,
+            PlaneMut {
+                data: &mut xbgr,
+                stride: 16,
+            },
+            PackedFormat::XBGR,
+            (4, 2),
+        );
+        assert_eq!(&xbgr[4..8], &[255, 0, 0, 255]);
+
+        assert_eq!(
+            to_nv12(&bgra, 20, PackedFormat::BGRA, (4, 2), BT709_LIMITED),
+            to_nv12(&BARS, 12, PackedFormat::RGB, (4, 2), BT709_LIMITED)
+        );
+        assert_eq!(
+            to_nv12(&xbgr, 16, PackedFormat::XBGR, (4, 2), BT709_LIMITED),
+            to_nv12(&BARS, 12, PackedFormat::RGB, (4, 2), BT709_LIMITED)
+        );
+    }
+
+    #[test]
+    fn test_odd_size() {
+        let (y, uv) = to_nv12(&BARS[..9], 12, PackedFormat::RGB, (3, 1), BT709_LIMITED);
+        assert_eq!(y, vec![235, 63, 173]);
+        // The last chroma sample only covers the green pixel
+        assert_eq!(&uv[2..], &[42, 26]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for colorimetry in [BT601_LIMITED, BT709_LIMITED, BT601_FULL] {
+            // Flat 2x2 blocks so chroma subsampling is lossless
+            let mut rgb = Vec::new();
+            for color in [[200, 30, 90], [10, 250, 120]] {
+                rgb.extend(color.repeat(2));
+            }
+            let rgb = rgb.repeat(2);
+
+            let (y, uv) = to_nv12(&rgb, 12, PackedFormat::RGB, (4, 2), colorimetry);
+            let mut output = vec![0; 24];
+            nv12_to_packed(
+                Plane {
+                    data: &y,
+                    stride: 4,
+                },
+                Plane {
+                    data: &uv,
+                    stride: 4,
+                },
+                PlaneMut {
+                    data: &mut output,
+                    stride: 12,
+                },
+                PackedFormat::RGB,
+                (4, 2),
+                colorimetry,
+            );
+            for (a, b) in rgb.iter().zip(&output) {
+                assert!(a.abs_diff(*b) <= 2, "{rgb:?} != {output:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_to_rgb() {
+        use crate::frame::{I420Frame, YUVFrame};
+        use std::time::{Duration, SystemTime};
+
+        // Flat 2x2 blocks so chroma subsampling is lossless
+        let rgb = [[200, 30, 90], [10, 250, 120]].map(|color| color.repeat(2));
+        let rgb = rgb.concat().repeat(2);
+        let (y, uv) = to_nv12(&rgb, 12, PackedFormat::RGB, (4, 2), BT709_LIMITED);
+        let expected = VideoFrame::YUVFrame(YUVFrame {
+            display_time: SystemTime::UNIX_EPOCH,
+            timestamp: Duration::ZERO,
+            sequence: 0,
+            repeated: false,
+            width: 4,
+            height: 2,
+            luminance_bytes: y.clone(),
+            luminance_stride: 4,
+            chrominance_bytes: uv.clone(),
+            chrominance_stride: 4,
+            colorimetry: BT709_LIMITED,
+            dirty_rects: None,
+        })
+        .to_rgb();
+        for (a, b) in rgb.iter().zip(&expected.data) {
+            assert!(a.abs_diff(*b) <= 2, "{rgb:?} != {:?}", expected.data);
+        }
+
+        // The same image as I420 with padded rows
+        let pad = |data: &[u8], width: usize| -> Vec<u8> {
+            data.chunks(width)
+                .flat_map(|row| [row, &[0; 4][..]].concat())
+                .collect()
+        };
+        let frame = VideoFrame::I420(I420Frame {
+            display_time: SystemTime::UNIX_EPOCH,
+            timestamp: Duration::ZERO,
+            sequence: 0,
+            repeated: false,
+            width: 4,
+            height: 2,
+            y_bytes: pad(&y, 4),
+            y_stride: 8,
+            u_bytes: pad(&[uv[0], uv[2]], 2),
+            u_stride: 6,
+            v_bytes: pad(&[uv[1], uv[3]], 2),
+            v_stride: 6,
+            colorimetry: BT709_LIMITED,
+            dirty_rects: None,
+        });
+        assert_eq!(frame.to_rgb().data, expected.data);
+        assert_eq!(
+            frame.to_packed(PackedFormat::BGRA)[..4],
+            [expected.data[2], expected.data[1], expected.data[0], 255]
+        );
+    }
+
+    #[test]
+    fn test_converted() {
+        let frame = VideoFrame::packed(PixelFormat::RGB, 4, 2, BARS.to_vec());
+        let (y, uv) = to_nv12(&BARS, 12, PackedFormat::RGB, (4, 2), BT709_LIMITED);
+
+        let nv12 = frame.clone().converted(PixelFormat::NV12);
+        assert_eq!((nv12.data(0), nv12.data(1)), (&y[..], &uv[..]));
+        assert_eq!(nv12.colorimetry(), Some(BT709_LIMITED));
+
+        let i420 = nv12.clone().converted(PixelFormat::I420);
+        assert_eq!(i420.data(1), &[100, 156]);
+        assert_eq!(i420.data(2), &[131, 125]);
+        let direct = frame.clone().converted(PixelFormat::I420);
+        assert_eq!(direct.data(0), &y[..]);
+        assert_eq!(
+            (direct.data(1), direct.data(2)),
+            (i420.data(1), i420.data(2))
+        );
+
+        let p010 = i420.converted(PixelFormat::P010);
+        assert_eq!(p010.stride(0), 8);
+        assert_eq!(&p010.data(1)[..4], &[0, 100, 0, 131]);
+
+        let round_trip = p010.converted(PixelFormat::NV12);
+        assert_eq!(round_trip.data(0), nv12.data(0));
+        assert_eq!(round_trip.data(1), nv12.data(1));
+
+        let bgra = frame.clone().converted(PixelFormat::BGRA);
+        assert_eq!(bgra.format(), PixelFormat::BGRA);
+        assert_eq!(&bgra.data(0)[4..8], &[0, 0, 255, 255]);
+        assert_eq!(
+            bgra.converted(PixelFormat::RGB).data(0),
+            frame.clone().converted(PixelFormat::RGB).data(0)
+        );
+    }
+}
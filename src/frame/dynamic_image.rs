@@ -0,0 +1,117 @@
+//! Conversions between frames and images of the `image` crate, enabled with
+//! the `image` feature
+
+use std::time::SystemTime;
+
+use image::{DynamicImage, RgbImage, RgbaImage};
+
+use super::{PackedFormat, PixelFormat, RGBAFrame, RGBFrame, VideoFrame};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImageConversionError {
+    /// A plane of the frame holds less data than its size and stride need
+    #[error("Plane {0} of the frame is too small for its size")]
+    TruncatedPlane(usize),
+}
+
+// Bytes of pixel data in a row of `plane`, without padding
+fn row_bytes(frame: &VideoFrame, plane: usize) -> usize {
+    let bytes_per_sample = match (frame.format(), plane) {
+        (PixelFormat::NV12 | PixelFormat::I420, 0) | (PixelFormat::I420, _) => 1,
+        (PixelFormat::NV12, _) | (PixelFormat::P010, 0) => 2,
+        (PixelFormat::P010, _) => 4,
+        (PixelFormat::RGBA16F, _) => 8,
+        (format, _) => format.packed_format().unwrap().bytes_per_pixel(),
+    };
+    frame.plane_size(plane).0 as usize * bytes_per_sample
+}
+
+/// Converts the frame to an 8 bit image, tone mapping HDR frames. Frames with
+/// an alpha channel become opaque RGBA images, all others RGB images.
+impl TryFrom<&VideoFrame> for DynamicImage {
+    type Error = ImageConversionError;
+
+    fn try_from(frame: &VideoFrame) -> Result<Self, Self::Error> {
+        for plane in 0..frame.planes() {
+            let rows = frame.plane_size(plane).1 as usize;
+            let size = match rows {
+                0 => 0,
+                _ => frame.stride(plane) * (rows - 1) + row_bytes(frame, plane),
+            };
+            if frame.data(plane).len() < size {
+                return Err(ImageConversionError::TruncatedPlane(plane));
+            }
+        }
+
+        let (width, height) = (frame.width(), frame.height());
+        let image = match frame.format() {
+            PixelFormat::RGBA | PixelFormat::BGRA | PixelFormat::RGBA16F => {
+                let data = frame.to_packed(PackedFormat::RGBA);
+                DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, data).unwrap())
+            }
+            _ => {
+                let data = frame.to_packed(PackedFormat::RGB);
+                DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, data).unwrap())
+            }
+        };
+        Ok(image)
+    }
+}
+
+/// Converts the image to an RGBA frame if it has an alpha channel, otherwise
+/// to an RGB frame. Samples of more than 8 bits are narrowed.
+impl From<&DynamicImage> for VideoFrame {
+    fn from(image: &DynamicImage) -> Self {
+        let (width, height) = (image.width() as i32, image.height() as i32);
+        match image.color().has_alpha() {
+            true => VideoFrame::RGBA(RGBAFrame {
+                display_time: SystemTime::now(),
+                timestamp: Default::default(),
+                sequence: 0,
+                repeated: false,
+                width,
+                height,
+                data: image.to_rgba8().into_raw(),
+                dirty_rects: None,
+            }),
+            false => VideoFrame::RGB(RGBFrame {
+                display_time: SystemTime::now(),
+                timestamp: Default::default(),
+                sequence: 0,
+                repeated: false,
+                width,
+                height,
+                data: image.to_rgb8().into_raw(),
+                dirty_rects: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dynamic_image() {
+        let frame = VideoFrame::packed(PixelFormat::BGRA, 2, 1, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        let image = DynamicImage::try_from(&frame).unwrap();
+        assert_eq!(image.as_bytes(), [3, 2, 1, 255, 7, 6, 5, 255]);
+
+        let round_trip = VideoFrame::from(&image);
+        assert_eq!(round_trip.format(), PixelFormat::RGBA);
+        assert_eq!(round_trip.data(0), image.as_bytes());
+
+        let image = DynamicImage::ImageRgb8(RgbImage::from_raw(1, 1, vec![9, 8, 7]).unwrap());
+        assert_eq!(VideoFrame::from(&image).data(0), [9, 8, 7]);
+
+        let VideoFrame::BGRA(mut truncated) = frame else {
+            unreachable!()
+        };
+        truncated.data.pop();
+        assert!(matches!(
+            DynamicImage::try_from(&VideoFrame::BGRA(truncated)),
+            Err(ImageConversionError::TruncatedPlane(0))
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Image encoders for single frames, enabled with the `encoders` feature.
+//!
+//! Frames of any pixel format are packed through the conversion layer first,
+//! with HDR frames tone mapped, so every [VideoFrame] can be written.
+
+use std::{fs, io, path::Path};
+
+use super::{PackedFormat, VideoFrame};
+
+mod qoi;
+
+impl VideoFrame {
+    /// Encodes the frame as a PNG image with an alpha channel. Fails for
+    /// empty frames, which PNG can't hold.
+    pub fn to_png_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width(), self.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        // Picks the filter per row, which suits screen content best
+        encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_packed(PackedFormat::RGBA))?;
+        writer.finish()?;
+        Ok(out)
+    }
+
+    /// Encodes the frame as a QOI image with an alpha channel
+    pub fn to_qoi_bytes(&self) -> Vec<u8> {
+        qoi::encode(
+            self.width(),
+            self.height(),
+            &self.to_packed(PackedFormat::RGBA),
+        )
+    }
+
+    /// Encodes the frame as a binary PPM image, which has no alpha channel
+    pub fn to_ppm_bytes(&self) -> Vec<u8> {
+        let mut out = format!("P6\n{} {}\n255\n", self.width(), self.height()).into_bytes();
+        out.extend(self.to_packed(PackedFormat::RGB));
+        out
+    }
+
+    /// Writes the frame to `path` as a PNG image
+    pub fn write_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_png_bytes()?)
+    }
+
+    /// Writes the frame to `path` as a QOI image
+    pub fn write_qoi(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_qoi_bytes())
+    }
+
+    /// Writes the frame to `path` as a binary PPM image
+    pub fn write_ppm(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_ppm_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::PixelFormat;
+
+    #[test]
+    fn test_encode() {
+        let frame = VideoFrame::packed(
+            PixelFormat::BGRx,
+            2,
+            1,
+            vec![10, 120, 200, 0, 90, 30, 250, 0],
+        );
+
+        assert_eq!(
+            frame.to_ppm_bytes(),
+            b"P6\n2 1\n255\n\xc8\x78\x0a\xfa\x1e\x5a"
+        );
+        assert_eq!(
+            &frame.to_qoi_bytes()[14..22],
+            [0xfe, 200, 120, 10, 0xfe, 250, 30, 90]
+        );
+        let png = frame.to_png_bytes().unwrap();
+        let mut reader = png::Decoder::new(&png[..]).read_info().unwrap();
+        let mut rgba = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut rgba).unwrap();
+        assert_eq!(rgba, [200, 120, 10, 255, 250, 30, 90, 255]);
+    }
+}
@@ -0,0 +1,139 @@
+//! QOI encoding of 8 bit RGBA images, see https://qoiformat.org
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const MAX_RUN: u8 = 62;
+const END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+fn index_of([r, g, b, a]: [u8; 4]) -> usize {
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+/// Encodes tightly packed RGBA pixels
+pub(crate) fn encode(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(14 + rgba.len() / 2 + END.len());
+    out.extend(b"qoif");
+    out.extend(width.to_be_bytes());
+    out.extend(height.to_be_bytes());
+    // RGBA in sRGB with linear alpha
+    out.extend([4, 0]);
+
+    let mut seen = [[0u8; 4]; 64];
+    let mut previous = [0, 0, 0, 255];
+    let mut run = 0;
+    for pixel in rgba.chunks_exact(4) {
+        let pixel: [u8; 4] = pixel.try_into().unwrap();
+        if pixel == previous {
+            run += 1;
+            if run == MAX_RUN {
+                out.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            out.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+
+        let index = index_of(pixel);
+        if seen[index] == pixel {
+            out.push(OP_INDEX | index as u8);
+        } else {
+            seen[index] = pixel;
+            if pixel[3] != previous[3] {
+                out.push(OP_RGBA);
+                out.extend(pixel);
+            } else {
+                let dr = pixel[0].wrapping_sub(previous[0]) as i8;
+                let dg = pixel[1].wrapping_sub(previous[1]) as i8;
+                let db = pixel[2].wrapping_sub(previous[2]) as i8;
+                let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+                if (-2..2).contains(&dr) && (-2..2).contains(&dg) && (-2..2).contains(&db) {
+                    out.push(
+                        OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8,
+                    );
+                } else if (-32..32).contains(&dg)
+                    && (-8..8).contains(&dr_dg)
+                    && (-8..8).contains(&db_dg)
+                {
+                    out.push(OP_LUMA | (dg + 32) as u8);
+                    out.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+                } else {
+                    out.push(OP_RGB);
+                    out.extend(&pixel[..3]);
+                }
+            }
+        }
+        previous = pixel;
+    }
+    if run > 0 {
+        out.push(OP_RUN | (run - 1));
+    }
+
+    out.extend(END);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qoi() {
+        let pixels = [
+            // Run of the implicit start pixel
+            [0, 0, 0, 255],
+            [0, 0, 0, 255],
+            // Small difference
+            [1, 255, 0, 255],
+            // Luma difference
+            [21, 15, 8, 255],
+            // Full color
+            [200, 100, 50, 255],
+            // Alpha change
+            [200, 100, 50, 128],
+            // Seen before
+            [1, 255, 0, 255],
+        ];
+        let rgba: Vec<u8> = pixels.concat();
+        let qoi = encode(7, 1, &rgba);
+
+        assert_eq!(&qoi[..14], b"qoif\0\0\0\x07\0\0\0\x01\x04\0");
+        assert_eq!(
+            &qoi[14..],
+            [
+                OP_RUN | 1,
+                OP_DIFF | 3 << 4 | 1 << 2 | 2,
+                OP_LUMA | (16 + 32),
+                (4 + 8) << 4 | (-8i8 + 8) as u8,
+                OP_RGB,
+                200,
+                100,
+                50,
+                OP_RGBA,
+                200,
+                100,
+                50,
+                128,
+                OP_INDEX | index_of([1, 255, 0, 255]) as u8,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                1,
+            ]
+        );
+
+        // Runs are split at their maximum length
+        let qoi = encode(100, 1, &[0, 0, 0, 255].repeat(100));
+        assert_eq!(&qoi[14..16], [OP_RUN | 61, OP_RUN | 37]);
+    }
+}
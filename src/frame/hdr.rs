@@ -0,0 +1,563 @@
+use super::{
+    ColorMatrix, ColorPrimaries, ColorRange, Colorimetry, P010Frame, RGBA16FFrame, RGBAFrame,
+    TransferFunction, VideoFrame,
+};
+
+/// How [VideoFrame::tone_mapped] fits HDR content into SDR
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    /// Luminance in nits of SDR white in the content. Content up to three
+    /// quarters of it is left untouched, brighter content is compressed.
+    pub sdr_white: f32,
+    /// Brightest luminance of the content in nits, which becomes white.
+    /// Anything brighter is clipped.
+    pub peak: f32,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        // Reference white of ITU-R BT.2408
+        ToneMapping {
+            sdr_white: 203.0,
+            peak: 1000.0,
+        }
+    }
+}
+
+// Luminance of 1.0 in scRGB
+const SCRGB_NITS: f32 = 80.0;
+// Peak luminance of PQ and the nominal peak of HLG displays
+const PQ_NITS: f32 = 10000.0;
+const HLG_NITS: f32 = 1000.0;
+// Fraction of SDR white below which tone mapping leaves values unchanged
+const KNEE: f32 = 0.75;
+
+pub(crate) fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1F;
+    let mantissa = (bits & 0x3FF) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent as i32 - 15),
+    }
+}
+
+// Rounds to the nearest half float, ties to even
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32;
+    let mantissa = bits & 0x7F_FFFF;
+
+    if exponent == 0xFF {
+        return sign | 0x7C00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 31 {
+        return sign | 0x7C00;
+    }
+
+    let (half, rest, halfway) = if exponent > 0 {
+        (
+            ((exponent as u32) << 10) | (mantissa >> 13),
+            mantissa & 0x1FFF,
+            0x1000,
+        )
+    } else if exponent >= -10 {
+        // Subnormal, including the implicit leading bit
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (
+            mantissa >> shift,
+            mantissa & ((1 << shift) - 1),
+            1 << (shift - 1),
+        )
+    } else {
+        return sign;
+    };
+    // A carry out of the mantissa correctly bumps the exponent
+    let round = rest > halfway || (rest == halfway && half & 1 == 1);
+    sign | (half + round as u32) as u16
+}
+
+fn pq_to_nits(value: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let p = value.clamp(0.0, 1.0).powf(1.0 / M2);
+    ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1) * PQ_NITS
+}
+
+fn nits_to_pq(nits: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let y = (nits / PQ_NITS).clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+// Inverse of the HLG OETF, giving relative scene light
+fn hlg_to_scene(value: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 1.0 - 4.0 * A;
+    const C: f32 = 0.559_910_7;
+
+    let value = value.max(0.0);
+    if value <= 0.5 {
+        value * value / 3.0
+    } else {
+        (((value - C) / A).exp() + B) / 12.0
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], rgb: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2])
+}
+
+const BT709_TO_BT2020: [[f32; 3]; 3] = [
+    [0.6274, 0.3293, 0.0433],
+    [0.0691, 0.9195, 0.0114],
+    [0.0164, 0.0880, 0.8956],
+];
+
+impl ColorPrimaries {
+    // Linear RGB matrix to BT.709 primaries
+    fn to_bt709(self) -> [[f32; 3]; 3] {
+        match self {
+            ColorPrimaries::Bt709 => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            ColorPrimaries::DisplayP3 => [
+                [1.2249, -0.2247, 0.0],
+                [-0.0420, 1.0419, 0.0],
+                [-0.0197, -0.0786, 1.0979],
+            ],
+            ColorPrimaries::Bt2020 => [
+                [1.6605, -0.5876, -0.0728],
+                [-0.1246, 1.1329, -0.0083],
+                [-0.0182, -0.1006, 1.1187],
+            ],
+        }
+    }
+}
+
+impl TransferFunction {
+    // Linear light in nits of encoded RGB values. SDR white is placed at
+    // `sdr_white` nits.
+    fn to_nits(self, rgb: [f32; 3], sdr_white: f32) -> [f32; 3] {
+        match self {
+            TransferFunction::Srgb => rgb.map(|value| srgb_to_linear(value) * sdr_white),
+            TransferFunction::Linear => rgb.map(|value| value * SCRGB_NITS),
+            TransferFunction::Pq => rgb.map(pq_to_nits),
+            TransferFunction::Hlg => {
+                // HLG is scene referred, so apply the reference OOTF of
+                // BT.2100 for a 1000 nit display
+                let scene = rgb.map(hlg_to_scene);
+                let (kr, kb) = ColorMatrix::Bt2020.coefficients();
+                let (kr, kb) = (kr as f32, kb as f32);
+                let luma = kr * scene[0] + (1.0 - kr - kb) * scene[1] + kb * scene[2];
+                let gain = HLG_NITS * luma.max(0.0).powf(0.2);
+                scene.map(|value| value * gain)
+            }
+        }
+    }
+}
+
+// Full range value of a YUV sample with `bits` bits
+fn normalize_yuv([y, u, v]: [u32; 3], bits: u32, colorimetry: Colorimetry) -> [f32; 3] {
+    let scale = (1 << (bits - 8)) as f32;
+    let (y, u, v) = (y as f32, u as f32, v as f32);
+    let center = 128.0 * scale;
+    let (y, u, v) = match colorimetry.range {
+        ColorRange::Limited => (
+            (y - 16.0 * scale) / (219.0 * scale),
+            (u - center) / (224.0 * scale),
+            (v - center) / (224.0 * scale),
+        ),
+        ColorRange::Full => {
+            let max = ((1 << bits) - 1) as f32;
+            (y / max, (u - center) / max, (v - center) / max)
+        }
+    };
+
+    let (kr, kb) = colorimetry.matrix.coefficients();
+    let (kr, kb) = (kr as f32, kb as f32);
+    let r = y + 2.0 * (1.0 - kr) * v;
+    let b = y + 2.0 * (1.0 - kb) * u;
+    let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+    [r, g, b]
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u32 {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) as u32
+}
+
+/// Unpacks `height` rows of `width` 10 bit RGB pixels, packed into 32 bit
+/// little endian words with the red, green and blue values starting at the
+/// given bits, appending them to `output` as half float RGBA
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) fn rgb10_to_rgba16f(
+    data: &[u8],
+    stride: usize,
+    (width, height): (usize, usize),
+    shifts: [u32; 3],
+    output: &mut Vec<u8>,
+) {
+    let alpha = f32_to_f16(1.0).to_le_bytes();
+    for y in 0..height {
+        let row = &data[y * stride..][..width * 4];
+        output.extend(
+            row.chunks_exact(4)
+                .flat_map(|word| {
+                    let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+                    let [r, g, b] =
+                        shifts.map(|shift| f32_to_f16(((word >> shift) & 0x3FF) as f32 / 1023.0));
+                    [r.to_le_bytes(), g.to_le_bytes(), b.to_le_bytes(), alpha]
+                })
+                .flatten(),
+        );
+    }
+}
+
+impl VideoFrame {
+    /// Whether the frame has to be tone mapped to be shown as SDR. That is
+    /// the case for PQ and HLG content, as well as linear frames whose values
+    /// are not limited to SDR white.
+    pub fn is_hdr(&self) -> bool {
+        self.colorimetry().is_some_and(|colorimetry| {
+            matches!(
+                colorimetry.transfer,
+                TransferFunction::Pq | TransferFunction::Hlg | TransferFunction::Linear
+            )
+        })
+    }
+
+    // Whether the frame has to be converted through linear light, which is
+    // the case for HDR and half float frames
+    pub(crate) fn needs_linear_conversion(&self) -> bool {
+        self.is_hdr() || matches!(self, VideoFrame::RGBA16F(_))
+    }
+
+    // Encoded RGB values of the pixel at (`x`, `y`), from 0 to 1 in SDR
+    fn encoded_rgb(&self, x: usize, y: usize) -> [f32; 3] {
+        let colorimetry = self.colorimetry().unwrap_or_default();
+        let sample = |plane: usize, offset: usize| self.data(plane)[offset] as u32;
+        match self {
+            VideoFrame::YUVFrame(_) => {
+                let chroma = (y / 2) * self.stride(1) + (x / 2) * 2;
+                let yuv = [
+                    sample(0, y * self.stride(0) + x),
+                    sample(1, chroma),
+                    sample(1, chroma + 1),
+                ];
+                normalize_yuv(yuv, 8, colorimetry)
+            }
+            VideoFrame::I420(_) => {
+                let yuv = [
+                    sample(0, y * self.stride(0) + x),
+                    sample(1, (y / 2) * self.stride(1) + x / 2),
+                    sample(2, (y / 2) * self.stride(2) + x / 2),
+                ];
+                normalize_yuv(yuv, 8, colorimetry)
+            }
+            VideoFrame::P010(_) => {
+                let chroma = (y / 2) * self.stride(1) + (x / 2) * 4;
+                let yuv = [
+                    read_u16(self.data(0), y * self.stride(0) + x * 2),
+                    read_u16(self.data(1), chroma),
+                    read_u16(self.data(1), chroma + 2),
+                ];
+                normalize_yuv(yuv.map(|value| value >> 6), 10, colorimetry)
+            }
+            VideoFrame::RGBA16F(_) => {
+                let offset = y * self.stride(0) + x * 8;
+                [0, 2, 4].map(|channel| f16_to_f32(read_u16(self.data(0), offset + channel) as u16))
+            }
+            _ => {
+                let format = self.format().packed_format().unwrap();
+                let offset = y * self.stride(0) + x * format.bytes_per_pixel();
+                format
+                    .channels()
+                    .map(|channel| sample(0, offset + channel) as f32 / 255.0)
+            }
+        }
+    }
+
+    // Linear light of every pixel in nits, with BT.709 primaries. The values
+    // can be negative for colors outside of BT.709.
+    fn linear_pixels(&self, sdr_white: f32) -> Vec<[f32; 3]> {
+        let colorimetry = self.colorimetry().unwrap_or_default();
+        let primaries = colorimetry.primaries.to_bt709();
+        let (width, height) = (self.width() as usize, self.height() as usize);
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let rgb = self.encoded_rgb(x, y);
+                let nits = colorimetry.transfer.to_nits(rgb, sdr_white);
+                pixels.push(multiply(&primaries, nits));
+            }
+        }
+        pixels
+    }
+
+    /// Maps the frame to an 8 bit sRGB frame that can be shown on SDR
+    /// displays, compressing highlights above SDR white.
+    ///
+    /// SDR frames are converted as they are.
+    pub fn tone_mapped(&self, mapping: ToneMapping) -> VideoFrame {
+        if !self.needs_linear_conversion() {
+            return self.clone().converted(super::PixelFormat::RGBA);
+        }
+
+        // The content peak relative to SDR white, which is mapped to 1. SDR
+        // half float frames are only clipped.
+        let peak = match self.is_hdr() {
+            true => mapping.peak / mapping.sdr_white,
+            false => 1.0,
+        };
+        let curve = |value: f32| {
+            if value <= KNEE || peak <= 1.0 {
+                return value.min(1.0);
+            }
+            // Extended Reinhard above the knee, continuous in value and
+            // slope, that reaches 1 at the peak
+            let t = (value - KNEE) / (1.0 - KNEE);
+            let max = (peak - KNEE) / (1.0 - KNEE);
+            let mapped = t * (1.0 + t / (max * max)) / (1.0 + t);
+            (KNEE + (1.0 - KNEE) * mapped).min(1.0)
+        };
+
+        let data = self
+            .linear_pixels(mapping.sdr_white)
+            .into_iter()
+            .flat_map(|nits| {
+                let rgb = nits.map(|value| value.max(0.0) / mapping.sdr_white);
+                // Scale all channels alike to keep the hue
+                let max = rgb[0].max(rgb[1]).max(rgb[2]);
+                let gain = if max > 0.0 { curve(max) / max } else { 0.0 };
+                let [r, g, b] =
+                    rgb.map(|value| (linear_to_srgb(value * gain) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect();
+
+        VideoFrame::RGBA(RGBAFrame {
+            display_time: self.display_time(),
+            timestamp: self.timestamp(),
+            sequence: self.sequence(),
+            repeated: self.is_repeated(),
+            width: self.width() as i32,
+            height: self.height() as i32,
+            data,
+            dirty_rects: self.dirty_rects().map(<[_]>::to_vec),
+        })
+    }
+
+    // Linear scRGB half float frame. SDR white ends up at the default
+    // tone mapping white.
+    pub(crate) fn to_rgba16f(&self) -> RGBA16FFrame {
+        let alpha = f32_to_f16(1.0).to_le_bytes();
+        let data = self
+            .linear_pixels(ToneMapping::default().sdr_white)
+            .into_iter()
+            .flat_map(|nits| {
+                let [r, g, b] = nits.map(|value| f32_to_f16(value / SCRGB_NITS).to_le_bytes());
+                [r, g, b, alpha]
+            })
+            .flatten()
+            .collect();
+
+        RGBA16FFrame {
+            display_time: self.display_time(),
+            timestamp: self.timestamp(),
+            sequence: self.sequence(),
+            repeated: self.is_repeated(),
+            width: self.width() as i32,
+            height: self.height() as i32,
+            data,
+            colorimetry: Colorimetry {
+                matrix: ColorMatrix::Bt709,
+                range: ColorRange::Full,
+                primaries: ColorPrimaries::Bt709,
+                transfer: TransferFunction::Linear,
+            },
+            dirty_rects: self.dirty_rects().map(<[_]>::to_vec),
+        }
+    }
+
+    // HDR10 frame: PQ with BT.2020 primaries and matrix in limited range
+    pub(crate) fn to_hdr10(&self) -> P010Frame {
+        let colorimetry = Colorimetry {
+            matrix: ColorMatrix::Bt2020,
+            range: ColorRange::Limited,
+            primaries: ColorPrimaries::Bt2020,
+            transfer: TransferFunction::Pq,
+        };
+        let (kr, kb) = colorimetry.matrix.coefficients();
+        let (kr, kb) = (kr as f32, kb as f32);
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+        let pixels: Vec<[f32; 3]> = self
+            .linear_pixels(ToneMapping::default().sdr_white)
+            .into_iter()
+            .map(|nits| multiply(&BT709_TO_BT2020, nits).map(|value| nits_to_pq(value.max(0.0))))
+            .collect();
+        let quantize = |value: f32, offset: f32, scale: f32| {
+            let value = (offset + value * scale).round().clamp(0.0, 1023.0) as u16;
+            (value << 6).to_le_bytes()
+        };
+
+        let luma = pixels
+            .iter()
+            .flat_map(|[r, g, b]| {
+                let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+                quantize(y, 64.0, 876.0)
+            })
+            .collect();
+
+        let mut chroma = Vec::with_capacity(chroma_width * chroma_height * 4);
+        for cy in 0..chroma_height {
+            for cx in 0..chroma_width {
+                // Average of the up to four pixels the sample covers
+                let mut sum = [0.0; 3];
+                let mut count = 0.0;
+                for y in cy * 2..(cy * 2 + 2).min(height) {
+                    for x in cx * 2..(cx * 2 + 2).min(width) {
+                        for (sum, value) in sum.iter_mut().zip(pixels[y * width + x]) {
+                            *sum += value;
+                        }
+                        count += 1.0;
+                    }
+                }
+                let [r, g, b] = sum.map(|sum| sum / count);
+                let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+                chroma.extend(quantize((b - y) / (2.0 * (1.0 - kb)), 512.0, 896.0));
+                chroma.extend(quantize((r - y) / (2.0 * (1.0 - kr)), 512.0, 896.0));
+            }
+        }
+
+        P010Frame {
+            display_time: self.display_time(),
+            timestamp: self.timestamp(),
+            sequence: self.sequence(),
+            repeated: self.is_repeated(),
+            width: width as i32,
+            height: height as i32,
+            luminance_bytes: luma,
+            luminance_stride: width as i32 * 2,
+            chrominance_bytes: chroma,
+            chrominance_stride: chroma_width as i32 * 4,
+            colorimetry,
+            dirty_rects: self.dirty_rects().map(<[_]>::to_vec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{PackedFormat, PixelFormat};
+
+    fn rgba16f_frame(pixels: &[[f32; 3]]) -> VideoFrame {
+        let data = pixels
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0].map(|value| f32_to_f16(value).to_le_bytes()))
+            .flatten()
+            .collect();
+        VideoFrame::packed(PixelFormat::RGBA16F, pixels.len() as i32, 1, data)
+    }
+
+    #[test]
+    fn test_half_float() {
+        for value in [0.0, 1.0, -2.5, 0.333, 65504.0, 6.0e-8, 1.0e-5] {
+            let half = f32_to_f16(value);
+            assert!((f16_to_f32(half) - value).abs() <= value.abs() / 1024.0 + 6.0e-8);
+        }
+        assert_eq!(f32_to_f16(1.0), 0x3C00);
+        assert_eq!(f32_to_f16(-2.0), 0xC000);
+        assert_eq!(f32_to_f16(1.0e6), 0x7C00);
+        // Halfway between 1 and the next half float rounds to even
+        assert_eq!(f32_to_f16(1.0 + 1.0 / 2048.0), 0x3C00);
+        assert!(f16_to_f32(0x7E00).is_nan());
+    }
+
+    #[test]
+    fn test_pq() {
+        assert!(pq_to_nits(0.0).abs() < 1e-3);
+        assert!((pq_to_nits(1.0) - 10000.0).abs() < 1.0);
+        // 100 nits is encoded at about 0.508
+        assert!((nits_to_pq(100.0) - 0.508).abs() < 1e-3);
+        for nits in [0.5, 100.0, 203.0, 1000.0, 4000.0] {
+            assert!((pq_to_nits(nits_to_pq(nits)) - nits).abs() < nits * 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_tone_mapped() {
+        // SDR white in scRGB at the default white, half as bright and red
+        // brighter than the peak
+        let white = 203.0 / SCRGB_NITS;
+        let frame = rgba16f_frame(&[[white; 3], [white * 0.5; 3], [100.0, 0.0, 0.0]]);
+        assert!(frame.is_hdr());
+
+        let VideoFrame::RGBA(mapped) = frame.tone_mapped(ToneMapping::default()) else {
+            panic!("tone mapping should give RGBA frames");
+        };
+        // SDR white is compressed a little to make room for highlights
+        assert_eq!(&mapped.data[..4], &[240, 240, 240, 255]);
+        // Below the knee values are only encoded as sRGB
+        assert_eq!(&mapped.data[4..8], &[188, 188, 188, 255]);
+        assert_eq!(&mapped.data[8..], &[255, 0, 0, 255]);
+
+        // Half float SDR frames are not compressed
+        let VideoFrame::RGBA16F(mut sdr) = rgba16f_frame(&[[1.0; 3]]) else {
+            unreachable!();
+        };
+        sdr.colorimetry.transfer = TransferFunction::Srgb;
+        let sdr = VideoFrame::RGBA16F(sdr);
+        assert!(!sdr.is_hdr());
+        assert_eq!(sdr.to_packed(PackedFormat::RGB), vec![255, 255, 255]);
+    }
+
+    #[test]
+    fn test_hdr10() {
+        let frame = rgba16f_frame(&[[203.0 / SCRGB_NITS; 3], [0.0; 3]]);
+        let hdr10 = VideoFrame::P010(frame.to_hdr10());
+        assert!(hdr10.is_hdr());
+
+        // Reference white is at 58% of the PQ range
+        let luma = read_u16(hdr10.data(0), 0) >> 6;
+        assert!((luma as f32 - (64.0 + 876.0 * 0.58)).abs() < 2.0);
+        assert_eq!(read_u16(hdr10.data(0), 2) >> 6, 64);
+
+        let VideoFrame::RGBA16F(back) = hdr10.converted(super::super::PixelFormat::RGBA16F) else {
+            panic!("conversion should give half float frames");
+        };
+        let red = f16_to_f32(u16::from_le_bytes([back.data[0], back.data[1]]));
+        assert!((red - 203.0 / SCRGB_NITS).abs() < 0.1);
+    }
+}
@@ -0,0 +1,581 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    crop_packed, BGRAFrame, BGRFrame, BGRxFrame, ColorMatrix, ColorPrimaries, ColorRange,
+    Colorimetry, DirtyRect, PixelFormat, RGBA16FFrame, RGBAFrame, RGBFrame, RGBxFrame,
+    TransferFunction, VideoFrame, XBGRFrame,
+};
+
+/// Recycles frame buffers, so that frames which have to be copied stop
+/// allocating once the pool is warmed up.
+///
+/// Buffers come back when the [PooledBuffer] holding them is dropped, or
+/// with [BufferPool::recycle] for those taken out of the pool, like the
+/// pixels of frames handed back through
+/// [Capturer::recycle](crate::capturer::Capturer::recycle).
+#[derive(Debug, Clone, Default)]
+pub struct BufferPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl BufferPool {
+    // Idle buffers kept around at most. Frames that are held on to get fresh
+    // buffers beyond that.
+    const MAX_IDLE: usize = 4;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty buffer with room for at least `capacity` bytes, which goes
+    /// back to the pool when dropped
+    pub fn take(&self, capacity: usize) -> PooledBuffer {
+        let mut data = self.buffers.lock().unwrap().pop().unwrap_or_default();
+        data.clear();
+        data.reserve(capacity);
+        PooledBuffer {
+            data,
+            pool: self.clone(),
+        }
+    }
+
+    /// Puts a buffer that was taken out of the pool back into it
+    pub fn recycle(&self, data: Vec<u8>) {
+        if data.capacity() == 0 {
+            return;
+        }
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < Self::MAX_IDLE {
+            buffers.push(data);
+        }
+    }
+
+    // Copies `data` into a buffer that is taken out of the pool
+    #[cfg_attr(target_os = "linux", allow(dead_code))]
+    pub(crate) fn copy(&self, data: &[u8]) -> Vec<u8> {
+        let mut buffer = self.take(data.len());
+        buffer.extend_from_slice(data);
+        buffer.into_vec()
+    }
+
+    /// Number of idle buffers in the pool
+    pub fn idle(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+}
+
+/// A buffer borrowed from a [BufferPool]
+pub struct PooledBuffer {
+    data: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl PooledBuffer {
+    /// Takes the buffer out of the pool for good
+    pub fn into_vec(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.data)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.recycle(std::mem::take(&mut self.data));
+    }
+}
+
+impl fmt::Debug for PooledBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuffer")
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+enum Storage {
+    // Memory of the backend, handed back by `release`
+    Borrowed {
+        data: *const u8,
+        len: usize,
+        release: Option<Box<dyn FnOnce() + Send>>,
+    },
+    Pooled {
+        buffer: PooledBuffer,
+        offset: usize,
+    },
+}
+
+/// A captured frame of packed pixels that are not owned by the frame itself.
+/// They either stay in the buffer of the backend, which is handed back when
+/// the lease is dropped, or live in a buffer of a [BufferPool] when they had
+/// to be copied.
+///
+/// Backends only have a few buffers, so leases should be dropped quickly.
+/// Capturing stalls while all of them are leased. The lease borrows the
+/// capturer it came from, which can't be stopped before the lease is gone.
+///
+/// Only the PipeWire backend hands out leases, through
+/// `RawCapturer::get_next_frame_lease`. On macOS the sample buffers of
+/// ScreenCaptureKit are available through `RawCapturer::get_next_sample_buffer`
+/// instead. Frames that have to be copied, which are all of them on Windows,
+/// are copied into buffers of a [BufferPool].
+pub struct FrameLease<'a> {
+    timestamp: Duration,
+    display_time: SystemTime,
+    sequence: u64,
+    width: u32,
+    height: u32,
+    stride: usize,
+    format: PixelFormat,
+    colorimetry: Option<Colorimetry>,
+    dirty_rects: Option<Vec<DirtyRect>>,
+    storage: Storage,
+    _capturer: PhantomData<&'a ()>,
+}
+
+// The borrowed memory is only read and stays valid until it is released
+unsafe impl Send for FrameLease<'_> {}
+
+fn bytes_per_pixel(format: PixelFormat) -> usize {
+    match format {
+        PixelFormat::RGBA16F => 8,
+        _ => format
+            .packed_format()
+            .expect("Leased frames hold packed pixels")
+            .bytes_per_pixel(),
+    }
+}
+
+// Leases are only handed out by the PipeWire backend so far
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+impl FrameLease<'static> {
+    /// Leases `height` rows of `stride` bytes at `data`, calling `release`
+    /// when the lease is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `data` must stay valid and unchanged until `release` is called.
+    pub(crate) unsafe fn borrowed(
+        data: *const u8,
+        stride: usize,
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        timestamp: Duration,
+        release: impl FnOnce() + Send + 'static,
+    ) -> Self {
+        // The last row does not have to be padded to the stride
+        let len = match height {
+            0 => 0,
+            _ => stride * (height as usize - 1) + width as usize * bytes_per_pixel(format),
+        };
+        Self::new(
+            Storage::Borrowed {
+                data,
+                len,
+                release: Some(Box::new(release)),
+            },
+            stride,
+            width,
+            height,
+            format,
+            timestamp,
+        )
+    }
+
+    /// Leases the pixels starting at `offset` in `buffer`, with `stride`
+    /// bytes per row
+    pub(crate) fn pooled(
+        buffer: PooledBuffer,
+        offset: usize,
+        stride: usize,
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        timestamp: Duration,
+    ) -> Self {
+        Self::new(
+            Storage::Pooled { buffer, offset },
+            stride,
+            width,
+            height,
+            format,
+            timestamp,
+        )
+    }
+
+    fn new(
+        storage: Storage,
+        stride: usize,
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        timestamp: Duration,
+    ) -> Self {
+        FrameLease {
+            timestamp,
+            display_time: SystemTime::UNIX_EPOCH + timestamp,
+            sequence: 0,
+            width,
+            height,
+            stride,
+            format,
+            colorimetry: None,
+            dirty_rects: None,
+            storage,
+            _capturer: PhantomData,
+        }
+    }
+}
+
+impl FrameLease<'_> {
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn with_colorimetry(mut self, colorimetry: Colorimetry) -> Self {
+        self.colorimetry = Some(colorimetry);
+        self
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn with_dirty_rects(mut self, dirty_rects: Option<Vec<DirtyRect>>) -> Self {
+        self.dirty_rects = dirty_rects;
+        self
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn set_time(&mut self, timestamp: Duration, display_time: SystemTime) {
+        self.timestamp = timestamp;
+        self.display_time = display_time;
+    }
+
+    /// Copies borrowed pixels into a buffer of `pool` and hands the memory
+    /// back to the backend, for leases that outlive the stream
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn detach(&mut self, pool: &BufferPool) {
+        if !self.is_borrowed() {
+            return;
+        }
+        let mut buffer = pool.take(self.data().len());
+        buffer.extend_from_slice(self.data());
+        let borrowed = std::mem::replace(&mut self.storage, Storage::Pooled { buffer, offset: 0 });
+        if let Storage::Borrowed {
+            release: Some(release),
+            ..
+        } = borrowed
+        {
+            release();
+        }
+    }
+
+    // Whether the pixels are still in the memory of the backend
+    pub(crate) fn is_borrowed(&self) -> bool {
+        matches!(self.storage, Storage::Borrowed { .. })
+    }
+
+    /// Time since the capture started, see [VideoFrame::timestamp]
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// Wall clock time the frame was captured at
+    pub fn display_time(&self) -> SystemTime {
+        self.display_time
+    }
+
+    /// Number of the frame in the video of the capture, see
+    /// [VideoFrame::sequence]
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    /// Bytes from the start of one row to the next
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// How the pixels are encoded. None for 8 bit RGB frames, which are sRGB.
+    pub fn colorimetry(&self) -> Option<Colorimetry> {
+        self.colorimetry
+    }
+
+    /// Regions that changed since the previous frame, if the backend reports
+    /// them
+    pub fn dirty_rects(&self) -> Option<&[DirtyRect]> {
+        self.dirty_rects.as_deref()
+    }
+
+    /// The pixels, starting at the first one. The last row is not padded to
+    /// the stride.
+    pub fn data(&self) -> &[u8] {
+        let row_size = self.width as usize * bytes_per_pixel(self.format);
+        match &self.storage {
+            Storage::Borrowed { data, len, .. } => unsafe {
+                std::slice::from_raw_parts(*data, *len)
+            },
+            Storage::Pooled { buffer, offset } => {
+                let len = match self.height {
+                    0 => 0,
+                    height => self.stride * (height as usize - 1) + row_size,
+                };
+                &buffer[*offset..*offset + len]
+            }
+        }
+    }
+
+    /// Copies the frame into a [VideoFrame]
+    pub fn to_video_frame(&self) -> VideoFrame {
+        let data = crop_packed(
+            self.data(),
+            self.stride,
+            bytes_per_pixel(self.format),
+            0,
+            0,
+            self.width as usize,
+            self.height as usize,
+        );
+        self.video_frame(data)
+    }
+
+    /// Turns the lease into a [VideoFrame], which only copies the pixels if
+    /// they are not already tightly packed in a pooled buffer
+    pub fn into_video_frame(mut self) -> VideoFrame {
+        let row_size = self.width as usize * bytes_per_pixel(self.format);
+        let tight =
+            matches!(self.storage, Storage::Pooled { offset: 0, .. }) && self.stride == row_size;
+        if !tight {
+            return self.to_video_frame();
+        }
+
+        let empty = Storage::Borrowed {
+            data: std::ptr::NonNull::dangling().as_ptr(),
+            len: 0,
+            release: None,
+        };
+        let Storage::Pooled { buffer, .. } = std::mem::replace(&mut self.storage, empty) else {
+            unreachable!();
+        };
+        let mut data = buffer.into_vec();
+        data.truncate(row_size * self.height as usize);
+        self.video_frame(data)
+    }
+
+    fn video_frame(&self, data: Vec<u8>) -> VideoFrame {
+        let timestamp = self.timestamp;
+        let display_time = self.display_time;
+        let sequence = self.sequence;
+        let width = self.width as i32;
+        let height = self.height as i32;
+        let dirty_rects = self.dirty_rects.clone();
+
+        macro_rules! packed_frame {
+            ($variant:ident, $frame:ident) => {
+                VideoFrame::$variant($frame {
+                    display_time,
+                    timestamp,
+                    sequence,
+                    repeated: false,
+                    width,
+                    height,
+                    data,
+                    dirty_rects,
+                })
+            };
+        }
+
+        match self.format {
+            PixelFormat::RGB => packed_frame!(RGB, RGBFrame),
+            PixelFormat::RGBA => packed_frame!(RGBA, RGBAFrame),
+            PixelFormat::RGBx => packed_frame!(RGBx, RGBxFrame),
+            PixelFormat::XBGR => packed_frame!(XBGR, XBGRFrame),
+            PixelFormat::BGRx => packed_frame!(BGRx, BGRxFrame),
+            PixelFormat::BGR => packed_frame!(BGR0, BGRFrame),
+            PixelFormat::BGRA => packed_frame!(BGRA, BGRAFrame),
+            PixelFormat::RGBA16F => VideoFrame::RGBA16F(RGBA16FFrame {
+                display_time,
+                timestamp,
+                sequence,
+                repeated: false,
+                width,
+                height,
+                data,
+                colorimetry: self.colorimetry.unwrap_or(Colorimetry {
+                    matrix: ColorMatrix::Bt709,
+                    range: ColorRange::Full,
+                    primaries: ColorPrimaries::Bt709,
+                    transfer: TransferFunction::Linear,
+                }),
+                dirty_rects,
+            }),
+            PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::P010 => {
+                unreachable!("Leased frames hold packed pixels")
+            }
+        }
+    }
+}
+
+impl Drop for FrameLease<'_> {
+    fn drop(&mut self) {
+        if let Storage::Borrowed { release, .. } = &mut self.storage {
+            if let Some(release) = release.take() {
+                release();
+            }
+        }
+    }
+}
+
+impl fmt::Debug for FrameLease<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FrameLease")
+            .field("timestamp", &self.timestamp)
+            .field("sequence", &self.sequence)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("stride", &self.stride)
+            .field("format", &self.format)
+            .field(
+                "borrowed",
+                &matches!(self.storage, Storage::Borrowed { .. }),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = BufferPool::new();
+        let mut buffer = pool.take(16);
+        buffer.extend_from_slice(&[1, 2, 3]);
+        let address = buffer.as_ptr();
+        drop(buffer);
+        assert_eq!(pool.idle(), 1);
+
+        // The buffer comes back empty
+        let buffer = pool.take(8);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr(), address);
+        assert_eq!(pool.idle(), 0);
+
+        // Buffers taken out of the pool only come back when recycled
+        let data = buffer.into_vec();
+        assert_eq!(pool.idle(), 0);
+        pool.recycle(data);
+        let data = pool.copy(&[4, 5]);
+        assert_eq!((data.as_ptr(), &data[..]), (address, &[4, 5][..]));
+
+        let buffers: Vec<_> = (0..6).map(|_| pool.take(1)).collect();
+        drop(buffers);
+        assert_eq!(pool.idle(), BufferPool::MAX_IDLE);
+    }
+
+    #[test]
+    fn test_borrowed_lease() {
+        static RELEASED: AtomicBool = AtomicBool::new(false);
+
+        // 2x2 RGB pixels with a stride of 8 bytes
+        let data = [1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0];
+        let lease = unsafe {
+            FrameLease::borrowed(
+                data.as_ptr(),
+                8,
+                2,
+                2,
+                PixelFormat::RGB,
+                Duration::ZERO,
+                || RELEASED.store(true, Ordering::Relaxed),
+            )
+        };
+        assert_eq!(lease.data().len(), 14);
+
+        let frame = lease.to_video_frame();
+        assert_eq!(frame.data(0), &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        assert!(!RELEASED.load(Ordering::Relaxed));
+        drop(lease);
+        assert!(RELEASED.load(Ordering::Relaxed));
+
+        // Detached leases keep their pixels after handing the memory back
+        let released = Arc::new(AtomicBool::new(false));
+        let mut lease = unsafe {
+            let released = released.clone();
+            FrameLease::borrowed(
+                data.as_ptr(),
+                8,
+                2,
+                2,
+                PixelFormat::RGB,
+                Duration::ZERO,
+                move || released.store(true, Ordering::Relaxed),
+            )
+        };
+        lease.detach(&BufferPool::new());
+        assert!(released.load(Ordering::Relaxed));
+        assert!(!lease.is_borrowed());
+        assert_eq!(
+            lease.into_video_frame().data(0),
+            &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
+        );
+    }
+
+    #[test]
+    fn test_pooled_lease() {
+        let pool = BufferPool::new();
+        let mut buffer = pool.take(8);
+        buffer.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let address = buffer.as_ptr();
+
+        let lease = FrameLease::pooled(buffer, 0, 4, 1, 2, PixelFormat::BGRA, Duration::ZERO);
+        let VideoFrame::BGRA(frame) = lease.into_video_frame() else {
+            panic!("lease should give a BGRA frame");
+        };
+        // Tightly packed pixels are not copied
+        assert_eq!(frame.data.as_ptr(), address);
+        assert_eq!(frame.data, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // A cropped view is copied and the buffer returned
+        let mut buffer = pool.take(8);
+        buffer.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        let lease = FrameLease::pooled(buffer, 4, 8, 1, 1, PixelFormat::BGRA, Duration::ZERO);
+        assert_eq!(lease.into_video_frame().data(0), &[5, 6, 7, 8]);
+        assert_eq!(pool.idle(), 1);
+    }
+}
@@ -1,10 +1,64 @@
+use std::time::{Duration, SystemTime};
+
 mod audio;
+mod convert;
+#[cfg(feature = "image")]
+mod dynamic_image;
+#[cfg(feature = "encoders")]
+mod encode;
+mod hdr;
+mod lease;
+mod scale;
+mod sequence;
+mod simd;
+mod transform;
 mod video;
 
 pub use audio::*;
+pub use convert::*;
+#[cfg(feature = "image")]
+pub use dynamic_image::*;
+pub use hdr::*;
+pub use lease::*;
+pub use scale::*;
+pub use sequence::*;
+pub use transform::*;
 pub use video::*;
 
 pub enum Frame {
     Audio(AudioFrame),
     Video(VideoFrame),
 }
+
+impl Frame {
+    /// Time since the capture started, on the clock shared by audio and video
+    pub fn timestamp(&self) -> Duration {
+        match self {
+            Frame::Audio(frame) => frame.timestamp(),
+            Frame::Video(frame) => frame.timestamp(),
+        }
+    }
+
+    /// Wall clock time the frame was captured at
+    pub fn display_time(&self) -> SystemTime {
+        match self {
+            Frame::Audio(frame) => frame.time(),
+            Frame::Video(frame) => frame.display_time(),
+        }
+    }
+
+    /// Number of the frame within the audio or the video of the capture
+    pub fn sequence(&self) -> u64 {
+        match self {
+            Frame::Audio(frame) => frame.sequence(),
+            Frame::Video(frame) => frame.sequence(),
+        }
+    }
+
+    pub(crate) fn set_time(&mut self, timestamp: Duration, display_time: SystemTime) {
+        match self {
+            Frame::Audio(frame) => frame.set_time(timestamp, display_time),
+            Frame::Video(frame) => frame.set_time(timestamp, display_time),
+        }
+    }
+}
@@ -0,0 +1,772 @@
+use std::ops::AddAssign;
+
+use super::{f16_to_f32, f32_to_f16, pad_packed, ColorRange, Colorimetry, DirtyRect, VideoFrame};
+
+/// Filter used when resizing frames in software
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleFilter {
+    /// Picks the closest source pixel. Fastest, but aliases badly.
+    Nearest,
+    /// Interpolates between the four closest source pixels
+    Bilinear,
+    /// Averages every source pixel covered by the output pixel. Best suited
+    /// for downscaling screen content such as text.
+    #[default]
+    Area,
+}
+
+/// How frames are fitted into an output size with a different aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    /// Scales both axes independently, distorting the image
+    #[default]
+    Stretch,
+    /// Scales the whole frame to fit and fills the remaining area with black
+    /// bars (letterboxing)
+    Fit,
+    /// Scales the frame to cover the output and crops what overflows
+    Fill,
+}
+
+impl ScaleMode {
+    /// Size a `size` frame is scaled to before being letterboxed or cropped
+    /// to `output_size`
+    pub fn content_size(self, size: (u32, u32), output_size: (u32, u32)) -> (u32, u32) {
+        let scale_x = output_size.0 as f64 / size.0 as f64;
+        let scale_y = output_size.1 as f64 / size.1 as f64;
+        let scale = match self {
+            ScaleMode::Stretch => return output_size,
+            ScaleMode::Fit => scale_x.min(scale_y),
+            ScaleMode::Fill => scale_x.max(scale_y),
+        };
+
+        let width = (size.0 as f64 * scale).round() as u32;
+        let height = (size.1 as f64 * scale).round() as u32;
+        match self {
+            ScaleMode::Fit => (
+                width.clamp(1, output_size.0),
+                height.clamp(1, output_size.1),
+            ),
+            _ => (width.max(output_size.0), height.max(output_size.1)),
+        }
+    }
+}
+
+// Largest pixel size any of the frame formats use
+const MAX_BYTES_PER_PIXEL: usize = 8;
+
+// A channel value the filters work on
+trait Sample: Copy + Default {
+    // Type the area filter sums samples in
+    type Sum: Copy + Default + AddAssign;
+
+    fn widen(self) -> Self::Sum;
+
+    fn average(sum: Self::Sum, count: usize) -> Self;
+
+    // Interpolates between the top and bottom pairs of samples, with the
+    // weights of the second sample out of 256
+    fn bilinear(top: [Self; 2], bottom: [Self; 2], wx: u32, wy: u32) -> Self;
+}
+
+macro_rules! impl_integer_sample {
+    ($type:ty) => {
+        impl Sample for $type {
+            type Sum = u64;
+
+            fn widen(self) -> u64 {
+                self as u64
+            }
+
+            fn average(sum: u64, count: usize) -> Self {
+                let count = count as u64;
+                ((sum + count / 2) / count) as $type
+            }
+
+            fn bilinear(top: [Self; 2], bottom: [Self; 2], wx: u32, wy: u32) -> Self {
+                let lerp = |[a, b]: [Self; 2]| a as u32 * (256 - wx) + b as u32 * wx;
+                ((lerp(top) * (256 - wy) + lerp(bottom) * wy + (1 << 15)) >> 16) as $type
+            }
+        }
+    };
+}
+
+impl_integer_sample!(u8);
+impl_integer_sample!(u16);
+
+impl Sample for f32 {
+    type Sum = f32;
+
+    fn widen(self) -> f32 {
+        self
+    }
+
+    fn average(sum: f32, count: usize) -> Self {
+        sum / count as f32
+    }
+
+    fn bilinear(top: [Self; 2], bottom: [Self; 2], wx: u32, wy: u32) -> Self {
+        let (wx, wy) = (wx as f32 / 256.0, wy as f32 / 256.0);
+        let lerp = |[a, b]: [Self; 2]| a + (b - a) * wx;
+        lerp(top) + (lerp(bottom) - lerp(top)) * wy
+    }
+}
+
+/// Resizes a packed frame with `stride` bytes per row from `size` to
+/// `output_size` pixels, returning a tightly packed buffer.
+///
+/// Every byte of a pixel is treated as an independent 8 bit channel, so this
+/// works for any packed RGB format as well as the individual planes of a YUV
+/// frame.
+pub fn scale_packed(
+    data: &[u8],
+    stride: usize,
+    bytes_per_pixel: usize,
+    size: (usize, usize),
+    output_size: (usize, usize),
+    filter: ScaleFilter,
+) -> Vec<u8> {
+    scale_samples(data, stride, bytes_per_pixel, size, output_size, filter)
+}
+
+// Like `scale_packed`, for planes of 16 bit little endian samples with
+// `stride` bytes per row. `float` marks half float samples.
+fn scale_wide(
+    data: &[u8],
+    stride: usize,
+    samples_per_pixel: usize,
+    size: (usize, usize),
+    output_size: (usize, usize),
+    filter: ScaleFilter,
+) -> Vec<u8> {
+    let samples: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect();
+    scale_samples(
+        &samples,
+        stride / 2,
+        samples_per_pixel,
+        size,
+        output_size,
+        filter,
+    )
+    .into_iter()
+    .flat_map(u16::to_le_bytes)
+    .collect()
+}
+
+// Like `scale_packed`, for half float RGBA with `stride` bytes per row
+fn scale_half_float(
+    data: &[u8],
+    stride: usize,
+    size: (usize, usize),
+    output_size: (usize, usize),
+    filter: ScaleFilter,
+) -> Vec<u8> {
+    let samples: Vec<f32> = data
+        .chunks_exact(2)
+        .map(|bytes| f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])))
+        .collect();
+    scale_samples(&samples, stride / 2, 4, size, output_size, filter)
+        .into_iter()
+        .flat_map(|value| f32_to_f16(value).to_le_bytes())
+        .collect()
+}
+
+fn scale_samples<T: Sample>(
+    data: &[T],
+    stride: usize,
+    bytes_per_pixel: usize,
+    size: (usize, usize),
+    output_size: (usize, usize),
+    filter: ScaleFilter,
+) -> Vec<T> {
+    assert!(bytes_per_pixel <= MAX_BYTES_PER_PIXEL);
+
+    let (width, height) = size;
+    let (out_width, out_height) = output_size;
+    let mut output = vec![T::default(); out_width * out_height * bytes_per_pixel];
+    if width == 0 || height == 0 || out_width == 0 || out_height == 0 {
+        return output;
+    }
+
+    match filter {
+        ScaleFilter::Nearest => scale_nearest(
+            data,
+            stride,
+            bytes_per_pixel,
+            size,
+            output_size,
+            &mut output,
+        ),
+        ScaleFilter::Bilinear => scale_bilinear(
+            data,
+            stride,
+            bytes_per_pixel,
+            size,
+            output_size,
+            &mut output,
+        ),
+        ScaleFilter::Area => scale_area(
+            data,
+            stride,
+            bytes_per_pixel,
+            size,
+            output_size,
+            &mut output,
+        ),
+    }
+
+    output
+}
+
+fn scale_nearest<T: Sample>(
+    data: &[T],
+    stride: usize,
+    bpp: usize,
+    (width, height): (usize, usize),
+    (out_width, out_height): (usize, usize),
+    output: &mut [T],
+) {
+    let columns: Vec<usize> = (0..out_width)
+        .map(|x| ((2 * x + 1) * width / (2 * out_width)) * bpp)
+        .collect();
+
+    for (y, row) in output.chunks_exact_mut(out_width * bpp).enumerate() {
+        let src_row = &data[((2 * y + 1) * height / (2 * out_height)) * stride..];
+        for (dst, &src) in row.chunks_exact_mut(bpp).zip(&columns) {
+            dst.copy_from_slice(&src_row[src..src + bpp]);
+        }
+    }
+}
+
+// Source positions and 8 bit weights of the second sample, for every output
+// position along one axis
+fn bilinear_taps(size: usize, out_size: usize) -> Vec<(usize, usize, u32)> {
+    (0..out_size)
+        .map(|i| {
+            let position = ((i as f64 + 0.5) * size as f64 / out_size as f64 - 0.5)
+                .clamp(0.0, (size - 1) as f64);
+            let first = position.floor() as usize;
+            let second = (first + 1).min(size - 1);
+            let weight = ((position - first as f64) * 256.0).round() as u32;
+            (first, second, weight)
+        })
+        .collect()
+}
+
+fn scale_bilinear<T: Sample>(
+    data: &[T],
+    stride: usize,
+    bpp: usize,
+    (width, height): (usize, usize),
+    (out_width, out_height): (usize, usize),
+    output: &mut [T],
+) {
+    let columns = bilinear_taps(width, out_width);
+    let rows = bilinear_taps(height, out_height);
+
+    for (row, &(y0, y1, wy)) in output.chunks_exact_mut(out_width * bpp).zip(&rows) {
+        let top = &data[y0 * stride..];
+        let bottom = &data[y1 * stride..];
+        for (dst, &(x0, x1, wx)) in row.chunks_exact_mut(bpp).zip(&columns) {
+            for (c, value) in dst.iter_mut().enumerate() {
+                let pair = |row: &[T]| [row[x0 * bpp + c], row[x1 * bpp + c]];
+                *value = T::bilinear(pair(top), pair(bottom), wx, wy);
+            }
+        }
+    }
+}
+
+// Range of source positions covered by every output position along one axis
+fn area_spans(size: usize, out_size: usize) -> Vec<(usize, usize)> {
+    (0..out_size)
+        .map(|i| {
+            let start = i * size / out_size;
+            let end = ((i + 1) * size).div_ceil(out_size).clamp(start + 1, size);
+            (start, end)
+        })
+        .collect()
+}
+
+fn scale_area<T: Sample>(
+    data: &[T],
+    stride: usize,
+    bpp: usize,
+    (width, height): (usize, usize),
+    (out_width, out_height): (usize, usize),
+    output: &mut [T],
+) {
+    let columns = area_spans(width, out_width);
+    let rows = area_spans(height, out_height);
+
+    for (row, &(y0, y1)) in output.chunks_exact_mut(out_width * bpp).zip(&rows) {
+        for (dst, &(x0, x1)) in row.chunks_exact_mut(bpp).zip(&columns) {
+            let mut sums = [T::Sum::default(); MAX_BYTES_PER_PIXEL];
+            for src_row in data[y0 * stride..].chunks(stride).take(y1 - y0) {
+                for pixel in src_row[x0 * bpp..x1 * bpp].chunks_exact(bpp) {
+                    for (sum, &value) in sums.iter_mut().zip(pixel) {
+                        *sum += value.widen();
+                    }
+                }
+            }
+
+            let count = (x1 - x0) * (y1 - y0);
+            for (value, sum) in dst.iter_mut().zip(sums) {
+                *value = T::average(sum, count);
+            }
+        }
+    }
+}
+
+// Maps a dirty rectangle onto the scaled frame, growing it to whole pixels
+fn scale_rect(rect: DirtyRect, size: (usize, usize), output_size: (usize, usize)) -> DirtyRect {
+    let scale_x = output_size.0 as f64 / size.0 as f64;
+    let scale_y = output_size.1 as f64 / size.1 as f64;
+    let x0 = (rect.x as f64 * scale_x).floor() as i32;
+    let y0 = (rect.y as f64 * scale_y).floor() as i32;
+    let x1 = ((rect.x + rect.width) as f64 * scale_x).ceil() as i32;
+    let y1 = ((rect.y + rect.height) as f64 * scale_y).ceil() as i32;
+
+    DirtyRect {
+        x: x0,
+        y: y0,
+        width: x1 - x0,
+        height: y1 - y0,
+    }
+}
+
+// Luma value of black in the frame's range
+fn black_luma(colorimetry: Colorimetry) -> u8 {
+    match colorimetry.range {
+        ColorRange::Limited => 16,
+        ColorRange::Full => 0,
+    }
+}
+
+fn scale_rects(
+    rects: &Option<Vec<DirtyRect>>,
+    size: (usize, usize),
+    output_size: (usize, usize),
+) -> Option<Vec<DirtyRect>> {
+    rects.as_ref().map(|rects| {
+        rects
+            .iter()
+            .map(|rect| scale_rect(*rect, size, output_size))
+            .collect()
+    })
+}
+
+impl VideoFrame {
+    /// Resizes the frame to `width`x`height` pixels
+    pub fn scaled(self, width: u32, height: u32, filter: ScaleFilter) -> VideoFrame {
+        let output_size = (width as usize, height as usize);
+
+        macro_rules! scale_packed_frame {
+            ($variant:ident, $frame:expr, $bpp:expr) => {{
+                let mut frame = $frame;
+                let size = (frame.width as usize, frame.height as usize);
+                if size == output_size {
+                    return VideoFrame::$variant(frame);
+                }
+                frame.data =
+                    scale_packed(&frame.data, size.0 * $bpp, $bpp, size, output_size, filter);
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = scale_rects(&frame.dirty_rects, size, output_size);
+                VideoFrame::$variant(frame)
+            }};
+        }
+
+        // NV12 style frames store both chroma components interleaved at half
+        // resolution, every sample taking `$sample_size` bytes
+        macro_rules! scale_semi_planar_frame {
+            ($variant:ident, $frame:expr, $scale:ident, $sample_size:expr) => {{
+                let mut frame = $frame;
+                let size = (frame.width as usize, frame.height as usize);
+                if size == output_size {
+                    return VideoFrame::$variant(frame);
+                }
+                let chroma_size = (size.0.div_ceil(2), size.1.div_ceil(2));
+                let output_chroma_size = (output_size.0.div_ceil(2), output_size.1.div_ceil(2));
+
+                frame.luminance_bytes = $scale(
+                    &frame.luminance_bytes,
+                    frame.luminance_stride as usize,
+                    1,
+                    size,
+                    output_size,
+                    filter,
+                );
+                frame.luminance_stride = (output_size.0 * $sample_size) as i32;
+                frame.chrominance_bytes = $scale(
+                    &frame.chrominance_bytes,
+                    frame.chrominance_stride as usize,
+                    2,
+                    chroma_size,
+                    output_chroma_size,
+                    filter,
+                );
+                frame.chrominance_stride = (output_chroma_size.0 * 2 * $sample_size) as i32;
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = scale_rects(&frame.dirty_rects, size, output_size);
+                VideoFrame::$variant(frame)
+            }};
+        }
+
+        match self {
+            VideoFrame::YUVFrame(frame) => {
+                scale_semi_planar_frame!(YUVFrame, frame, scale_packed, 1)
+            }
+            VideoFrame::P010(frame) => scale_semi_planar_frame!(P010, frame, scale_wide, 2),
+            VideoFrame::I420(mut frame) => {
+                let size = (frame.width as usize, frame.height as usize);
+                if size == output_size {
+                    return VideoFrame::I420(frame);
+                }
+                let chroma_size = (size.0.div_ceil(2), size.1.div_ceil(2));
+                let output_chroma_size = (output_size.0.div_ceil(2), output_size.1.div_ceil(2));
+
+                frame.y_bytes = scale_packed(
+                    &frame.y_bytes,
+                    frame.y_stride as usize,
+                    1,
+                    size,
+                    output_size,
+                    filter,
+                );
+                frame.y_stride = width as i32;
+                frame.u_bytes = scale_packed(
+                    &frame.u_bytes,
+                    frame.u_stride as usize,
+                    1,
+                    chroma_size,
+                    output_chroma_size,
+                    filter,
+                );
+                frame.u_stride = output_chroma_size.0 as i32;
+                frame.v_bytes = scale_packed(
+                    &frame.v_bytes,
+                    frame.v_stride as usize,
+                    1,
+                    chroma_size,
+                    output_chroma_size,
+                    filter,
+                );
+                frame.v_stride = output_chroma_size.0 as i32;
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = scale_rects(&frame.dirty_rects, size, output_size);
+                VideoFrame::I420(frame)
+            }
+            VideoFrame::RGB(frame) => scale_packed_frame!(RGB, frame, 3),
+            VideoFrame::RGBA(frame) => scale_packed_frame!(RGBA, frame, 4),
+            VideoFrame::RGBx(frame) => scale_packed_frame!(RGBx, frame, 4),
+            VideoFrame::XBGR(frame) => scale_packed_frame!(XBGR, frame, 4),
+            VideoFrame::BGRx(frame) => scale_packed_frame!(BGRx, frame, 4),
+            VideoFrame::BGR0(frame) => scale_packed_frame!(BGR0, frame, 3),
+            VideoFrame::BGRA(frame) => scale_packed_frame!(BGRA, frame, 4),
+            VideoFrame::RGBA16F(mut frame) => {
+                let size = (frame.width as usize, frame.height as usize);
+                if size == output_size {
+                    return VideoFrame::RGBA16F(frame);
+                }
+                frame.data = scale_half_float(&frame.data, size.0 * 8, size, output_size, filter);
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = scale_rects(&frame.dirty_rects, size, output_size);
+                VideoFrame::RGBA16F(frame)
+            }
+        }
+    }
+}
+
+impl VideoFrame {
+    /// Resizes the frame to exactly `width`x`height` pixels, using `mode` to
+    /// deal with a different aspect ratio
+    pub fn resized(
+        self,
+        width: u32,
+        height: u32,
+        filter: ScaleFilter,
+        mode: ScaleMode,
+    ) -> VideoFrame {
+        let size = self.size();
+        if size == (width, height) || size.0 == 0 || size.1 == 0 {
+            return self;
+        }
+
+        match mode {
+            ScaleMode::Stretch => self.scaled(width, height, filter),
+            ScaleMode::Fit => {
+                let (content_width, content_height) = mode.content_size(size, (width, height));
+                self.scaled(content_width, content_height, filter)
+                    .padded(width, height)
+            }
+            ScaleMode::Fill => {
+                // Crop the part of the frame that has the output aspect ratio
+                let (content_width, content_height) = mode.content_size(size, (width, height));
+                let crop_width = (width as u64 * size.0 as u64 / content_width as u64) as u32;
+                let crop_height = (height as u64 * size.1 as u64 / content_height as u64) as u32;
+                self.cropped(
+                    (size.0 - crop_width.min(size.0)) / 2,
+                    (size.1 - crop_height.min(size.1)) / 2,
+                    crop_width.max(1),
+                    crop_height.max(1),
+                )
+                .scaled(width, height, filter)
+            }
+        }
+    }
+
+    // Centers the frame in a `width`x`height` frame with black borders
+    fn padded(self, width: u32, height: u32) -> VideoFrame {
+        let size = (self.size().0 as usize, self.size().1 as usize);
+        let output_size = (width as usize, height as usize);
+        // Keep the offset even so YUV chroma planes stay aligned
+        let offset = (
+            (output_size.0.saturating_sub(size.0) / 2) & !1,
+            (output_size.1.saturating_sub(size.1) / 2) & !1,
+        );
+        let offset_rects = |rects: Option<Vec<DirtyRect>>| {
+            rects.map(|rects| {
+                rects
+                    .into_iter()
+                    .map(|rect| DirtyRect {
+                        x: rect.x + offset.0 as i32,
+                        y: rect.y + offset.1 as i32,
+                        ..rect
+                    })
+                    .collect()
+            })
+        };
+
+        macro_rules! pad_packed_frame {
+            ($variant:ident, $frame:expr, $fill:expr) => {{
+                let mut frame = $frame;
+                frame.data = pad_packed(&frame.data, size, output_size, offset, &$fill);
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = offset_rects(frame.dirty_rects);
+                VideoFrame::$variant(frame)
+            }};
+        }
+
+        match self {
+            VideoFrame::YUVFrame(mut frame) => {
+                let black = black_luma(frame.colorimetry);
+                frame.luminance_bytes =
+                    pad_packed(&frame.luminance_bytes, size, output_size, offset, &[black]);
+                frame.luminance_stride = width as i32;
+                frame.chrominance_bytes = pad_packed(
+                    &frame.chrominance_bytes,
+                    (size.0.div_ceil(2), size.1.div_ceil(2)),
+                    (output_size.0.div_ceil(2), output_size.1.div_ceil(2)),
+                    (offset.0 / 2, offset.1 / 2),
+                    &[128, 128],
+                );
+                frame.chrominance_stride = (output_size.0.div_ceil(2) * 2) as i32;
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = offset_rects(frame.dirty_rects);
+                VideoFrame::YUVFrame(frame)
+            }
+            VideoFrame::P010(mut frame) => {
+                let black = (black_luma(frame.colorimetry) as u16) << 8;
+                frame.luminance_bytes = pad_packed(
+                    &frame.luminance_bytes,
+                    size,
+                    output_size,
+                    offset,
+                    &black.to_le_bytes(),
+                );
+                frame.luminance_stride = (output_size.0 * 2) as i32;
+                frame.chrominance_bytes = pad_packed(
+                    &frame.chrominance_bytes,
+                    (size.0.div_ceil(2), size.1.div_ceil(2)),
+                    (output_size.0.div_ceil(2), output_size.1.div_ceil(2)),
+                    (offset.0 / 2, offset.1 / 2),
+                    &[0, 128, 0, 128],
+                );
+                frame.chrominance_stride = (output_size.0.div_ceil(2) * 4) as i32;
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = offset_rects(frame.dirty_rects);
+                VideoFrame::P010(frame)
+            }
+            VideoFrame::I420(mut frame) => {
+                let black = black_luma(frame.colorimetry);
+                let chroma_size = (size.0.div_ceil(2), size.1.div_ceil(2));
+                let output_chroma_size = (output_size.0.div_ceil(2), output_size.1.div_ceil(2));
+                let chroma_offset = (offset.0 / 2, offset.1 / 2);
+                frame.y_bytes = pad_packed(&frame.y_bytes, size, output_size, offset, &[black]);
+                frame.y_stride = width as i32;
+                frame.u_bytes = pad_packed(
+                    &frame.u_bytes,
+                    chroma_size,
+                    output_chroma_size,
+                    chroma_offset,
+                    &[128],
+                );
+                frame.u_stride = output_chroma_size.0 as i32;
+                frame.v_bytes = pad_packed(
+                    &frame.v_bytes,
+                    chroma_size,
+                    output_chroma_size,
+                    chroma_offset,
+                    &[128],
+                );
+                frame.v_stride = output_chroma_size.0 as i32;
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = offset_rects(frame.dirty_rects);
+                VideoFrame::I420(frame)
+            }
+            VideoFrame::RGB(frame) => pad_packed_frame!(RGB, frame, [0, 0, 0]),
+            VideoFrame::RGBA(frame) => pad_packed_frame!(RGBA, frame, [0, 0, 0, 255]),
+            VideoFrame::RGBx(frame) => pad_packed_frame!(RGBx, frame, [0, 0, 0, 255]),
+            VideoFrame::XBGR(frame) => pad_packed_frame!(XBGR, frame, [255, 0, 0, 0]),
+            VideoFrame::BGRx(frame) => pad_packed_frame!(BGRx, frame, [0, 0, 0, 255]),
+            VideoFrame::BGR0(frame) => pad_packed_frame!(BGR0, frame, [0, 0, 0]),
+            VideoFrame::BGRA(frame) => pad_packed_frame!(BGRA, frame, [0, 0, 0, 255]),
+            // Opaque black, with alpha as a half float 1.0
+            VideoFrame::RGBA16F(frame) => {
+                pad_packed_frame!(RGBA16F, frame, [0, 0, 0, 0, 0, 0, 0, 0x3C])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::PixelFormat;
+
+    #[test]
+    fn test_scale_nearest() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        assert_eq!(
+            scale_packed(&data, 4, 1, (4, 4), (2, 2), ScaleFilter::Nearest),
+            vec![6, 8, 14, 16]
+        );
+        assert_eq!(
+            scale_packed(&[1, 2], 2, 1, (2, 1), (4, 1), ScaleFilter::Nearest),
+            vec![1, 1, 2, 2]
+        );
+    }
+
+    #[test]
+    fn test_scale_bilinear() {
+        assert_eq!(
+            scale_packed(&[0, 100], 2, 1, (2, 1), (4, 1), ScaleFilter::Bilinear),
+            vec![0, 25, 75, 100]
+        );
+        // Two channels are interpolated independently, padding is skipped
+        assert_eq!(
+            scale_packed(
+                &[0, 200, 100, 0, 9, 9],
+                6,
+                2,
+                (2, 1),
+                (1, 1),
+                ScaleFilter::Bilinear
+            ),
+            vec![50, 100]
+        );
+    }
+
+    #[test]
+    fn test_scale_area() {
+        let data = [10, 20, 30, 40, 50, 60, 70, 80];
+        assert_eq!(
+            scale_packed(&data, 4, 1, (4, 2), (2, 1), ScaleFilter::Area),
+            vec![35, 55]
+        );
+        assert_eq!(
+            scale_packed(&data, 4, 4, (1, 2), (1, 1), ScaleFilter::Area),
+            vec![30, 40, 50, 60]
+        );
+    }
+
+    #[test]
+    fn test_scale_wide() {
+        // Interpolating the bytes separately would carry nothing into the
+        // upper byte
+        let data = [0xFFu16, 0x101].map(u16::to_le_bytes).concat();
+        let scaled = scale_wide(&data, 4, 1, (2, 1), (1, 1), ScaleFilter::Area);
+        assert_eq!(scaled, 0x100u16.to_le_bytes());
+    }
+
+    #[test]
+    fn test_content_size() {
+        assert_eq!(
+            ScaleMode::Fit.content_size((1920, 1080), (1080, 1920)),
+            (1080, 608)
+        );
+        assert_eq!(
+            ScaleMode::Fill.content_size((1920, 1080), (1080, 1920)),
+            (3413, 1920)
+        );
+        assert_eq!(
+            ScaleMode::Stretch.content_size((1920, 1080), (1080, 1920)),
+            (1080, 1920)
+        );
+    }
+
+    fn bgra_frame(width: i32, height: i32, data: Vec<u8>) -> VideoFrame {
+        VideoFrame::packed(PixelFormat::BGRA, width, height, data)
+    }
+
+    #[test]
+    fn test_resized() {
+        let pixels = |frame: VideoFrame| match frame {
+            VideoFrame::BGRA(frame) => (frame.width, frame.height, frame.data),
+            _ => unreachable!(),
+        };
+        let frame = || bgra_frame(2, 1, vec![1, 1, 1, 1, 2, 2, 2, 2]);
+
+        // Letterboxed into the middle rows
+        let (width, height, data) =
+            pixels(frame().resized(2, 5, ScaleFilter::Nearest, ScaleMode::Fit));
+        assert_eq!((width, height), (2, 5));
+        assert_eq!(&data[16..24], &[1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!(&data[..4], &[0, 0, 0, 255]);
+
+        // Only the center of the frame remains
+        let (width, height, data) = pixels(
+            bgra_frame(3, 1, vec![1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3]).resized(
+                1,
+                1,
+                ScaleFilter::Nearest,
+                ScaleMode::Fill,
+            ),
+        );
+        assert_eq!((width, height), (1, 1));
+        assert_eq!(data, vec![2, 2, 2, 2]);
+
+        let (width, height, _) =
+            pixels(frame().resized(3, 3, ScaleFilter::Nearest, ScaleMode::Stretch));
+        assert_eq!((width, height), (3, 3));
+    }
+
+    #[test]
+    fn test_scale_rect() {
+        let rect = DirtyRect {
+            x: 1,
+            y: 1,
+            width: 3,
+            height: 1,
+        };
+        assert_eq!(
+            scale_rect(rect, (8, 8), (4, 4)),
+            DirtyRect {
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 1
+            }
+        );
+    }
+}
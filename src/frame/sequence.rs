@@ -0,0 +1,116 @@
+/// What receiving a frame with a given sequence number means, see
+/// [SequenceTracker::check]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceCheck {
+    /// The frame follows the previous one, or is the first
+    InOrder,
+    /// `missing` frames before this one never arrived
+    Gap { expected: u64, missing: u64 },
+    /// The frame is not newer than one received before. Idle frames on macOS
+    /// repeat the number of the previous frame.
+    Duplicate { last: u64 },
+}
+
+/// Follows the sequence numbers of the frames of one stream, the video or the
+/// audio of a capture, to report frames that were dropped on the way or
+/// arrived more than once.
+#[derive(Debug, Clone, Default)]
+pub struct SequenceTracker {
+    last: Option<u64>,
+    received: u64,
+    missing: u64,
+    duplicates: u64,
+}
+
+impl SequenceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a received frame with the `sequence` number. The first frame
+    /// may have any number, as sources don't start counting at zero.
+    pub fn check(&mut self, sequence: u64) -> SequenceCheck {
+        self.received += 1;
+        let Some(last) = self.last else {
+            self.last = Some(sequence);
+            return SequenceCheck::InOrder;
+        };
+
+        if sequence <= last {
+            self.duplicates += 1;
+            return SequenceCheck::Duplicate { last };
+        }
+        self.last = Some(sequence);
+        match sequence - last - 1 {
+            0 => SequenceCheck::InOrder,
+            missing => {
+                self.missing += missing;
+                SequenceCheck::Gap {
+                    expected: last + 1,
+                    missing,
+                }
+            }
+        }
+    }
+
+    /// Highest sequence number received so far
+    pub fn last(&self) -> Option<u64> {
+        self.last
+    }
+
+    /// Number of frames checked
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// Number of frames that went missing between the received ones
+    pub fn missing(&self) -> u64 {
+        self.missing
+    }
+
+    /// Number of frames that were not newer than an earlier one
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Forgets the frames seen so far, for when the capture is restarted
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sequence_tracker() {
+        let mut tracker = SequenceTracker::new();
+        let checks: Vec<_> = [5, 6, 9, 9, 7, 10]
+            .into_iter()
+            .map(|sequence| tracker.check(sequence))
+            .collect();
+        assert_eq!(
+            checks,
+            vec![
+                SequenceCheck::InOrder,
+                SequenceCheck::InOrder,
+                SequenceCheck::Gap {
+                    expected: 7,
+                    missing: 2
+                },
+                SequenceCheck::Duplicate { last: 9 },
+                SequenceCheck::Duplicate { last: 9 },
+                SequenceCheck::InOrder,
+            ]
+        );
+        assert_eq!(tracker.last(), Some(10));
+        assert_eq!(tracker.received(), 6);
+        assert_eq!(tracker.missing(), 2);
+        assert_eq!(tracker.duplicates(), 2);
+
+        tracker.reset();
+        assert_eq!(tracker.check(0), SequenceCheck::InOrder);
+        assert_eq!(tracker.missing(), 0);
+    }
+}
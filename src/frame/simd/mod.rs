@@ -0,0 +1,464 @@
+//! Row kernels behind the frame conversions.
+//!
+//! Every kernel handles a whole row: the SIMD implementation picked at
+//! runtime converts as many pixels as it can and the scalar code finishes
+//! the rest, so all paths produce identical output.
+
+use std::sync::OnceLock;
+
+use super::convert::{RgbToYuv, YuvToRgb};
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86;
+
+#[cfg(target_arch = "aarch64")]
+mod neon;
+
+/// Instruction set used by the kernels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Isa {
+    Scalar,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Sse2,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Avx2,
+    #[cfg(target_arch = "aarch64")]
+    Neon,
+}
+
+fn detect() -> Isa {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return Isa::Avx2;
+        }
+        if is_x86_feature_detected!("sse2") {
+            return Isa::Sse2;
+        }
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Isa::Neon;
+        }
+    }
+
+    Isa::Scalar
+}
+
+#[cfg(test)]
+thread_local! {
+    static FORCED_ISA: std::cell::Cell<Option<Isa>> = const { std::cell::Cell::new(None) };
+}
+
+fn isa() -> Isa {
+    static DETECTED: OnceLock<Isa> = OnceLock::new();
+
+    #[cfg(test)]
+    if let Some(isa) = FORCED_ISA.with(|isa| isa.get()) {
+        return isa;
+    }
+
+    *DETECTED.get_or_init(detect)
+}
+
+/// Every instruction set the current CPU supports, scalar first
+#[cfg(test)]
+pub(crate) fn supported_isas() -> Vec<Isa> {
+    let mut isas = vec![Isa::Scalar];
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("sse2") {
+            isas.push(Isa::Sse2);
+        }
+        if is_x86_feature_detected!("avx2") {
+            isas.push(Isa::Avx2);
+        }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            isas.push(Isa::Neon);
+        }
+    }
+    isas
+}
+
+/// Runs `f` with the kernels on this thread forced to `isa`
+#[cfg(test)]
+pub(crate) fn with_isa<T>(isa: Isa, f: impl FnOnce() -> T) -> T {
+    FORCED_ISA.with(|forced| forced.set(Some(isa)));
+    let result = f();
+    FORCED_ISA.with(|forced| forced.set(None));
+    result
+}
+
+/// Rearranges 4 byte pixels, writing source byte `order[i]` to byte `i`.
+/// The `filler` byte is set to 255 instead.
+pub(crate) fn swizzle(src: &[u8], dst: &mut [u8], order: [usize; 4], filler: Option<usize>) {
+    let pixels = (src.len() / 4).min(dst.len() / 4);
+    let done = match isa() {
+        Isa::Scalar => 0,
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Sse2 => unsafe { x86::swizzle_sse2(src, dst, pixels, order, filler) },
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Avx2 => unsafe { x86::swizzle_avx2(src, dst, pixels, order, filler) },
+        #[cfg(target_arch = "aarch64")]
+        Isa::Neon => unsafe { neon::swizzle(src, dst, pixels, order, filler) },
+    };
+
+    for (src, dst) in src[done * 4..pixels * 4]
+        .chunks_exact(4)
+        .zip(dst[done * 4..].chunks_exact_mut(4))
+    {
+        for (byte, &offset) in dst.iter_mut().zip(&order) {
+            *byte = src[offset];
+        }
+        if let Some(filler) = filler {
+            dst[filler] = 255;
+        }
+    }
+}
+
+/// Packs 4 byte pixels into 3 byte ones, writing source byte `order[i]` to
+/// byte `i`
+pub(crate) fn pack(src: &[u8], dst: &mut [u8], order: [usize; 3]) {
+    let pixels = (src.len() / 4).min(dst.len() / 3);
+    let done = match isa() {
+        Isa::Scalar => 0,
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Sse2 => unsafe { x86::pack_sse2(src, dst, pixels, order) },
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Avx2 => unsafe { x86::pack_avx2(src, dst, pixels, order) },
+        #[cfg(target_arch = "aarch64")]
+        Isa::Neon => unsafe { neon::pack(src, dst, pixels, order) },
+    };
+
+    for (src, dst) in src[done * 4..pixels * 4]
+        .chunks_exact(4)
+        .zip(dst[done * 3..].chunks_exact_mut(3))
+    {
+        for (byte, &offset) in dst.iter_mut().zip(&order) {
+            *byte = src[offset];
+        }
+    }
+}
+
+/// Computes the luma of a row of `bpp` byte pixels whose red, green and
+/// blue bytes are at `channels`
+pub(crate) fn luma(
+    src: &[u8],
+    dst: &mut [u8],
+    bpp: usize,
+    channels: [usize; 3],
+    matrix: &RgbToYuv,
+) {
+    let done = match isa() {
+        _ if bpp != 4 => 0,
+        Isa::Scalar => 0,
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Sse2 => unsafe { x86::luma_sse2(src, dst, channels, matrix) },
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Avx2 => unsafe { x86::luma_avx2(src, dst, channels, matrix) },
+        #[cfg(target_arch = "aarch64")]
+        Isa::Neon => unsafe { neon::luma(src, dst, channels, matrix) },
+    };
+
+    for (luma, pixel) in dst[done..]
+        .iter_mut()
+        .zip(src[done * bpp..].chunks_exact(bpp))
+    {
+        *luma = matrix.luma(channels.map(|offset| pixel[offset] as i32));
+    }
+}
+
+/// Computes the chroma of the 2x2 blocks of two rows of `width` pixels. The
+/// last block of an odd width reuses the last column.
+pub(crate) fn chroma(
+    rows: [&[u8]; 2],
+    width: usize,
+    u: &mut [u8],
+    v: &mut [u8],
+    bpp: usize,
+    channels: [usize; 3],
+    matrix: &RgbToYuv,
+) {
+    let done = match isa() {
+        _ if bpp != 4 => 0,
+        Isa::Scalar => 0,
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Sse2 => unsafe { x86::chroma_sse2(rows, width, u, v, channels, matrix) },
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Avx2 => unsafe { x86::chroma_avx2(rows, width, u, v, channels, matrix) },
+        #[cfg(target_arch = "aarch64")]
+        Isa::Neon => unsafe { neon::chroma(rows, width, u, v, channels, matrix) },
+    };
+
+    for x in done..width.div_ceil(2) {
+        let columns = [x * 2, (x * 2 + 1).min(width - 1)];
+        let mut sum = [0; 3];
+        for row in rows {
+            for column in columns {
+                let pixel = &row[column * bpp..];
+                for (total, &offset) in sum.iter_mut().zip(&channels) {
+                    *total += pixel[offset] as i32;
+                }
+            }
+        }
+        (u[x], v[x]) = matrix.chroma(sum);
+    }
+}
+
+/// Converts a row of luma and half resolution `u` and `v` samples to `bpp`
+/// byte pixels. The `filler` byte is set to 255.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn rgb(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    dst: &mut [u8],
+    bpp: usize,
+    channels: [usize; 3],
+    filler: Option<usize>,
+    matrix: &YuvToRgb,
+) {
+    let done = match isa() {
+        _ if bpp != 4 => 0,
+        Isa::Scalar => 0,
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Sse2 => unsafe { x86::rgb_sse2(y, u, v, dst, channels, filler, matrix) },
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        Isa::Avx2 => unsafe { x86::rgb_avx2(y, u, v, dst, channels, filler, matrix) },
+        #[cfg(target_arch = "aarch64")]
+        Isa::Neon => unsafe { neon::rgb(y, u, v, dst, channels, filler, matrix) },
+    };
+
+    for (x, (&luma, pixel)) in y
+        .iter()
+        .zip(dst.chunks_exact_mut(bpp))
+        .enumerate()
+        .skip(done)
+    {
+        for (&offset, value) in channels.iter().zip(matrix.rgb(luma, u[x / 2], v[x / 2])) {
+            pixel[offset] = value;
+        }
+        if let Some(filler) = filler {
+            pixel[filler] = 255;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::{
+        convert_packed, i420_to_packed, nv12_to_packed, packed_to_i420, packed_to_nv12,
+        ColorMatrix, ColorRange, Colorimetry, PackedFormat, Plane, PlaneMut,
+    };
+
+    const FORMATS: [PackedFormat; 7] = [
+        PackedFormat::BGRA,
+        PackedFormat::RGBA,
+        PackedFormat::BGRx,
+        PackedFormat::RGBx,
+        PackedFormat::XBGR,
+        PackedFormat::RGB,
+        PackedFormat::BGR,
+    ];
+
+    // Deterministic noise, so failures can be reproduced
+    fn noise(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    // Runs `f` with every supported instruction set and checks the results
+    // against the scalar code
+    fn assert_equivalent<T: PartialEq + std::fmt::Debug>(f: impl Fn() -> T) {
+        let expected = with_isa(Isa::Scalar, &f);
+        for isa in supported_isas() {
+            assert_eq!(with_isa(isa, &f), expected, "{isa:?} differs from scalar");
+        }
+    }
+
+    #[test]
+    fn test_swizzle_and_pack() {
+        // Odd sizes so every kernel also leaves a scalar tail
+        let (width, height) = (37, 3);
+        for src_format in FORMATS {
+            let src_stride = width * src_format.bytes_per_pixel() + 5;
+            let src = noise(src_stride * height, 1);
+            for dst_format in FORMATS {
+                assert_equivalent(|| {
+                    let dst_stride = width * dst_format.bytes_per_pixel() + 3;
+                    let mut dst = vec![0; dst_stride * height];
+                    convert_packed(
+                        Plane {
+                            data: &src,
+                            stride: src_stride,
+                        },
+                        src_format,
+                        PlaneMut {
+                            data: &mut dst,
+                            stride: dst_stride,
+                        },
+                        dst_format,
+                        (width, height),
+                    );
+                    dst
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn test_rgb_to_yuv() {
+        let (width, height) = (45, 7);
+        for format in FORMATS {
+            let stride = width * format.bytes_per_pixel();
+            let src = noise(stride * height, 2);
+            for matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709, ColorMatrix::Bt2020] {
+                for range in [ColorRange::Limited, ColorRange::Full] {
+                    let colorimetry = Colorimetry {
+                        matrix,
+                        range,
+                        ..Default::default()
+                    };
+                    let src = Plane { data: &src, stride };
+                    let chroma_size = width.div_ceil(2) * height.div_ceil(2);
+
+                    assert_equivalent(|| {
+                        let mut y = vec![0; width * height];
+                        let mut uv = vec![0; chroma_size * 2];
+                        packed_to_nv12(
+                            src,
+                            format,
+                            PlaneMut {
+                                data: &mut y,
+                                stride: width,
+                            },
+                            PlaneMut {
+                                data: &mut uv,
+                                stride: width.div_ceil(2) * 2,
+                            },
+                            (width, height),
+                            colorimetry,
+                        );
+                        (y, uv)
+                    });
+                    assert_equivalent(|| {
+                        let mut y = vec![0; width * height];
+                        let mut u = vec![0; chroma_size];
+                        let mut v = vec![0; chroma_size];
+                        packed_to_i420(
+                            src,
+                            format,
+                            PlaneMut {
+                                data: &mut y,
+                                stride: width,
+                            },
+                            PlaneMut {
+                                data: &mut u,
+                                stride: width.div_ceil(2),
+                            },
+                            PlaneMut {
+                                data: &mut v,
+                                stride: width.div_ceil(2),
+                            },
+                            (width, height),
+                            colorimetry,
+                        );
+                        (y, u, v)
+                    });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_yuv_to_rgb() {
+        let (width, height) = (45usize, 7usize);
+        let chroma_width = width.div_ceil(2);
+        let chroma_height = height.div_ceil(2);
+        let y = noise(width * height, 3);
+        let uv = noise(chroma_width * 2 * chroma_height, 4);
+        let u = noise(chroma_width * chroma_height, 5);
+        let v = noise(chroma_width * chroma_height, 6);
+
+        for format in FORMATS {
+            let stride = width * format.bytes_per_pixel();
+            for matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709, ColorMatrix::Bt2020] {
+                for range in [ColorRange::Limited, ColorRange::Full] {
+                    let colorimetry = Colorimetry {
+                        matrix,
+                        range,
+                        ..Default::default()
+                    };
+                    assert_equivalent(|| {
+                        let mut dst = vec![0; stride * height];
+                        nv12_to_packed(
+                            Plane {
+                                data: &y,
+                                stride: width,
+                            },
+                            Plane {
+                                data: &uv,
+                                stride: chroma_width * 2,
+                            },
+                            PlaneMut {
+                                data: &mut dst,
+                                stride,
+                            },
+                            format,
+                            (width, height),
+                            colorimetry,
+                        );
+                        dst
+                    });
+                    assert_equivalent(|| {
+                        let mut dst = vec![0; stride * height];
+                        i420_to_packed(
+                            Plane {
+                                data: &y,
+                                stride: width,
+                            },
+                            Plane {
+                                data: &u,
+                                stride: chroma_width,
+                            },
+                            Plane {
+                                data: &v,
+                                stride: chroma_width,
+                            },
+                            PlaneMut {
+                                data: &mut dst,
+                                stride,
+                            },
+                            format,
+                            (width, height),
+                            colorimetry,
+                        );
+                        dst
+                    });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_strip() {
+        let data = noise(4 * 71, 7);
+        assert_equivalent(|| {
+            (
+                crate::frame::remove_alpha_channel(data.clone()),
+                crate::frame::convert_bgra_to_rgb(data.clone()),
+            )
+        });
+    }
+}
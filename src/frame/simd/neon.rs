@@ -0,0 +1,194 @@
+//! NEON kernels. They return the number of pixels (or chroma samples)
+//! converted and leave the rest of the row to the scalar code.
+
+use std::arch::aarch64::*;
+
+use crate::frame::convert::{RgbToYuv, YuvToRgb, RGB_SHIFT, YUV_SHIFT};
+
+// Weighted sums of eight red, green and blue values, as two halves
+#[target_feature(enable = "neon")]
+unsafe fn weighted([r, g, b]: [int16x8_t; 3], [kr, kg, kb]: [i32; 3]) -> [int32x4_t; 2] {
+    let low = vmull_n_s16(vget_low_s16(r), kr as i16);
+    let low = vmlal_n_s16(low, vget_low_s16(g), kg as i16);
+    let low = vmlal_n_s16(low, vget_low_s16(b), kb as i16);
+    let high = vmull_high_n_s16(r, kr as i16);
+    let high = vmlal_high_n_s16(high, g, kg as i16);
+    let high = vmlal_high_n_s16(high, b, kb as i16);
+    [low, high]
+}
+
+// Rounds, shifts and offsets fixed point values, saturating them to bytes
+#[target_feature(enable = "neon")]
+unsafe fn narrow([low, high]: [int32x4_t; 2], shift: u32, offset: i32) -> uint8x8_t {
+    let round = vdupq_n_s32(1 << (shift - 1));
+    let shift = vdupq_n_s32(-(shift as i32));
+    let offset = vdupq_n_s32(offset);
+    let low = vaddq_s32(vshlq_s32(vaddq_s32(low, round), shift), offset);
+    let high = vaddq_s32(vshlq_s32(vaddq_s32(high, round), shift), offset);
+    vqmovun_s16(vcombine_s16(vqmovn_s32(low), vqmovn_s32(high)))
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn widen(values: uint8x8_t) -> int16x8_t {
+    vreinterpretq_s16_u16(vmovl_u8(values))
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn swizzle(
+    src: &[u8],
+    dst: &mut [u8],
+    pixels: usize,
+    order: [usize; 4],
+    filler: Option<usize>,
+) -> usize {
+    let fill = vdupq_n_u8(255);
+
+    let mut i = 0;
+    while i + 16 <= pixels {
+        let input = vld4q_u8(src.as_ptr().add(i * 4));
+        let channels = [input.0, input.1, input.2, input.3];
+        let mut output = [fill; 4];
+        for (position, value) in output.iter_mut().enumerate() {
+            if Some(position) != filler {
+                *value = channels[order[position]];
+            }
+        }
+        vst4q_u8(
+            dst.as_mut_ptr().add(i * 4),
+            uint8x16x4_t(output[0], output[1], output[2], output[3]),
+        );
+        i += 16;
+    }
+    i
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn pack(src: &[u8], dst: &mut [u8], pixels: usize, order: [usize; 3]) -> usize {
+    let mut i = 0;
+    while i + 16 <= pixels {
+        let input = vld4q_u8(src.as_ptr().add(i * 4));
+        let channels = [input.0, input.1, input.2, input.3];
+        vst3q_u8(
+            dst.as_mut_ptr().add(i * 3),
+            uint8x16x3_t(channels[order[0]], channels[order[1]], channels[order[2]]),
+        );
+        i += 16;
+    }
+    i
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn luma(
+    src: &[u8],
+    dst: &mut [u8],
+    [r, g, b]: [usize; 3],
+    matrix: &RgbToYuv,
+) -> usize {
+    let pixels = (src.len() / 4).min(dst.len());
+    let mut i = 0;
+    while i + 16 <= pixels {
+        let input = vld4q_u8(src.as_ptr().add(i * 4));
+        let channels = [input.0, input.1, input.2, input.3];
+        let low = [r, g, b].map(|channel| widen(vget_low_u8(channels[channel])));
+        let high = [r, g, b].map(|channel| widen(vget_high_u8(channels[channel])));
+
+        let low = narrow(weighted(low, matrix.y), RGB_SHIFT, matrix.y_offset);
+        let high = narrow(weighted(high, matrix.y), RGB_SHIFT, matrix.y_offset);
+        vst1q_u8(dst.as_mut_ptr().add(i), vcombine_u8(low, high));
+        i += 16;
+    }
+    i
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn chroma(
+    [row0, row1]: [&[u8]; 2],
+    width: usize,
+    u: &mut [u8],
+    v: &mut [u8],
+    [r, g, b]: [usize; 3],
+    matrix: &RgbToYuv,
+) -> usize {
+    // Eight blocks of 2x2 pixels per iteration
+    let mut x = 0;
+    while (x + 8) * 2 <= width {
+        let top = vld4q_u8(row0.as_ptr().add(x * 8));
+        let bottom = vld4q_u8(row1.as_ptr().add(x * 8));
+        let top = [top.0, top.1, top.2, top.3];
+        let bottom = [bottom.0, bottom.1, bottom.2, bottom.3];
+        let sums = [r, g, b].map(|channel| {
+            vreinterpretq_s16_u16(vaddq_u16(
+                vpaddlq_u8(top[channel]),
+                vpaddlq_u8(bottom[channel]),
+            ))
+        });
+
+        let cb = narrow(weighted(sums, matrix.u), RGB_SHIFT + 2, 128);
+        let cr = narrow(weighted(sums, matrix.v), RGB_SHIFT + 2, 128);
+        vst1_u8(u.as_mut_ptr().add(x), cb);
+        vst1_u8(v.as_mut_ptr().add(x), cr);
+        x += 8;
+    }
+    x
+}
+
+// Centered chroma samples of eight pixels starting at `x`, every sample
+// covering two pixels
+#[target_feature(enable = "neon")]
+unsafe fn chroma_samples(plane: &[u8], x: usize) -> int16x8_t {
+    let samples = u32::from_le_bytes(plane[x / 2..x / 2 + 4].try_into().unwrap());
+    let samples = vcreate_u8(samples as u64);
+    vsubq_s16(widen(vzip1_u8(samples, samples)), vdupq_n_s16(128))
+}
+
+#[target_feature(enable = "neon")]
+pub(super) unsafe fn rgb(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    dst: &mut [u8],
+    channels: [usize; 3],
+    filler: Option<usize>,
+    matrix: &YuvToRgb,
+) -> usize {
+    let pixels = y.len().min(dst.len() / 4);
+    let mut i = 0;
+    while i + 8 <= pixels {
+        let luma = vsubq_s16(
+            widen(vld1_u8(y.as_ptr().add(i))),
+            vdupq_n_s16(matrix.y_offset as i16),
+        );
+        let (cb, cr) = (chroma_samples(u, i), chroma_samples(v, i));
+        let zero = vdupq_n_s16(0);
+
+        let red = narrow(
+            weighted([luma, cr, zero], [matrix.y, matrix.rv, 0]),
+            YUV_SHIFT,
+            0,
+        );
+        let green = narrow(
+            weighted([luma, cb, cr], [matrix.y, matrix.gu, matrix.gv]),
+            YUV_SHIFT,
+            0,
+        );
+        let blue = narrow(
+            weighted([luma, cb, zero], [matrix.y, matrix.bu, 0]),
+            YUV_SHIFT,
+            0,
+        );
+
+        let mut output = [vdup_n_u8(255); 4];
+        for (&offset, value) in channels.iter().zip([red, green, blue]) {
+            output[offset] = value;
+        }
+        if let Some(filler) = filler {
+            output[filler] = vdup_n_u8(255);
+        }
+        vst4_u8(
+            dst.as_mut_ptr().add(i * 4),
+            uint8x8x4_t(output[0], output[1], output[2], output[3]),
+        );
+        i += 8;
+    }
+    i
+}
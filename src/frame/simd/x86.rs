@@ -0,0 +1,515 @@
+//! SSE2 and AVX2 kernels. They return the number of pixels (or chroma
+//! samples) converted and leave the rest of the row to the scalar code.
+//!
+//! SSE2 has no 32 bit multiply, so those kernels use `madd` on pairs of 16
+//! bit values, which is why the conversion coefficients fit in an `i16`.
+
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use crate::frame::convert::{RgbToYuv, YuvToRgb, RGB_SHIFT, YUV_SHIFT};
+
+// Two 16 bit values in every 32 bit lane, `low` first, as `madd` expects
+#[target_feature(enable = "sse2")]
+unsafe fn pair(low: i32, high: i32) -> __m128i {
+    _mm_set1_epi32(((high as u32) << 16 | (low as u16 as u32)) as i32)
+}
+
+// Shuffle mask for `pshufb` moving source byte `order[i]` of every 4 byte
+// pixel to byte `i`. Negative entries zero the byte.
+fn pixel_shuffle(order: [usize; 4], filler: Option<usize>) -> [i8; 32] {
+    let mut mask = [0; 32];
+    for (i, byte) in mask.iter_mut().enumerate() {
+        let pixel = (i % 16) / 4;
+        *byte = match i % 4 {
+            position if Some(position) == filler => -128,
+            position => (pixel * 4 + order[position]) as i8,
+        };
+    }
+    mask
+}
+
+// Byte `channel` of every 32 bit lane
+#[target_feature(enable = "sse2")]
+unsafe fn channel_sse2(pixels: __m128i, channel: usize) -> __m128i {
+    let shifted = _mm_srl_epi32(pixels, _mm_cvtsi32_si128(channel as i32 * 8));
+    _mm_and_si128(shifted, _mm_set1_epi32(0xFF))
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn channel_avx2(pixels: __m256i, channel: usize) -> __m256i {
+    let shifted = _mm256_srl_epi32(pixels, _mm_cvtsi32_si128(channel as i32 * 8));
+    _mm256_and_si256(shifted, _mm256_set1_epi32(0xFF))
+}
+
+// Channel sums of the 2x2 blocks of two rows of pixels, in the even lanes
+#[target_feature(enable = "sse2")]
+unsafe fn block_sums_sse2(top: __m128i, bottom: __m128i, channel: usize) -> __m128i {
+    let sum = _mm_add_epi32(channel_sse2(top, channel), channel_sse2(bottom, channel));
+    _mm_add_epi32(sum, _mm_srli_epi64(sum, 32))
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn block_sums_avx2(top: __m256i, bottom: __m256i, channel: usize) -> __m256i {
+    let sum = _mm256_add_epi32(channel_avx2(top, channel), channel_avx2(bottom, channel));
+    _mm256_add_epi32(sum, _mm256_srli_epi64(sum, 32))
+}
+
+// Weighted sum of red, green and blue values
+#[target_feature(enable = "avx2")]
+unsafe fn weighted_avx2([r, g, b]: [__m256i; 3], [kr, kg, kb]: [i32; 3]) -> __m256i {
+    _mm256_add_epi32(
+        _mm256_add_epi32(
+            _mm256_mullo_epi32(r, _mm256_set1_epi32(kr)),
+            _mm256_mullo_epi32(g, _mm256_set1_epi32(kg)),
+        ),
+        _mm256_mullo_epi32(b, _mm256_set1_epi32(kb)),
+    )
+}
+
+// Centered chroma samples of four pixels starting at `x` as 16 bit values,
+// every sample covering two pixels
+#[target_feature(enable = "sse2")]
+unsafe fn chroma_samples_sse2(plane: &[u8], x: usize) -> __m128i {
+    let samples = _mm_cvtsi32_si128(plane[x / 2] as i32 | (plane[x / 2 + 1] as i32) << 8);
+    let samples = _mm_unpacklo_epi8(samples, samples);
+    _mm_sub_epi16(
+        _mm_unpacklo_epi8(samples, _mm_setzero_si128()),
+        _mm_set1_epi16(128),
+    )
+}
+
+// Centered chroma samples of eight pixels starting at `x` as 32 bit values
+#[target_feature(enable = "avx2")]
+unsafe fn chroma_samples_avx2(plane: &[u8], x: usize) -> __m256i {
+    let samples = u32::from_le_bytes(plane[x / 2..x / 2 + 4].try_into().unwrap());
+    let samples = _mm_cvtsi32_si128(samples as i32);
+    let samples = _mm256_cvtepu8_epi32(_mm_unpacklo_epi8(samples, samples));
+    _mm256_sub_epi32(samples, _mm256_set1_epi32(128))
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn swizzle_sse2(
+    src: &[u8],
+    dst: &mut [u8],
+    pixels: usize,
+    order: [usize; 4],
+    filler: Option<usize>,
+) -> usize {
+    let fill = match filler {
+        Some(filler) => _mm_set1_epi32((0xFFu32 << (filler * 8)) as i32),
+        None => _mm_setzero_si128(),
+    };
+
+    let mut i = 0;
+    while i + 4 <= pixels {
+        let input = _mm_loadu_si128(src.as_ptr().add(i * 4) as *const __m128i);
+        let mut output = fill;
+        for (position, &offset) in order.iter().enumerate() {
+            if Some(position) != filler {
+                let byte = channel_sse2(input, offset);
+                let shift = _mm_cvtsi32_si128(position as i32 * 8);
+                output = _mm_or_si128(output, _mm_sll_epi32(byte, shift));
+            }
+        }
+        _mm_storeu_si128(dst.as_mut_ptr().add(i * 4) as *mut __m128i, output);
+        i += 4;
+    }
+    i
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn swizzle_avx2(
+    src: &[u8],
+    dst: &mut [u8],
+    pixels: usize,
+    order: [usize; 4],
+    filler: Option<usize>,
+) -> usize {
+    let mask = pixel_shuffle(order, filler);
+    let shuffle = _mm256_loadu_si256(mask.as_ptr() as *const __m256i);
+    let fill = match filler {
+        Some(filler) => _mm256_set1_epi32((0xFFu32 << (filler * 8)) as i32),
+        None => _mm256_setzero_si256(),
+    };
+
+    let mut i = 0;
+    while i + 8 <= pixels {
+        let input = _mm256_loadu_si256(src.as_ptr().add(i * 4) as *const __m256i);
+        let output = _mm256_or_si256(_mm256_shuffle_epi8(input, shuffle), fill);
+        _mm256_storeu_si256(dst.as_mut_ptr().add(i * 4) as *mut __m256i, output);
+        i += 8;
+    }
+    i
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn pack_sse2(
+    src: &[u8],
+    dst: &mut [u8],
+    pixels: usize,
+    order: [usize; 3],
+) -> usize {
+    let low = _mm_set_epi32(0, 0x00FF_FFFF, 0, 0x00FF_FFFF);
+    let high = _mm_set_epi32(0xFFFF, 0xFF00_0000u32 as i32, 0xFFFF, 0xFF00_0000u32 as i32);
+
+    // Every store writes 8 bytes of which only 6 are kept
+    let mut i = 0;
+    while i + 4 <= pixels && i * 3 + 16 <= dst.len() {
+        let input = _mm_loadu_si128(src.as_ptr().add(i * 4) as *const __m128i);
+        let mut pixel = _mm_setzero_si128();
+        for (position, &offset) in order.iter().enumerate() {
+            let byte = channel_sse2(input, offset);
+            let shift = _mm_cvtsi32_si128(position as i32 * 8);
+            pixel = _mm_or_si128(pixel, _mm_sll_epi32(byte, shift));
+        }
+
+        // Join the two pixels of every 64 bit lane into 6 bytes
+        let packed = _mm_or_si128(
+            _mm_and_si128(pixel, low),
+            _mm_and_si128(_mm_srli_epi64(pixel, 8), high),
+        );
+        let output = dst.as_mut_ptr().add(i * 3);
+        _mm_storel_epi64(output as *mut __m128i, packed);
+        _mm_storel_epi64(output.add(6) as *mut __m128i, _mm_srli_si128(packed, 8));
+        i += 4;
+    }
+    i
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn pack_avx2(
+    src: &[u8],
+    dst: &mut [u8],
+    pixels: usize,
+    order: [usize; 3],
+) -> usize {
+    // Packs the 4 pixels of every 128 bit lane into its first 12 bytes
+    let mut mask = [-128i8; 32];
+    for lane in 0..2 {
+        for i in 0..12 {
+            mask[lane * 16 + i] = ((i / 3) * 4 + order[i % 3]) as i8;
+        }
+    }
+    let shuffle = _mm256_loadu_si256(mask.as_ptr() as *const __m256i);
+    let join = _mm256_setr_epi32(0, 1, 2, 4, 5, 6, 3, 7);
+
+    // Every store writes 32 bytes of which only 24 are kept
+    let mut i = 0;
+    while i + 8 <= pixels && i * 3 + 32 <= dst.len() {
+        let input = _mm256_loadu_si256(src.as_ptr().add(i * 4) as *const __m256i);
+        let packed = _mm256_permutevar8x32_epi32(_mm256_shuffle_epi8(input, shuffle), join);
+        _mm256_storeu_si256(dst.as_mut_ptr().add(i * 3) as *mut __m256i, packed);
+        i += 8;
+    }
+    i
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn luma_sse2(
+    src: &[u8],
+    dst: &mut [u8],
+    [r, g, b]: [usize; 3],
+    matrix: &RgbToYuv,
+) -> usize {
+    let red_green = pair(matrix.y[0], matrix.y[1]);
+    let blue_round = pair(matrix.y[2], 1 << (RGB_SHIFT - 1));
+    let one = _mm_set1_epi32(1 << 16);
+    let shift = _mm_cvtsi32_si128(RGB_SHIFT as i32);
+    let offset = _mm_set1_epi32(matrix.y_offset);
+
+    let pixels = (src.len() / 4).min(dst.len());
+    let mut i = 0;
+    while i + 4 <= pixels {
+        let input = _mm_loadu_si128(src.as_ptr().add(i * 4) as *const __m128i);
+        let rg = _mm_or_si128(
+            channel_sse2(input, r),
+            _mm_slli_epi32(channel_sse2(input, g), 16),
+        );
+        let b1 = _mm_or_si128(channel_sse2(input, b), one);
+
+        let luma = _mm_add_epi32(
+            _mm_madd_epi16(rg, red_green),
+            _mm_madd_epi16(b1, blue_round),
+        );
+        let luma = _mm_add_epi32(_mm_sra_epi32(luma, shift), offset);
+        let luma = _mm_packs_epi32(luma, luma);
+        let luma = _mm_packus_epi16(luma, luma);
+
+        let bytes = (_mm_cvtsi128_si32(luma) as u32).to_le_bytes();
+        dst[i..i + 4].copy_from_slice(&bytes);
+        i += 4;
+    }
+    i
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn luma_avx2(
+    src: &[u8],
+    dst: &mut [u8],
+    [r, g, b]: [usize; 3],
+    matrix: &RgbToYuv,
+) -> usize {
+    let kr = _mm256_set1_epi32(matrix.y[0]);
+    let kg = _mm256_set1_epi32(matrix.y[1]);
+    let kb = _mm256_set1_epi32(matrix.y[2]);
+    let round = _mm256_set1_epi32(1 << (RGB_SHIFT - 1));
+    let shift = _mm_cvtsi32_si128(RGB_SHIFT as i32);
+    let offset = _mm256_set1_epi32(matrix.y_offset);
+    let gather = _mm256_setr_epi32(0, 4, 0, 0, 0, 0, 0, 0);
+
+    let pixels = (src.len() / 4).min(dst.len());
+    let mut i = 0;
+    while i + 8 <= pixels {
+        let input = _mm256_loadu_si256(src.as_ptr().add(i * 4) as *const __m256i);
+        let luma = _mm256_add_epi32(
+            _mm256_add_epi32(
+                _mm256_mullo_epi32(channel_avx2(input, r), kr),
+                _mm256_mullo_epi32(channel_avx2(input, g), kg),
+            ),
+            _mm256_add_epi32(_mm256_mullo_epi32(channel_avx2(input, b), kb), round),
+        );
+        let luma = _mm256_add_epi32(_mm256_sra_epi32(luma, shift), offset);
+        let luma = _mm256_packs_epi32(luma, luma);
+        let luma = _mm256_packus_epi16(luma, luma);
+        let luma = _mm256_permutevar8x32_epi32(luma, gather);
+
+        _mm_storel_epi64(
+            dst.as_mut_ptr().add(i) as *mut __m128i,
+            _mm256_castsi256_si128(luma),
+        );
+        i += 8;
+    }
+    i
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn chroma_sse2(
+    [row0, row1]: [&[u8]; 2],
+    width: usize,
+    u: &mut [u8],
+    v: &mut [u8],
+    [r, g, b]: [usize; 3],
+    matrix: &RgbToYuv,
+) -> usize {
+    let u_red_green = pair(matrix.u[0], matrix.u[1]);
+    let u_blue = pair(matrix.u[2], 0);
+    let v_red_green = pair(matrix.v[0], matrix.v[1]);
+    let v_blue = pair(matrix.v[2], 0);
+    let round = _mm_set1_epi32(1 << (RGB_SHIFT + 1));
+    let shift = _mm_cvtsi32_si128(RGB_SHIFT as i32 + 2);
+    let offset = _mm_set1_epi32(128);
+
+    // Two blocks of 2x2 pixels per iteration
+    let mut x = 0;
+    while (x + 2) * 2 <= width {
+        let top = _mm_loadu_si128(row0.as_ptr().add(x * 8) as *const __m128i);
+        let bottom = _mm_loadu_si128(row1.as_ptr().add(x * 8) as *const __m128i);
+        let rg = _mm_or_si128(
+            block_sums_sse2(top, bottom, r),
+            _mm_slli_epi32(block_sums_sse2(top, bottom, g), 16),
+        );
+        let blue = block_sums_sse2(top, bottom, b);
+
+        let cb = _mm_add_epi32(
+            _mm_madd_epi16(rg, u_red_green),
+            _mm_madd_epi16(blue, u_blue),
+        );
+        let cr = _mm_add_epi32(
+            _mm_madd_epi16(rg, v_red_green),
+            _mm_madd_epi16(blue, v_blue),
+        );
+        let cb = _mm_add_epi32(_mm_sra_epi32(_mm_add_epi32(cb, round), shift), offset);
+        let cr = _mm_add_epi32(_mm_sra_epi32(_mm_add_epi32(cr, round), shift), offset);
+
+        // Move the even lanes together
+        let uv = _mm_unpacklo_epi64(
+            _mm_shuffle_epi32(cb, 0b00_00_10_00),
+            _mm_shuffle_epi32(cr, 0b00_00_10_00),
+        );
+        let uv = _mm_packs_epi32(uv, uv);
+        let uv = _mm_packus_epi16(uv, uv);
+
+        let bytes = (_mm_cvtsi128_si32(uv) as u32).to_le_bytes();
+        u[x..x + 2].copy_from_slice(&bytes[..2]);
+        v[x..x + 2].copy_from_slice(&bytes[2..]);
+        x += 2;
+    }
+    x
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn chroma_avx2(
+    [row0, row1]: [&[u8]; 2],
+    width: usize,
+    u: &mut [u8],
+    v: &mut [u8],
+    [r, g, b]: [usize; 3],
+    matrix: &RgbToYuv,
+) -> usize {
+    let round = _mm256_set1_epi32(1 << (RGB_SHIFT + 1));
+    let shift = _mm_cvtsi32_si128(RGB_SHIFT as i32 + 2);
+    let offset = _mm256_set1_epi32(128);
+    let gather = _mm256_setr_epi32(0, 2, 4, 6, 0, 2, 4, 6);
+
+    // Four blocks of 2x2 pixels per iteration
+    let mut x = 0;
+    while (x + 4) * 2 <= width {
+        let top = _mm256_loadu_si256(row0.as_ptr().add(x * 8) as *const __m256i);
+        let bottom = _mm256_loadu_si256(row1.as_ptr().add(x * 8) as *const __m256i);
+        let sums = [
+            block_sums_avx2(top, bottom, r),
+            block_sums_avx2(top, bottom, g),
+            block_sums_avx2(top, bottom, b),
+        ];
+
+        let cb = _mm256_add_epi32(weighted_avx2(sums, matrix.u), round);
+        let cr = _mm256_add_epi32(weighted_avx2(sums, matrix.v), round);
+        let cb = _mm256_add_epi32(_mm256_sra_epi32(cb, shift), offset);
+        let cr = _mm256_add_epi32(_mm256_sra_epi32(cr, shift), offset);
+
+        // Move the even lanes together
+        let uv = _mm_packs_epi32(
+            _mm256_castsi256_si128(_mm256_permutevar8x32_epi32(cb, gather)),
+            _mm256_castsi256_si128(_mm256_permutevar8x32_epi32(cr, gather)),
+        );
+        let uv = _mm_packus_epi16(uv, uv);
+
+        let mut bytes = [0u8; 8];
+        _mm_storel_epi64(bytes.as_mut_ptr() as *mut __m128i, uv);
+        u[x..x + 4].copy_from_slice(&bytes[..4]);
+        v[x..x + 4].copy_from_slice(&bytes[4..]);
+        x += 4;
+    }
+    x
+}
+
+// Byte offsets of the red, green, blue and filler values in a pixel, given
+// the index of each in `[red, green, blue, filler]`
+fn channel_order(channels: [usize; 3], filler: Option<usize>) -> [usize; 4] {
+    let mut order = [3; 4];
+    for (index, &offset) in channels.iter().enumerate() {
+        order[offset] = index;
+    }
+    if let Some(filler) = filler {
+        order[filler] = 3;
+    }
+    order
+}
+
+#[target_feature(enable = "sse2")]
+pub(super) unsafe fn rgb_sse2(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    dst: &mut [u8],
+    channels: [usize; 3],
+    filler: Option<usize>,
+    matrix: &YuvToRgb,
+) -> usize {
+    let zero = _mm_setzero_si128();
+    let luma_scale = pair(matrix.y, 0);
+    let red_v = pair(matrix.rv, 0);
+    let green_uv = pair(matrix.gu, matrix.gv);
+    let blue_u = pair(matrix.bu, 0);
+    let luma_offset = _mm_set1_epi16(matrix.y_offset as i16);
+    let round = _mm_set1_epi32(1 << (YUV_SHIFT - 1));
+    let shift = _mm_cvtsi32_si128(YUV_SHIFT as i32);
+    let order = channel_order(channels, filler);
+
+    let pixels = y.len().min(dst.len() / 4);
+    let mut i = 0;
+    while i + 4 <= pixels {
+        let luma = _mm_cvtsi32_si128(i32::from_le_bytes(y[i..i + 4].try_into().unwrap()));
+        let luma = _mm_sub_epi16(_mm_unpacklo_epi8(luma, zero), luma_offset);
+        let (cb, cr) = (chroma_samples_sse2(u, i), chroma_samples_sse2(v, i));
+
+        let base = _mm_add_epi32(
+            _mm_madd_epi16(_mm_unpacklo_epi16(luma, zero), luma_scale),
+            round,
+        );
+        let red = _mm_add_epi32(base, _mm_madd_epi16(_mm_unpacklo_epi16(cr, zero), red_v));
+        let green = _mm_add_epi32(base, _mm_madd_epi16(_mm_unpacklo_epi16(cb, cr), green_uv));
+        let blue = _mm_add_epi32(base, _mm_madd_epi16(_mm_unpacklo_epi16(cb, zero), blue_u));
+        let values = [
+            _mm_sra_epi32(red, shift),
+            _mm_sra_epi32(green, shift),
+            _mm_sra_epi32(blue, shift),
+            _mm_set1_epi32(255),
+        ];
+
+        // One byte position of all four pixels per 32 bit lane, then
+        // transposed into pixels
+        let planar = _mm_packus_epi16(
+            _mm_packs_epi32(values[order[0]], values[order[1]]),
+            _mm_packs_epi32(values[order[2]], values[order[3]]),
+        );
+        let low = _mm_unpacklo_epi8(planar, _mm_srli_si128(planar, 4));
+        let high = _mm_unpacklo_epi8(_mm_srli_si128(planar, 8), _mm_srli_si128(planar, 12));
+        let output = _mm_unpacklo_epi16(low, high);
+
+        _mm_storeu_si128(dst.as_mut_ptr().add(i * 4) as *mut __m128i, output);
+        i += 4;
+    }
+    i
+}
+
+#[target_feature(enable = "avx2")]
+pub(super) unsafe fn rgb_avx2(
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    dst: &mut [u8],
+    channels: [usize; 3],
+    filler: Option<usize>,
+    matrix: &YuvToRgb,
+) -> usize {
+    let luma_scale = _mm256_set1_epi32(matrix.y);
+    let luma_offset = _mm256_set1_epi32(matrix.y_offset);
+    let round = _mm256_set1_epi32(1 << (YUV_SHIFT - 1));
+    let shift = _mm_cvtsi32_si128(YUV_SHIFT as i32);
+    let order = channel_order(channels, filler);
+    let transpose = _mm256_setr_epi8(
+        0, 4, 8, 12, 1, 5, 9, 13, 2, 6, 10, 14, 3, 7, 11, 15, //
+        0, 4, 8, 12, 1, 5, 9, 13, 2, 6, 10, 14, 3, 7, 11, 15,
+    );
+
+    let pixels = y.len().min(dst.len() / 4);
+    let mut i = 0;
+    while i + 8 <= pixels {
+        let luma = _mm256_cvtepu8_epi32(_mm_loadl_epi64(y.as_ptr().add(i) as *const __m128i));
+        let luma = _mm256_sub_epi32(luma, luma_offset);
+        let (cb, cr) = (chroma_samples_avx2(u, i), chroma_samples_avx2(v, i));
+
+        let base = _mm256_add_epi32(_mm256_mullo_epi32(luma, luma_scale), round);
+        let red = _mm256_add_epi32(base, _mm256_mullo_epi32(cr, _mm256_set1_epi32(matrix.rv)));
+        let green = _mm256_add_epi32(
+            base,
+            _mm256_add_epi32(
+                _mm256_mullo_epi32(cb, _mm256_set1_epi32(matrix.gu)),
+                _mm256_mullo_epi32(cr, _mm256_set1_epi32(matrix.gv)),
+            ),
+        );
+        let blue = _mm256_add_epi32(base, _mm256_mullo_epi32(cb, _mm256_set1_epi32(matrix.bu)));
+        let values = [
+            _mm256_sra_epi32(red, shift),
+            _mm256_sra_epi32(green, shift),
+            _mm256_sra_epi32(blue, shift),
+            _mm256_set1_epi32(255),
+        ];
+
+        // One byte position of four pixels per 32 bit lane, then transposed
+        // into pixels within every 128 bit lane
+        let planar = _mm256_packus_epi16(
+            _mm256_packs_epi32(values[order[0]], values[order[1]]),
+            _mm256_packs_epi32(values[order[2]], values[order[3]]),
+        );
+        let output = _mm256_shuffle_epi8(planar, transpose);
+
+        _mm256_storeu_si256(dst.as_mut_ptr().add(i * 4) as *mut __m256i, output);
+        i += 8;
+    }
+    i
+}
@@ -0,0 +1,424 @@
+use super::{DirtyRect, VideoFrame};
+
+/// Orientation change a compositor applied to the buffer content.
+///
+/// Rotations are counter-clockwise, flips are around the vertical axis and
+/// happen before the rotation (matching `SPA_META_VideoTransform`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoTransform {
+    #[default]
+    None,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+impl VideoTransform {
+    // Number of counter-clockwise quarter turns
+    fn quarter_turns(self) -> u32 {
+        match self {
+            Self::None | Self::Flipped => 0,
+            Self::Rotate90 | Self::Flipped90 => 1,
+            Self::Rotate180 | Self::Flipped180 => 2,
+            Self::Rotate270 | Self::Flipped270 => 3,
+        }
+    }
+
+    fn is_flipped(self) -> bool {
+        matches!(
+            self,
+            Self::Flipped | Self::Flipped90 | Self::Flipped180 | Self::Flipped270
+        )
+    }
+
+    /// Size of the upright image for a buffer of the given size
+    pub fn output_size(self, width: usize, height: usize) -> (usize, usize) {
+        if self.quarter_turns() % 2 == 1 {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    // Maps a pixel of the upright image (of size `w`x`h`) to the buffer
+    fn to_buffer(self, x: usize, y: usize, w: usize, h: usize) -> (usize, usize) {
+        let x = if self.is_flipped() { w - 1 - x } else { x };
+        match self.quarter_turns() {
+            1 => (y, w - 1 - x),
+            2 => (w - 1 - x, h - 1 - y),
+            3 => (h - 1 - y, x),
+            _ => (x, y),
+        }
+    }
+
+    // Maps a pixel of the buffer (of size `bw`x`bh`) to the upright image
+    fn to_output(self, bx: usize, by: usize, bw: usize, bh: usize) -> (usize, usize) {
+        let (x, y) = match self.quarter_turns() {
+            1 => (bh - 1 - by, bx),
+            2 => (bw - 1 - bx, bh - 1 - by),
+            3 => (by, bw - 1 - bx),
+            _ => (bx, by),
+        };
+        let (w, _) = self.output_size(bw, bh);
+        if self.is_flipped() {
+            (w - 1 - x, y)
+        } else {
+            (x, y)
+        }
+    }
+
+    /// Maps a rectangle in buffer coordinates to the upright image
+    pub fn transform_rect(self, rect: DirtyRect, width: usize, height: usize) -> DirtyRect {
+        if rect.width <= 0 || rect.height <= 0 || width == 0 || height == 0 {
+            return rect;
+        }
+
+        // Clamp to the buffer and work with inclusive corners
+        let x0 = rect.x.clamp(0, width as i32 - 1);
+        let y0 = rect.y.clamp(0, height as i32 - 1);
+        let x1 = (rect.x + rect.width).clamp(x0 + 1, width as i32) - 1;
+        let y1 = (rect.y + rect.height).clamp(y0 + 1, height as i32) - 1;
+        let (x0, y0, x1, y1) = (x0 as usize, y0 as usize, x1 as usize, y1 as usize);
+
+        let (ax, ay) = self.to_output(x0, y0, width, height);
+        let (bx, by) = self.to_output(x1, y1, width, height);
+
+        DirtyRect {
+            x: ax.min(bx) as i32,
+            y: ay.min(by) as i32,
+            width: (ax.abs_diff(bx) + 1) as i32,
+            height: (ay.abs_diff(by) + 1) as i32,
+        }
+    }
+}
+
+/// Copies the `width`x`height` region at (`x`, `y`) out of a packed frame
+/// with `stride` bytes per row into a tightly packed buffer
+pub fn crop_packed(
+    data: &[u8],
+    stride: usize,
+    bytes_per_pixel: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let row_size = width * bytes_per_pixel;
+    let mut cropped = Vec::with_capacity(row_size * height);
+
+    for row in y..y + height {
+        let base = row * stride + x * bytes_per_pixel;
+        cropped.extend_from_slice(&data[base..base + row_size]);
+    }
+
+    cropped
+}
+
+/// Places a tightly packed frame of `size` pixels at `offset` inside a frame
+/// of `output_size` pixels, filling the remaining pixels with `fill`
+pub fn pad_packed(
+    data: &[u8],
+    size: (usize, usize),
+    output_size: (usize, usize),
+    offset: (usize, usize),
+    fill: &[u8],
+) -> Vec<u8> {
+    let bytes_per_pixel = fill.len();
+    let row_size = size.0 * bytes_per_pixel;
+    let mut output = fill.repeat(output_size.0 * output_size.1);
+
+    for (src, dst) in data.chunks_exact(row_size).zip(
+        output
+            .chunks_exact_mut(output_size.0 * bytes_per_pixel)
+            .skip(offset.1),
+    ) {
+        let base = offset.0 * bytes_per_pixel;
+        dst[base..base + row_size].copy_from_slice(src);
+    }
+
+    output
+}
+
+impl DirtyRect {
+    // Intersects the rectangle with a region and makes it relative to the
+    // region's origin
+    pub(crate) fn clip(self, x: i32, y: i32, width: i32, height: i32) -> Option<DirtyRect> {
+        let x0 = self.x.max(x);
+        let y0 = self.y.max(y);
+        let x1 = (self.x + self.width).min(x + width);
+        let y1 = (self.y + self.height).min(y + height);
+
+        (x1 > x0 && y1 > y0).then(|| DirtyRect {
+            x: x0 - x,
+            y: y0 - y,
+            width: x1 - x0,
+            height: y1 - y0,
+        })
+    }
+}
+
+fn clip_rects(
+    rects: &Option<Vec<DirtyRect>>,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> Option<Vec<DirtyRect>> {
+    rects.as_ref().map(|rects| {
+        rects
+            .iter()
+            .filter_map(|rect| rect.clip(x as i32, y as i32, width as i32, height as i32))
+            .collect()
+    })
+}
+
+impl VideoFrame {
+    /// Crops the frame to the `width`x`height` region at (`x`, `y`).
+    ///
+    /// The region is clamped to the frame. For YUV frames the origin is
+    /// rounded down to even coordinates so the chroma planes stay aligned.
+    pub fn cropped(self, x: u32, y: u32, width: u32, height: u32) -> VideoFrame {
+        let (frame_width, frame_height) = self.size();
+        let (mut x, mut y) = (x.min(frame_width) as usize, y.min(frame_height) as usize);
+        if self.planes() > 1 {
+            x -= x % 2;
+            y -= y % 2;
+        }
+        let width = (width as usize).min(frame_width as usize - x);
+        let height = (height as usize).min(frame_height as usize - y);
+
+        macro_rules! crop_packed_frame {
+            ($variant:ident, $frame:expr, $bpp:expr) => {{
+                let mut frame = $frame;
+                let stride = frame.width as usize * $bpp;
+                frame.data = crop_packed(&frame.data, stride, $bpp, x, y, width, height);
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = clip_rects(&frame.dirty_rects, x, y, width, height);
+                VideoFrame::$variant(frame)
+            }};
+        }
+
+        // A luma plane and a half resolution plane of interleaved U and V
+        // samples, every sample taking `$sample_size` bytes
+        macro_rules! crop_semi_planar_frame {
+            ($variant:ident, $frame:expr, $sample_size:expr) => {{
+                let mut frame = $frame;
+                frame.luminance_bytes = crop_packed(
+                    &frame.luminance_bytes,
+                    frame.luminance_stride as usize,
+                    $sample_size,
+                    x,
+                    y,
+                    width,
+                    height,
+                );
+                frame.luminance_stride = (width * $sample_size) as i32;
+                frame.chrominance_bytes = crop_packed(
+                    &frame.chrominance_bytes,
+                    frame.chrominance_stride as usize,
+                    2 * $sample_size,
+                    x / 2,
+                    y / 2,
+                    width.div_ceil(2),
+                    height.div_ceil(2),
+                );
+                frame.chrominance_stride = (width.div_ceil(2) * 2 * $sample_size) as i32;
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = clip_rects(&frame.dirty_rects, x, y, width, height);
+                VideoFrame::$variant(frame)
+            }};
+        }
+
+        match self {
+            VideoFrame::YUVFrame(frame) => crop_semi_planar_frame!(YUVFrame, frame, 1),
+            VideoFrame::P010(frame) => crop_semi_planar_frame!(P010, frame, 2),
+            VideoFrame::I420(mut frame) => {
+                let crop_plane = |data: &[u8], stride: i32, divisor: usize| {
+                    crop_packed(
+                        data,
+                        stride as usize,
+                        1,
+                        x / divisor,
+                        y / divisor,
+                        width.div_ceil(divisor),
+                        height.div_ceil(divisor),
+                    )
+                };
+                frame.y_bytes = crop_plane(&frame.y_bytes, frame.y_stride, 1);
+                frame.y_stride = width as i32;
+                frame.u_bytes = crop_plane(&frame.u_bytes, frame.u_stride, 2);
+                frame.u_stride = width.div_ceil(2) as i32;
+                frame.v_bytes = crop_plane(&frame.v_bytes, frame.v_stride, 2);
+                frame.v_stride = width.div_ceil(2) as i32;
+                frame.width = width as i32;
+                frame.height = height as i32;
+                frame.dirty_rects = clip_rects(&frame.dirty_rects, x, y, width, height);
+                VideoFrame::I420(frame)
+            }
+            VideoFrame::RGB(frame) => crop_packed_frame!(RGB, frame, 3),
+            VideoFrame::RGBA(frame) => crop_packed_frame!(RGBA, frame, 4),
+            VideoFrame::RGBx(frame) => crop_packed_frame!(RGBx, frame, 4),
+            VideoFrame::XBGR(frame) => crop_packed_frame!(XBGR, frame, 4),
+            VideoFrame::BGRx(frame) => crop_packed_frame!(BGRx, frame, 4),
+            VideoFrame::BGR0(frame) => crop_packed_frame!(BGR0, frame, 3),
+            VideoFrame::BGRA(frame) => crop_packed_frame!(BGRA, frame, 4),
+            VideoFrame::RGBA16F(frame) => crop_packed_frame!(RGBA16F, frame, 8),
+        }
+    }
+}
+
+/// Reverts `transform` on a packed frame of `width`x`height` pixels, returning
+/// the upright image as a tightly packed buffer
+pub fn undo_video_transform(
+    data: &[u8],
+    stride: usize,
+    bytes_per_pixel: usize,
+    width: usize,
+    height: usize,
+    transform: VideoTransform,
+) -> Vec<u8> {
+    if transform == VideoTransform::None {
+        return crop_packed(data, stride, bytes_per_pixel, 0, 0, width, height);
+    }
+
+    let (out_width, out_height) = transform.output_size(width, height);
+    let mut output = vec![0; out_width * out_height * bytes_per_pixel];
+    undo_video_transform_into(
+        data,
+        stride,
+        bytes_per_pixel,
+        width,
+        height,
+        transform,
+        &mut output,
+    );
+    output
+}
+
+// Like undo_video_transform, writing the upright image into `output`, which
+// must hold exactly its pixels
+pub(crate) fn undo_video_transform_into(
+    data: &[u8],
+    stride: usize,
+    bytes_per_pixel: usize,
+    width: usize,
+    height: usize,
+    transform: VideoTransform,
+    output: &mut [u8],
+) {
+    let (out_width, out_height) = transform.output_size(width, height);
+    assert_eq!(output.len(), out_width * out_height * bytes_per_pixel);
+
+    for (y, row) in output
+        .chunks_exact_mut(out_width * bytes_per_pixel)
+        .enumerate()
+    {
+        for (x, dst) in row.chunks_exact_mut(bytes_per_pixel).enumerate() {
+            let (bx, by) = transform.to_buffer(x, y, out_width, out_height);
+            let base = by * stride + bx * bytes_per_pixel;
+            dst.copy_from_slice(&data[base..base + bytes_per_pixel]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x2 single byte "pixels":
+    // 1 2 3
+    // 4 5 6
+    const IMAGE: [u8; 6] = [1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn test_crop_packed() {
+        let data = [1, 2, 3, 0, 4, 5, 6, 0, 7, 8, 9, 0];
+        assert_eq!(crop_packed(&data, 4, 1, 1, 1, 2, 2), vec![5, 6, 8, 9]);
+        assert_eq!(crop_packed(&data, 4, 2, 0, 2, 2, 1), vec![7, 8, 9, 0]);
+    }
+
+    #[test]
+    fn test_pad_packed() {
+        assert_eq!(
+            pad_packed(&[1, 2, 3, 4], (2, 1), (2, 3), (0, 1), &[0, 9]),
+            vec![0, 9, 0, 9, 1, 2, 3, 4, 0, 9, 0, 9]
+        );
+    }
+
+    #[test]
+    fn test_undo_video_transform() {
+        // The buffer holds the image rotated 90 degrees counter-clockwise
+        let rotated = [3, 6, 2, 5, 1, 4];
+        assert_eq!(
+            undo_video_transform(&rotated, 2, 1, 2, 3, VideoTransform::Rotate90),
+            IMAGE
+        );
+
+        let rotated = [6, 5, 4, 3, 2, 1];
+        assert_eq!(
+            undo_video_transform(&rotated, 3, 1, 3, 2, VideoTransform::Rotate180),
+            IMAGE
+        );
+
+        let rotated = [4, 1, 5, 2, 6, 3];
+        assert_eq!(
+            undo_video_transform(&rotated, 2, 1, 2, 3, VideoTransform::Rotate270),
+            IMAGE
+        );
+
+        let flipped = [3, 2, 1, 6, 5, 4];
+        assert_eq!(
+            undo_video_transform(&flipped, 3, 1, 3, 2, VideoTransform::Flipped),
+            IMAGE
+        );
+
+        // Mirrored, then rotated 90 degrees counter-clockwise
+        let flipped = [1, 4, 2, 5, 3, 6];
+        assert_eq!(
+            undo_video_transform(&flipped, 2, 1, 2, 3, VideoTransform::Flipped90),
+            IMAGE
+        );
+    }
+
+    #[test]
+    fn test_transform_rect() {
+        // Pixel `3` sits at (0, 0) in a buffer rotated 90 degrees and at (2, 0) upright
+        let rect = DirtyRect {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(
+            VideoTransform::Rotate90.transform_rect(rect, 2, 3),
+            DirtyRect {
+                x: 2,
+                y: 0,
+                width: 1,
+                height: 1
+            }
+        );
+
+        let rect = DirtyRect {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        assert_eq!(
+            VideoTransform::Rotate270.transform_rect(rect, 2, 3),
+            DirtyRect {
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 2
+            }
+        );
+    }
+}
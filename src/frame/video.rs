@@ -1,89 +1,557 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
+use super::{crop_packed, simd, Colorimetry, PackedFormat};
+
+/// A region of a frame that changed since the previous frame, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// An NV12 frame: a luma plane followed by a half resolution plane of
+/// interleaved U and V samples
 #[derive(Debug, Clone)]
 pub struct YUVFrame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
+    pub width: i32,
+    pub height: i32,
+    pub luminance_bytes: Vec<u8>,
+    pub luminance_stride: i32,
+    pub chrominance_bytes: Vec<u8>,
+    pub chrominance_stride: i32,
+    pub colorimetry: Colorimetry,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
+}
+
+/// A P010 frame: the layout of [YUVFrame] with 16 bit little endian samples
+/// that hold 10 bit values in their upper bits
+#[derive(Debug, Clone)]
+pub struct P010Frame {
+    pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
     pub luminance_bytes: Vec<u8>,
     pub luminance_stride: i32,
     pub chrominance_bytes: Vec<u8>,
     pub chrominance_stride: i32,
+    pub colorimetry: Colorimetry,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
+}
+
+/// An I420 frame: a luma plane followed by half resolution U and V planes
+#[derive(Debug, Clone)]
+pub struct I420Frame {
+    pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
+    pub width: i32,
+    pub height: i32,
+    pub y_bytes: Vec<u8>,
+    pub y_stride: i32,
+    pub u_bytes: Vec<u8>,
+    pub u_stride: i32,
+    pub v_bytes: Vec<u8>,
+    pub v_stride: i32,
+    pub colorimetry: Colorimetry,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RGBFrame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
     pub data: Vec<u8>,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RGB8Frame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
 }
 
+#[derive(Debug, Clone)]
+pub struct RGBAFrame {
+    pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
+    pub width: i32,
+    pub height: i32,
+    pub data: Vec<u8>,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
+}
+
+/// An RGBA frame with a 16 bit little endian half float per channel. The
+/// values are linear scRGB unless [RGBA16FFrame::colorimetry] says otherwise.
+#[derive(Debug, Clone)]
+pub struct RGBA16FFrame {
+    pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
+    pub width: i32,
+    pub height: i32,
+    pub data: Vec<u8>,
+    pub colorimetry: Colorimetry,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
+}
+
 #[derive(Debug, Clone)]
 pub struct RGBxFrame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
     pub data: Vec<u8>,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct XBGRFrame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
     pub data: Vec<u8>,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct BGRxFrame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
     pub data: Vec<u8>,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct BGRFrame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
     pub data: Vec<u8>,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct BGRAFrame {
     pub display_time: SystemTime,
+    pub timestamp: Duration,
+    pub sequence: u64,
+    pub repeated: bool,
     pub width: i32,
     pub height: i32,
     pub data: Vec<u8>,
+    pub dirty_rects: Option<Vec<DirtyRect>>,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+/// Format frames are delivered in.
+///
+/// Backends produce the format natively where they can and convert to it in
+/// software otherwise, see [crate::capturer::Capturer::supported_frame_types].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum FrameType {
+    /// Same as [FrameType::NV12]
     #[default]
     YUVFrame,
+    /// Packed 24 bit BGR
     BGR0,
     RGB, // Prefer BGR0 because RGB is slower
     BGRAFrame,
+    RGBA,
+    RGBx,
+    BGRx,
+    XBGR,
+    NV12,
+    I420,
+    P010,
+    RGBA16F,
+}
+
+impl FrameType {
+    // Every type except the YUVFrame alias
+    pub(crate) const ALL: [FrameType; 11] = [
+        FrameType::NV12,
+        FrameType::I420,
+        FrameType::P010,
+        FrameType::RGBA16F,
+        FrameType::BGRAFrame,
+        FrameType::RGBA,
+        FrameType::BGRx,
+        FrameType::RGBx,
+        FrameType::XBGR,
+        FrameType::BGR0,
+        FrameType::RGB,
+    ];
+
+    /// Layout of the frames delivered for this type
+    pub fn pixel_format(self) -> PixelFormat {
+        match self {
+            FrameType::YUVFrame | FrameType::NV12 => PixelFormat::NV12,
+            FrameType::I420 => PixelFormat::I420,
+            FrameType::P010 => PixelFormat::P010,
+            FrameType::RGBA16F => PixelFormat::RGBA16F,
+            FrameType::BGR0 => PixelFormat::BGR,
+            FrameType::RGB => PixelFormat::RGB,
+            FrameType::BGRAFrame => PixelFormat::BGRA,
+            FrameType::RGBA => PixelFormat::RGBA,
+            FrameType::RGBx => PixelFormat::RGBx,
+            FrameType::BGRx => PixelFormat::BGRx,
+            FrameType::XBGR => PixelFormat::XBGR,
+        }
+    }
+}
+
+/// Layout of the pixel data of a [VideoFrame]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// A luma plane followed by a half resolution plane of interleaved U and
+    /// V samples
+    NV12,
+    /// A luma plane followed by half resolution U and V planes
+    I420,
+    /// The layout of [PixelFormat::NV12] with 16 bit samples, see [P010Frame]
+    P010,
+    RGB,
+    RGBA,
+    RGBx,
+    XBGR,
+    BGRx,
+    BGR,
+    BGRA,
+    /// Half float RGBA, see [RGBA16FFrame]
+    RGBA16F,
+}
+
+impl PixelFormat {
+    /// Number of planes the pixel data is split into
+    pub fn planes(self) -> usize {
+        match self {
+            PixelFormat::NV12 | PixelFormat::P010 => 2,
+            PixelFormat::I420 => 3,
+            _ => 1,
+        }
+    }
+
+    /// Width and height of `plane` in samples for a `width`x`height` frame.
+    ///
+    /// Panics if the format has no such plane.
+    pub fn plane_size(self, plane: usize, width: u32, height: u32) -> (u32, u32) {
+        assert!(
+            plane < self.planes(),
+            "Plane {plane} out of range for {self:?} frame"
+        );
+        match (self, plane) {
+            (PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::P010, 1..) => {
+                (width.div_ceil(2), height.div_ceil(2))
+            }
+            _ => (width, height),
+        }
+    }
+
+    /// Byte order of packed formats, for use with the conversion functions
+    pub fn packed_format(self) -> Option<PackedFormat> {
+        match self {
+            PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::P010 | PixelFormat::RGBA16F => {
+                None
+            }
+            PixelFormat::RGB => Some(PackedFormat::RGB),
+            PixelFormat::RGBA => Some(PackedFormat::RGBA),
+            PixelFormat::RGBx => Some(PackedFormat::RGBx),
+            PixelFormat::XBGR => Some(PackedFormat::XBGR),
+            PixelFormat::BGRx => Some(PackedFormat::BGRx),
+            PixelFormat::BGR => Some(PackedFormat::BGR),
+            PixelFormat::BGRA => Some(PackedFormat::BGRA),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum VideoFrame {
     YUVFrame(YUVFrame),
+    I420(I420Frame),
+    P010(P010Frame),
     RGB(RGBFrame),
+    RGBA(RGBAFrame),
     RGBx(RGBxFrame),
     XBGR(XBGRFrame),
     BGRx(BGRxFrame),
     BGR0(BGRFrame),
     BGRA(BGRAFrame),
+    RGBA16F(RGBA16FFrame),
+}
+
+// Evaluates `$body` with `$frame` bound to the inner frame of any variant
+macro_rules! with_frame {
+    ($video_frame:expr, $frame:ident => $body:expr) => {
+        match $video_frame {
+            VideoFrame::YUVFrame($frame) => $body,
+            VideoFrame::I420($frame) => $body,
+            VideoFrame::P010($frame) => $body,
+            VideoFrame::RGB($frame) => $body,
+            VideoFrame::RGBA($frame) => $body,
+            VideoFrame::RGBx($frame) => $body,
+            VideoFrame::XBGR($frame) => $body,
+            VideoFrame::BGRx($frame) => $body,
+            VideoFrame::BGR0($frame) => $body,
+            VideoFrame::BGRA($frame) => $body,
+            VideoFrame::RGBA16F($frame) => $body,
+        }
+    };
+}
+
+impl VideoFrame {
+    pub(crate) fn size(&self) -> (u32, u32) {
+        (self.width(), self.height())
+    }
+
+    /// Width of the frame in pixels
+    pub fn width(&self) -> u32 {
+        with_frame!(self, frame => frame.width as u32)
+    }
+
+    /// Height of the frame in pixels
+    pub fn height(&self) -> u32 {
+        with_frame!(self, frame => frame.height as u32)
+    }
+
+    /// Wall clock time the frame was captured at
+    pub fn display_time(&self) -> SystemTime {
+        with_frame!(self, frame => frame.display_time)
+    }
+
+    /// Time since the capture started, on a monotonic clock shared with the
+    /// audio frames of the capture
+    pub fn timestamp(&self) -> Duration {
+        with_frame!(self, frame => frame.timestamp)
+    }
+
+    /// Number of the frame in the video of the capture. Goes up by one for
+    /// every frame the source produced, so gaps mean frames were dropped, see
+    /// [SequenceTracker](super::SequenceTracker).
+    pub fn sequence(&self) -> u64 {
+        with_frame!(self, frame => frame.sequence)
+    }
+
+    /// Whether the frame repeats the previous one, because the source sent
+    /// no new frame in time, see
+    /// [Options::constant_frame_rate](crate::capturer::Options::constant_frame_rate)
+    pub fn is_repeated(&self) -> bool {
+        with_frame!(self, frame => frame.repeated)
+    }
+
+    pub(crate) fn set_time(&mut self, timestamp: Duration, display_time: SystemTime) {
+        with_frame!(self, frame => {
+            frame.timestamp = timestamp;
+            frame.display_time = display_time;
+        })
+    }
+
+    pub(crate) fn set_repeated(&mut self, repeated: bool) {
+        with_frame!(self, frame => frame.repeated = repeated)
+    }
+
+    pub(crate) fn set_dirty_rects(&mut self, dirty_rects: Option<Vec<DirtyRect>>) {
+        with_frame!(self, frame => frame.dirty_rects = dirty_rects)
+    }
+
+    /// Pixel format of this frame, which need not be the requested
+    /// [Options::output_type](crate::capturer::Options::output_type)
+    pub fn format(&self) -> PixelFormat {
+        match self {
+            VideoFrame::YUVFrame(_) => PixelFormat::NV12,
+            VideoFrame::I420(_) => PixelFormat::I420,
+            VideoFrame::P010(_) => PixelFormat::P010,
+            VideoFrame::RGB(_) => PixelFormat::RGB,
+            VideoFrame::RGBA(_) => PixelFormat::RGBA,
+            VideoFrame::RGBx(_) => PixelFormat::RGBx,
+            VideoFrame::XBGR(_) => PixelFormat::XBGR,
+            VideoFrame::BGRx(_) => PixelFormat::BGRx,
+            VideoFrame::BGR0(_) => PixelFormat::BGR,
+            VideoFrame::BGRA(_) => PixelFormat::BGRA,
+            VideoFrame::RGBA16F(_) => PixelFormat::RGBA16F,
+        }
+    }
+
+    /// Number of planes the pixel data is split into
+    pub fn planes(&self) -> usize {
+        self.format().planes()
+    }
+
+    /// Width and height of `plane` in samples. Chroma planes of YUV frames
+    /// are half the size of the frame, rounded up.
+    ///
+    /// Panics if the frame has no such plane.
+    pub fn plane_size(&self, plane: usize) -> (u32, u32) {
+        self.format().plane_size(plane, self.width(), self.height())
+    }
+
+    /// Bytes per row of `plane`.
+    ///
+    /// Panics if the frame has no such plane.
+    pub fn stride(&self, plane: usize) -> usize {
+        match (self, plane) {
+            (VideoFrame::YUVFrame(frame), 0) => frame.luminance_stride as usize,
+            (VideoFrame::YUVFrame(frame), 1) => frame.chrominance_stride as usize,
+            (VideoFrame::I420(frame), 0) => frame.y_stride as usize,
+            (VideoFrame::I420(frame), 1) => frame.u_stride as usize,
+            (VideoFrame::I420(frame), 2) => frame.v_stride as usize,
+            (VideoFrame::P010(frame), 0) => frame.luminance_stride as usize,
+            (VideoFrame::P010(frame), 1) => frame.chrominance_stride as usize,
+            (VideoFrame::RGBA16F(frame), 0) => frame.width as usize * 8,
+            (frame, 0) => {
+                let bytes_per_pixel = frame.format().packed_format().unwrap().bytes_per_pixel();
+                frame.width() as usize * bytes_per_pixel
+            }
+            _ => panic!("Plane {plane} out of range for {:?} frame", self.format()),
+        }
+    }
+
+    /// Pixel data of `plane`, with [VideoFrame::stride] bytes per row.
+    ///
+    /// Panics if the frame has no such plane.
+    pub fn data(&self, plane: usize) -> &[u8] {
+        match (self, plane) {
+            (VideoFrame::YUVFrame(frame), 0) => &frame.luminance_bytes,
+            (VideoFrame::YUVFrame(frame), 1) => &frame.chrominance_bytes,
+            (VideoFrame::I420(frame), 0) => &frame.y_bytes,
+            (VideoFrame::I420(frame), 1) => &frame.u_bytes,
+            (VideoFrame::I420(frame), 2) => &frame.v_bytes,
+            (VideoFrame::P010(frame), 0) => &frame.luminance_bytes,
+            (VideoFrame::P010(frame), 1) => &frame.chrominance_bytes,
+            (VideoFrame::RGB(frame), 0) => &frame.data,
+            (VideoFrame::RGBA(frame), 0) => &frame.data,
+            (VideoFrame::RGBx(frame), 0) => &frame.data,
+            (VideoFrame::XBGR(frame), 0) => &frame.data,
+            (VideoFrame::BGRx(frame), 0) => &frame.data,
+            (VideoFrame::BGR0(frame), 0) => &frame.data,
+            (VideoFrame::BGRA(frame), 0) => &frame.data,
+            (VideoFrame::RGBA16F(frame), 0) => &frame.data,
+            _ => panic!("Plane {plane} out of range for {:?} frame", self.format()),
+        }
+    }
+
+    // The buffers of all planes of the frame
+    pub(crate) fn into_buffers(self) -> Vec<Vec<u8>> {
+        match self {
+            VideoFrame::YUVFrame(frame) => vec![frame.luminance_bytes, frame.chrominance_bytes],
+            VideoFrame::I420(frame) => vec![frame.y_bytes, frame.u_bytes, frame.v_bytes],
+            VideoFrame::P010(frame) => vec![frame.luminance_bytes, frame.chrominance_bytes],
+            VideoFrame::RGB(frame) => vec![frame.data],
+            VideoFrame::RGBA(frame) => vec![frame.data],
+            VideoFrame::RGBx(frame) => vec![frame.data],
+            VideoFrame::XBGR(frame) => vec![frame.data],
+            VideoFrame::BGRx(frame) => vec![frame.data],
+            VideoFrame::BGR0(frame) => vec![frame.data],
+            VideoFrame::BGRA(frame) => vec![frame.data],
+            VideoFrame::RGBA16F(frame) => vec![frame.data],
+        }
+    }
+
+    /// Color description of the frame. `None` for 8 bit RGB frames, which are
+    /// sRGB.
+    pub fn colorimetry(&self) -> Option<Colorimetry> {
+        match self {
+            VideoFrame::YUVFrame(frame) => Some(frame.colorimetry),
+            VideoFrame::I420(frame) => Some(frame.colorimetry),
+            VideoFrame::P010(frame) => Some(frame.colorimetry),
+            VideoFrame::RGBA16F(frame) => Some(frame.colorimetry),
+            _ => None,
+        }
+    }
+
+    /// Regions that changed since the previous frame.
+    ///
+    /// `None` means the backend did not report damage, so the whole frame
+    /// should be treated as changed. An empty slice means nothing changed.
+    pub fn dirty_rects(&self) -> Option<&[DirtyRect]> {
+        with_frame!(self, frame => frame.dirty_rects.as_deref())
+    }
+}
+
+#[cfg(test)]
+impl VideoFrame {
+    // A frame of packed `format` pixels with zero times and number, for tests
+    pub(crate) fn packed(format: PixelFormat, width: i32, height: i32, data: Vec<u8>) -> Self {
+        macro_rules! packed_frame {
+            ($variant:ident, $frame:ident) => {
+                VideoFrame::$variant($frame {
+                    display_time: SystemTime::UNIX_EPOCH,
+                    timestamp: Duration::ZERO,
+                    sequence: 0,
+                    repeated: false,
+                    width,
+                    height,
+                    data,
+                    dirty_rects: None,
+                })
+            };
+        }
+
+        match format {
+            PixelFormat::RGB => packed_frame!(RGB, RGBFrame),
+            PixelFormat::RGBA => packed_frame!(RGBA, RGBAFrame),
+            PixelFormat::RGBx => packed_frame!(RGBx, RGBxFrame),
+            PixelFormat::XBGR => packed_frame!(XBGR, XBGRFrame),
+            PixelFormat::BGRx => packed_frame!(BGRx, BGRxFrame),
+            PixelFormat::BGR => packed_frame!(BGR0, BGRFrame),
+            PixelFormat::BGRA => packed_frame!(BGRA, BGRAFrame),
+            PixelFormat::RGBA16F => VideoFrame::RGBA16F(RGBA16FFrame {
+                display_time: SystemTime::UNIX_EPOCH,
+                timestamp: Duration::ZERO,
+                sequence: 0,
+                repeated: false,
+                width,
+                height,
+                data,
+                colorimetry: Colorimetry {
+                    matrix: super::ColorMatrix::Bt709,
+                    range: super::ColorRange::Full,
+                    primaries: super::ColorPrimaries::Bt709,
+                    transfer: super::TransferFunction::Linear,
+                },
+                dirty_rects: None,
+            }),
+            PixelFormat::NV12 | PixelFormat::I420 | PixelFormat::P010 => {
+                panic!("{format:?} is not a packed format")
+            }
+        }
+    }
+
+    pub(crate) fn with_sequence(mut self, sequence: u64) -> Self {
+        with_frame!(&mut self, frame => frame.sequence = sequence);
+        self
+    }
 }
 
 pub enum FrameData<'a> {
@@ -96,12 +564,7 @@ pub fn remove_alpha_channel(frame_data: Vec<u8>) -> Vec<u8> {
     let width_without_alpha = (width / 4) * 3;
 
     let mut data: Vec<u8> = vec![0; width_without_alpha];
-
-    for (src, dst) in frame_data.chunks_exact(4).zip(data.chunks_exact_mut(3)) {
-        dst[0] = src[0];
-        dst[1] = src[1];
-        dst[2] = src[2];
-    }
+    simd::pack(&frame_data, &mut data, [0, 1, 2]);
 
     data
 }
@@ -111,12 +574,7 @@ pub fn convert_bgra_to_rgb(frame_data: Vec<u8>) -> Vec<u8> {
     let width_without_alpha = (width / 4) * 3;
 
     let mut data: Vec<u8> = vec![0; width_without_alpha];
-
-    for (src, dst) in frame_data.chunks_exact(4).zip(data.chunks_exact_mut(3)) {
-        dst[0] = src[2];
-        dst[1] = src[1];
-        dst[2] = src[0];
-    }
+    simd::pack(&frame_data, &mut data, [2, 1, 0]);
 
     data
 }
@@ -125,17 +583,8 @@ pub fn get_cropped_data(data: Vec<u8>, cur_width: i32, height: i32, width: i32)
     if data.len() as i32 != height * cur_width * 4 {
         data
     } else {
-        let mut cropped_data: Vec<u8> = vec![0; (4 * height * width).try_into().unwrap()];
-        let mut cropped_data_index = 0;
-
-        for (i, item) in data.iter().enumerate() {
-            let x = i as i32 % (cur_width * 4);
-            if x < (width * 4) {
-                cropped_data[cropped_data_index] = *item;
-                cropped_data_index += 1;
-            }
-        }
-        cropped_data
+        let stride = cur_width as usize * 4;
+        crop_packed(&data, stride, 4, 0, 0, width as usize, height as usize)
     }
 }
 
@@ -161,6 +610,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_accessors() {
+        let frame = VideoFrame::packed(PixelFormat::BGRA, 2, 1, vec![1, 2, 3, 4, 5, 6, 7, 8])
+            .with_sequence(7);
+        assert_eq!((frame.width(), frame.height()), (2, 1));
+        assert_eq!(frame.format(), PixelFormat::BGRA);
+        assert_eq!(frame.planes(), 1);
+        assert_eq!(frame.stride(0), 8);
+        assert_eq!(frame.data(0), &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(frame.display_time(), SystemTime::UNIX_EPOCH);
+        assert_eq!(frame.timestamp(), Duration::ZERO);
+        assert_eq!(frame.sequence(), 7);
+        assert!(!frame.is_repeated());
+
+        let frame = VideoFrame::YUVFrame(YUVFrame {
+            display_time: SystemTime::UNIX_EPOCH,
+            timestamp: Duration::ZERO,
+            sequence: 0,
+            repeated: false,
+            width: 2,
+            height: 2,
+            luminance_bytes: vec![16; 4],
+            luminance_stride: 2,
+            chrominance_bytes: vec![128; 2],
+            chrominance_stride: 2,
+            colorimetry: Colorimetry::default(),
+            dirty_rects: None,
+        });
+        assert_eq!(frame.format(), PixelFormat::NV12);
+        assert_eq!(frame.planes(), 2);
+        assert_eq!(frame.data(1), &[128, 128]);
+        assert_eq!(frame.plane_size(1), (1, 1));
+        assert_eq!(frame.colorimetry(), Some(Colorimetry::default()));
+
+        let frame = VideoFrame::I420(I420Frame {
+            display_time: SystemTime::UNIX_EPOCH,
+            timestamp: Duration::ZERO,
+            sequence: 0,
+            repeated: false,
+            width: 3,
+            height: 3,
+            y_bytes: vec![16; 12],
+            y_stride: 4,
+            u_bytes: vec![1; 4],
+            u_stride: 2,
+            v_bytes: vec![2; 4],
+            v_stride: 2,
+            colorimetry: Colorimetry::default(),
+            dirty_rects: None,
+        });
+        assert_eq!(frame.format(), PixelFormat::I420);
+        assert_eq!(frame.planes(), 3);
+        assert_eq!((frame.plane_size(0), frame.plane_size(2)), ((3, 3), (2, 2)));
+        assert_eq!((frame.stride(0), frame.stride(2)), (4, 2));
+        assert_eq!(frame.data(2), &[2; 4]);
+    }
+
     macro_rules! rgba {
         ($n:expr) => {
             &mut vec![$n, $n, $n, $n]
@@ -2,9 +2,13 @@
 
 pub mod capturer;
 pub mod frame;
+mod screenshot;
+pub mod sink;
 mod targets;
 mod utils;
 
+pub use screenshot::{screenshot, ScreenshotError, ScreenshotOptions};
+
 // Helper Methods
 pub use targets::{get_all_targets, get_main_display};
 pub use targets::{Display, Target};
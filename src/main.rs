@@ -3,7 +3,7 @@
 
 use scap::{
     capturer::{Area, Capturer, Options, Point, Size},
-    frame::{Frame, VideoFrame},
+    frame::Frame,
 };
 use std::process;
 
@@ -29,7 +29,7 @@ fn main() {
 
     // Create Options
     let options = Options {
-        fps: 60,
+        fps: 60.into(),
         show_cursor: true,
         show_highlight: false,
         excluded_targets: None,
@@ -68,50 +68,14 @@ fn main() {
             }
         };
 
-        match frame {
-            VideoFrame::YUVFrame(frame) => {
-                println!(
-                    "Recieved YUV frame {} of width {} and height {} and pts {:?}",
-                    i, frame.width, frame.height, frame.display_time
-                );
-            }
-            VideoFrame::BGR0(frame) => {
-                println!(
-                    "Received BGR0 frame of width {} and height {}",
-                    frame.width, frame.height
-                );
-            }
-            VideoFrame::RGB(frame) => {
-                println!(
-                    "Recieved RGB frame {} of width {} and height {} and time {:?}",
-                    i, frame.width, frame.height, frame.display_time
-                );
-            }
-            VideoFrame::RGBx(frame) => {
-                println!(
-                    "Recieved RGBx frame of width {} and height {}",
-                    frame.width, frame.height
-                );
-            }
-            VideoFrame::XBGR(frame) => {
-                println!(
-                    "Recieved xRGB frame of width {} and height {}",
-                    frame.width, frame.height
-                );
-            }
-            VideoFrame::BGRx(frame) => {
-                println!(
-                    "Recieved BGRx frame of width {} and height {}",
-                    frame.width, frame.height
-                );
-            }
-            VideoFrame::BGRA(frame) => {
-                println!(
-                    "Recieved BGRA frame {} of width {} and height {} and time {:?}",
-                    i, frame.width, frame.height, frame.display_time
-                );
-            }
-        }
+        println!(
+            "Received {:?} frame {} of width {} and height {} and time {:?}",
+            frame.format(),
+            i,
+            frame.width(),
+            frame.height(),
+            frame.display_time()
+        );
     }
 
     // Stop Capture
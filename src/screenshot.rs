@@ -0,0 +1,99 @@
+use std::{error::Error, fmt};
+
+use crate::{
+    capturer::{engine, Area, Capturer, CapturerBuildError, DropPolicy, Options},
+    frame::{Frame, FrameType, VideoFrame},
+    targets::Target,
+};
+
+/// Options passed to [screenshot]
+#[derive(Debug, Default, Clone)]
+pub struct ScreenshotOptions {
+    /// Region of the target to capture, in logical coordinates
+    pub crop_area: Option<Area>,
+    pub output_type: FrameType,
+    pub show_cursor: bool,
+}
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    NotSupported,
+    PermissionNotGranted,
+    /// [ScreenshotOptions::crop_area] is empty or has negative or non-finite
+    /// values
+    InvalidCropArea(Area),
+    /// The capture ended before a frame arrived
+    NoFrame,
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::NotSupported => write!(f, "Screen capturing is not supported"),
+            ScreenshotError::PermissionNotGranted => {
+                write!(f, "Permission to capture the screen is not granted")
+            }
+            ScreenshotError::InvalidCropArea(area) => write!(f, "Invalid crop area: {area:?}"),
+            ScreenshotError::NoFrame => write!(f, "The capture ended without a frame"),
+        }
+    }
+}
+
+impl Error for ScreenshotError {}
+
+impl From<CapturerBuildError> for ScreenshotError {
+    fn from(e: CapturerBuildError) -> Self {
+        match e {
+            CapturerBuildError::NotSupported => ScreenshotError::NotSupported,
+            CapturerBuildError::PermissionNotGranted => ScreenshotError::PermissionNotGranted,
+            CapturerBuildError::InvalidCropArea(area) => ScreenshotError::InvalidCropArea(area),
+        }
+    }
+}
+
+/// Captures a single frame of `target`, or of the main display when `None`.
+///
+/// A capture is started for its first frame. On Linux with the
+/// `screenshot-portal` feature the screenshot portal is tried first for the
+/// whole desktop without the cursor, as it can't select a target, show the
+/// cursor or crop.
+pub fn screenshot(
+    target: Option<Target>,
+    options: ScreenshotOptions,
+) -> Result<VideoFrame, ScreenshotError> {
+    if target.is_some() || options.crop_area.is_some() || options.show_cursor {
+        return capture_frame(target, options);
+    }
+
+    match engine::screenshot() {
+        Some(frame) => Ok(frame.converted(options.output_type.pixel_format())),
+        None => capture_frame(target, options),
+    }
+}
+
+// Takes the first frame of a capture, which crops and converts it already
+fn capture_frame(
+    target: Option<Target>,
+    options: ScreenshotOptions,
+) -> Result<VideoFrame, ScreenshotError> {
+    let mut capturer = Capturer::build(Options {
+        target,
+        crop_area: options.crop_area,
+        output_type: options.output_type,
+        show_cursor: options.show_cursor,
+        queue_depth: 1,
+        drop_policy: DropPolicy::Latest,
+        ..Default::default()
+    })?;
+
+    capturer.start_capture();
+    let frame = loop {
+        match capturer.get_next_frame() {
+            Ok(Frame::Video(frame)) => break Ok(frame),
+            Ok(_) => continue,
+            Err(_) => break Err(ScreenshotError::NoFrame),
+        }
+    };
+    capturer.stop_capture();
+    frame
+}
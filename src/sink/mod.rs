@@ -0,0 +1,7 @@
+//! Writers that persist captured frames to files
+
+mod wav;
+mod y4m;
+
+pub use wav::*;
+pub use y4m::*;
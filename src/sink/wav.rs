@@ -0,0 +1,318 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::frame::{AudioFormat, AudioFrame};
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+// Tail shared by the sub format GUIDs of WAVE_FORMAT_EXTENSIBLE
+const GUID_TAIL: [u8; 14] = [
+    0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// Sample format, channel count and rate of the audio in a WAV file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavSpec {
+    pub format: AudioFormat,
+    pub channels: u16,
+    pub rate: u32,
+}
+
+impl WavSpec {
+    fn of(frame: &AudioFrame) -> Self {
+        WavSpec {
+            format: frame.format(),
+            channels: frame.channels(),
+            rate: frame.rate(),
+        }
+    }
+
+    // Format the samples are stored in, as WAV has no signed 8 bit or
+    // unsigned wider samples
+    fn stored_format(&self) -> Option<AudioFormat> {
+        match self.format {
+            AudioFormat::I8 | AudioFormat::U8 => Some(AudioFormat::U8),
+            AudioFormat::I16 | AudioFormat::U16 => Some(AudioFormat::I16),
+            AudioFormat::I32 | AudioFormat::U32 => Some(AudioFormat::I32),
+            AudioFormat::F32 => Some(AudioFormat::F32),
+            AudioFormat::F64 => Some(AudioFormat::F64),
+            AudioFormat::I64 | AudioFormat::U64 => None,
+        }
+    }
+}
+
+impl fmt::Display for WavSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?}, {} channels at {} Hz",
+            self.format, self.channels, self.rate
+        )
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum WavError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("WAV files can't hold {0:?} samples")]
+    UnsupportedFormat(AudioFormat),
+    /// A frame differs in format from the ones written before
+    #[error("Audio changed from {from} to {to} mid-stream")]
+    FormatChanged { from: WavSpec, to: WavSpec },
+}
+
+/// Writes audio frames to a WAV file as interleaved PCM or float samples.
+///
+/// The format is taken from the first frame, and later frames must match it.
+/// More than two channels are written as `WAVE_FORMAT_EXTENSIBLE`. The sizes
+/// in the header are only filled in by [WavWriter::finish].
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    spec: Option<WavSpec>,
+    // Offset of the data chunk's size, once the header is written
+    data_size_offset: u64,
+    data_size: u64,
+}
+
+impl WavWriter<BufWriter<File>> {
+    /// Creates or truncates the file at `path` to write to
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(WavWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(writer: W) -> Self {
+        WavWriter {
+            writer,
+            spec: None,
+            data_size_offset: 0,
+            data_size: 0,
+        }
+    }
+
+    /// Format of the file, once the first frame was written
+    pub fn spec(&self) -> Option<WavSpec> {
+        self.spec
+    }
+
+    /// Appends the samples of the frame, interleaving planar frames
+    pub fn write_frame(&mut self, frame: &AudioFrame) -> Result<(), WavError> {
+        let spec = WavSpec::of(frame);
+        match self.spec {
+            Some(from) if from != spec => return Err(WavError::FormatChanged { from, to: spec }),
+            Some(_) => {}
+            None => {
+                spec.stored_format()
+                    .ok_or(WavError::UnsupportedFormat(spec.format))?;
+                self.write_header(spec)?;
+                self.spec = Some(spec);
+            }
+        }
+
+        let samples = interleaved(frame);
+        self.writer.write_all(&stored(&samples, spec.format))?;
+        self.data_size += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header and returns the writer
+    pub fn finish(mut self) -> Result<W, WavError> {
+        if self.spec.is_none() {
+            return Ok(self.writer);
+        }
+
+        // Chunks have an even size
+        if self.data_size % 2 == 1 {
+            self.writer.write_all(&[0])?;
+        }
+        let end = self.writer.stream_position()?;
+        // Sizes beyond 4 GiB don't fit, readers then read to the end
+        let size = |size: u64| u32::try_from(size).unwrap_or(u32::MAX).to_le_bytes();
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&size(end - 8))?;
+        self.writer.seek(SeekFrom::Start(self.data_size_offset))?;
+        self.writer.write_all(&size(self.data_size))?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self, spec: WavSpec) -> io::Result<()> {
+        let format = spec.stored_format().unwrap();
+        let sample_size = format.sample_size() as u16;
+        let tag = match format {
+            AudioFormat::F32 | AudioFormat::F64 => WAVE_FORMAT_IEEE_FLOAT,
+            _ => WAVE_FORMAT_PCM,
+        };
+
+        let mut fmt = Vec::with_capacity(40);
+        let extensible = spec.channels > 2;
+        let format_tag = if extensible {
+            WAVE_FORMAT_EXTENSIBLE
+        } else {
+            tag
+        };
+        fmt.extend(format_tag.to_le_bytes());
+        fmt.extend(spec.channels.to_le_bytes());
+        fmt.extend(spec.rate.to_le_bytes());
+        fmt.extend((spec.rate * (spec.channels * sample_size) as u32).to_le_bytes());
+        fmt.extend((spec.channels * sample_size).to_le_bytes());
+        fmt.extend((sample_size * 8).to_le_bytes());
+        if extensible {
+            fmt.extend(22u16.to_le_bytes());
+            fmt.extend((sample_size * 8).to_le_bytes());
+            fmt.extend(channel_mask(spec.channels).to_le_bytes());
+            fmt.extend(tag.to_le_bytes());
+            fmt.extend(GUID_TAIL);
+        } else if tag == WAVE_FORMAT_IEEE_FLOAT {
+            fmt.extend(0u16.to_le_bytes());
+        }
+
+        self.writer.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        self.writer.write_all(&(fmt.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&fmt)?;
+        self.writer.write_all(b"data")?;
+        self.data_size_offset = self.writer.stream_position()?;
+        self.writer.write_all(&[0; 4])
+    }
+}
+
+// Speaker positions of the usual layouts, none assigned for the others
+fn channel_mask(channels: u16) -> u32 {
+    match channels {
+        1 => 0x4,
+        2 => 0x3,
+        3 => 0x7,
+        4 => 0x33,
+        6 => 0x3f,
+        8 => 0x63f,
+        _ => 0,
+    }
+}
+
+// Samples of all channels, one sample of every channel after the other
+fn interleaved(frame: &AudioFrame) -> Vec<u8> {
+    let sample_size = frame.format().sample_size();
+    let channels = frame.channels() as usize;
+    if !frame.is_planar() {
+        return frame.raw_data()[..frame.sample_count() * sample_size * channels].to_vec();
+    }
+
+    let planes: Vec<&[u8]> = (0..channels).map(|plane| frame.plane_data(plane)).collect();
+    let mut samples = Vec::with_capacity(frame.sample_count() * sample_size * channels);
+    for i in 0..frame.sample_count() {
+        for plane in &planes {
+            samples.extend_from_slice(&plane[i * sample_size..][..sample_size]);
+        }
+    }
+    samples
+}
+
+// Converts little endian samples of `format` to the format they are stored
+// in by flipping the sign bit
+fn stored(samples: &[u8], format: AudioFormat) -> Vec<u8> {
+    let sign_byte = match format {
+        AudioFormat::I8 => 0,
+        AudioFormat::U16 => 1,
+        AudioFormat::U32 => 3,
+        _ => return samples.to_vec(),
+    };
+    let sample_size = format.sample_size();
+    let mut samples = samples.to_vec();
+    for sample in samples.chunks_exact_mut(sample_size) {
+        sample[sign_byte] ^= 0x80;
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Cursor, time::Duration};
+
+    fn frame(format: AudioFormat, channels: u16, planar: bool, data: Vec<u8>) -> AudioFrame {
+        let sample_count = data.len() / format.sample_size() / channels as usize;
+        AudioFrame::new(
+            format,
+            channels,
+            planar,
+            data,
+            sample_count,
+            48_000,
+            Duration::ZERO,
+        )
+    }
+
+    #[test]
+    fn test_wav_writer() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()));
+        // Two samples of two channels, planar then interleaved
+        let planar = [1u16, 2, 3, 4].map(u16::to_le_bytes).concat();
+        writer
+            .write_frame(&frame(AudioFormat::I16, 2, true, planar))
+            .unwrap();
+        writer
+            .write_frame(&frame(AudioFormat::I16, 2, false, vec![5, 0, 6, 0]))
+            .unwrap();
+
+        let error = writer
+            .write_frame(&frame(AudioFormat::F32, 2, false, vec![0; 8]))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Audio changed from I16, 2 channels at 48000 Hz to F32, 2 channels at 48000 Hz \
+             mid-stream"
+        );
+
+        let wav = writer.finish().unwrap().into_inner();
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], (wav.len() as u32 - 8).to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[16..20], 16u32.to_le_bytes());
+        // PCM, 2 channels, 48 kHz, 192000 bytes per second, 4 byte blocks,
+        // 16 bits
+        assert_eq!(
+            &wav[20..36],
+            [1, 0, 2, 0, 0x80, 0xbb, 0, 0, 0, 0xee, 2, 0, 4, 0, 16, 0]
+        );
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[40..44], 12u32.to_le_bytes());
+        assert_eq!(&wav[44..], [1, 0, 3, 0, 2, 0, 4, 0, 5, 0, 6, 0]);
+    }
+
+    #[test]
+    fn test_wav_extensible() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()));
+        writer
+            .write_frame(&frame(AudioFormat::F32, 6, false, vec![0; 24]))
+            .unwrap();
+        let wav = writer.finish().unwrap().into_inner();
+
+        assert_eq!(wav[16..20], 40u32.to_le_bytes());
+        assert_eq!(wav[20..22], WAVE_FORMAT_EXTENSIBLE.to_le_bytes());
+        assert_eq!(wav[22..24], 6u16.to_le_bytes());
+        // Valid bits, 5.1 channel mask and the float sub format
+        assert_eq!(wav[38..40], 32u16.to_le_bytes());
+        assert_eq!(wav[40..44], 0x3fu32.to_le_bytes());
+        assert_eq!(wav[44..46], WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+        assert_eq!(wav[46..60], GUID_TAIL);
+        assert_eq!(wav[64..68], 24u32.to_le_bytes());
+
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()));
+        assert!(matches!(
+            writer.write_frame(&frame(AudioFormat::I64, 1, false, vec![0; 8])),
+            Err(WavError::UnsupportedFormat(AudioFormat::I64))
+        ));
+        assert_eq!(
+            stored(&[0x00, 0x80, 0xff], AudioFormat::I8),
+            [0x80, 0, 0x7f]
+        );
+    }
+}
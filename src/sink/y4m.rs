@@ -0,0 +1,235 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    capturer::FrameRate,
+    frame::{
+        packed_to_yuv444, ColorRange, Colorimetry, PackedFormat, PixelFormat, Plane, PlaneMut,
+        VideoFrame,
+    },
+};
+
+/// Chroma subsampling of the frames a [Y4mWriter] writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Y4mChroma {
+    /// Half resolution chroma, which every encoder takes
+    #[default]
+    C420,
+    /// Full resolution chroma, keeping sharp text and edges
+    C444,
+}
+
+/// Writes video frames to uncompressed YUV4MPEG2 files, which most encoders
+/// read directly.
+///
+/// A file holds frames of one size only, so a new one is started whenever the
+/// size or color range of the frames changes. The first file is written to
+/// the given path, later ones get `-1`, `-2` and so on appended to its name.
+pub struct Y4mWriter {
+    path: PathBuf,
+    fps: FrameRate,
+    chroma: Y4mChroma,
+    file: Option<BufWriter<File>>,
+    // Size and color range of the frames in the current file
+    format: (u32, u32, ColorRange),
+    paths: Vec<PathBuf>,
+}
+
+impl Y4mWriter {
+    /// Writes to `path`, with the frames played back at `fps`. Nothing is
+    /// created until the first frame.
+    pub fn new(path: impl Into<PathBuf>, fps: FrameRate) -> Self {
+        assert!(!fps.is_zero(), "Y4M files need a frame rate");
+        Y4mWriter {
+            path: path.into(),
+            fps,
+            chroma: Y4mChroma::default(),
+            file: None,
+            format: (0, 0, ColorRange::default()),
+            paths: Vec::new(),
+        }
+    }
+
+    pub fn with_chroma(mut self, chroma: Y4mChroma) -> Self {
+        self.chroma = chroma;
+        self
+    }
+
+    /// Converts the frame and appends it
+    pub fn write_frame(&mut self, frame: &VideoFrame) -> io::Result<()> {
+        let (width, height) = (frame.width(), frame.height());
+        let (planes, range) = match self.chroma {
+            Y4mChroma::C420 => i420_planes(frame),
+            Y4mChroma::C444 => yuv444_planes(frame),
+        };
+        if self.file.is_none() || self.format != (width, height, range) {
+            self.start_file(width, height, range)?;
+        }
+
+        let file = self.file.as_mut().unwrap();
+        file.write_all(b"FRAME\n")?;
+        for plane in planes {
+            file.write_all(&plane)?;
+        }
+        Ok(())
+    }
+
+    /// Files written so far, in order
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// Flushes the current file and returns the paths of all files written
+    pub fn finish(mut self) -> io::Result<Vec<PathBuf>> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        Ok(self.paths)
+    }
+
+    fn start_file(&mut self, width: u32, height: u32, range: ColorRange) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+
+        let path = segment_path(&self.path, self.paths.len());
+        let mut file = BufWriter::new(File::create(&path)?);
+        let chroma = match self.chroma {
+            Y4mChroma::C420 => "420jpeg XYSCSS=420JPEG",
+            Y4mChroma::C444 => "444 XYSCSS=444",
+        };
+        let range_name = match range {
+            ColorRange::Limited => "LIMITED",
+            ColorRange::Full => "FULL",
+        };
+        writeln!(
+            file,
+            "YUV4MPEG2 W{width} H{height} F{}:{} Ip A1:1 C{chroma} XCOLORRANGE={range_name}",
+            self.fps.num(),
+            self.fps.den()
+        )?;
+
+        self.file = Some(file);
+        self.format = (width, height, range);
+        self.paths.push(path);
+        Ok(())
+    }
+}
+
+// Path of the file with the `index`, counting from 0
+fn segment_path(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{index}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{index}"),
+    };
+    path.with_file_name(name)
+}
+
+// Tightly packed rows of a plane with `stride` bytes per row
+fn packed_rows(data: &[u8], stride: usize, (width, height): (u32, u32)) -> Vec<u8> {
+    let width = width as usize;
+    (0..height as usize)
+        .flat_map(|y| &data[y * stride..][..width])
+        .copied()
+        .collect()
+}
+
+fn i420_planes(frame: &VideoFrame) -> ([Vec<u8>; 3], ColorRange) {
+    let frame = frame.clone().converted(PixelFormat::I420);
+    let planes = [0, 1, 2].map(|plane| {
+        packed_rows(
+            frame.data(plane),
+            frame.stride(plane),
+            frame.plane_size(plane),
+        )
+    });
+    (planes, frame.colorimetry().unwrap_or_default().range)
+}
+
+fn yuv444_planes(frame: &VideoFrame) -> ([Vec<u8>; 3], ColorRange) {
+    // Only YUV frames carry a colorimetry that applies after converting to
+    // RGB
+    let colorimetry = match frame.format() {
+        PixelFormat::NV12 | PixelFormat::I420 => frame.colorimetry().unwrap_or_default(),
+        _ => Colorimetry::default(),
+    };
+    let size = (frame.width() as usize, frame.height() as usize);
+    let rgb = frame.to_packed(PackedFormat::RGB);
+
+    let mut planes = [0; 3].map(|_| vec![0; size.0 * size.1]);
+    let [y, u, v] = &mut planes;
+    let plane = |data| PlaneMut {
+        data,
+        stride: size.0,
+    };
+    packed_to_yuv444(
+        Plane {
+            data: &rgb,
+            stride: size.0 * 3,
+        },
+        PackedFormat::RGB,
+        plane(y),
+        plane(u),
+        plane(v),
+        size,
+        colorimetry,
+    );
+    (planes, colorimetry.range)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn frame(width: i32, height: i32) -> VideoFrame {
+        VideoFrame::packed(
+            PixelFormat::RGB,
+            width,
+            height,
+            vec![255; (width * height * 3) as usize],
+        )
+    }
+
+    #[test]
+    fn test_y4m_writer() {
+        let dir = std::env::temp_dir().join(format!("scap-y4m-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut writer = Y4mWriter::new(dir.join("capture.y4m"), FrameRate::NTSC_29_97);
+        writer.write_frame(&frame(3, 2)).unwrap();
+        writer.write_frame(&frame(3, 2)).unwrap();
+        writer.write_frame(&frame(2, 2)).unwrap();
+        let paths = writer.finish().unwrap();
+        assert_eq!(paths, [dir.join("capture.y4m"), dir.join("capture-1.y4m")]);
+
+        let header = "YUV4MPEG2 W3 H2 F30000:1001 Ip A1:1 C420jpeg XYSCSS=420JPEG \
+                      XCOLORRANGE=LIMITED\n";
+        let data = fs::read(&paths[0]).unwrap();
+        assert!(data.starts_with(header.as_bytes()));
+        // White is 235 in limited range luma, 128 in chroma
+        let frame_data = [&b"FRAME\n"[..], &[235; 6], &[128; 4]].concat();
+        assert_eq!(
+            &data[header.len()..],
+            [&frame_data[..], &frame_data].concat()
+        );
+
+        let mut writer =
+            Y4mWriter::new(dir.join("full"), FrameRate::from_fps(60)).with_chroma(Y4mChroma::C444);
+        writer.write_frame(&frame(2, 1)).unwrap();
+        let paths = writer.finish().unwrap();
+        let data = fs::read(&paths[0]).unwrap();
+        let header = "YUV4MPEG2 W2 H1 F60:1 Ip A1:1 C444 XYSCSS=444 XCOLORRANGE=LIMITED\n";
+        assert!(data.starts_with(header.as_bytes()));
+        assert_eq!(data.len(), header.len() + 6 + 2 * 3);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}